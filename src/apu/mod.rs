@@ -1,4 +1,6 @@
 use bitflags::bitflags;
+use crate::state::{StateReader, StateWriter};
+use std::io::Result;
 
 bitflags! {
     #[derive(Debug, Clone, Copy)]
@@ -108,6 +110,49 @@ impl Pulse {
             }
         }
     }
+
+    fn save_state(&self, state: &mut StateWriter) {
+        state.write_bool(self.enabled);
+        state.write_u8(self.duty);
+        state.write_u8(self.volume);
+        state.write_bool(self.constant_volume);
+        state.write_bool(self.envelope_loop);
+        state.write_u8(self.envelope_period);
+        state.write_u8(self.envelope_counter);
+        state.write_u8(self.envelope_divider);
+        state.write_bool(self.envelope_start);
+        state.write_bool(self.sweep_enabled);
+        state.write_u8(self.sweep_period);
+        state.write_bool(self.sweep_negate);
+        state.write_u8(self.sweep_shift);
+        state.write_u8(self._sweep_counter);
+        state.write_u16(self.timer_period);
+        state.write_u16(self.timer_counter);
+        state.write_u8(self.length_counter);
+        state.write_u8(self.sequence_pos);
+    }
+
+    fn load_state(&mut self, state: &mut StateReader) -> Result<()> {
+        self.enabled = state.read_bool()?;
+        self.duty = state.read_u8()?;
+        self.volume = state.read_u8()?;
+        self.constant_volume = state.read_bool()?;
+        self.envelope_loop = state.read_bool()?;
+        self.envelope_period = state.read_u8()?;
+        self.envelope_counter = state.read_u8()?;
+        self.envelope_divider = state.read_u8()?;
+        self.envelope_start = state.read_bool()?;
+        self.sweep_enabled = state.read_bool()?;
+        self.sweep_period = state.read_u8()?;
+        self.sweep_negate = state.read_bool()?;
+        self.sweep_shift = state.read_u8()?;
+        self._sweep_counter = state.read_u8()?;
+        self.timer_period = state.read_u16()?;
+        self.timer_counter = state.read_u16()?;
+        self.length_counter = state.read_u8()?;
+        self.sequence_pos = state.read_u8()?;
+        Ok(())
+    }
 }
 
 pub struct Triangle {
@@ -158,6 +203,29 @@ impl Triangle {
 
         triangle_table[self.sequence_pos as usize]
     }
+
+    fn save_state(&self, state: &mut StateWriter) {
+        state.write_bool(self.enabled);
+        state.write_u8(self.linear_counter);
+        state.write_u8(self.linear_counter_period);
+        state.write_bool(self.linear_counter_reload);
+        state.write_u16(self.timer_period);
+        state.write_u16(self.timer_counter);
+        state.write_u8(self.length_counter);
+        state.write_u8(self.sequence_pos);
+    }
+
+    fn load_state(&mut self, state: &mut StateReader) -> Result<()> {
+        self.enabled = state.read_bool()?;
+        self.linear_counter = state.read_u8()?;
+        self.linear_counter_period = state.read_u8()?;
+        self.linear_counter_reload = state.read_bool()?;
+        self.timer_period = state.read_u16()?;
+        self.timer_counter = state.read_u16()?;
+        self.length_counter = state.read_u8()?;
+        self.sequence_pos = state.read_u8()?;
+        Ok(())
+    }
 }
 
 pub struct Noise {
@@ -237,6 +305,39 @@ impl Noise {
             }
         }
     }
+
+    fn save_state(&self, state: &mut StateWriter) {
+        state.write_bool(self.enabled);
+        state.write_bool(self.mode);
+        state.write_u8(self.volume);
+        state.write_bool(self.constant_volume);
+        state.write_bool(self.envelope_loop);
+        state.write_u8(self.envelope_period);
+        state.write_u8(self.envelope_counter);
+        state.write_u8(self.envelope_divider);
+        state.write_bool(self.envelope_start);
+        state.write_u16(self.timer_period);
+        state.write_u16(self.timer_counter);
+        state.write_u8(self.length_counter);
+        state.write_u16(self.shift_register);
+    }
+
+    fn load_state(&mut self, state: &mut StateReader) -> Result<()> {
+        self.enabled = state.read_bool()?;
+        self.mode = state.read_bool()?;
+        self.volume = state.read_u8()?;
+        self.constant_volume = state.read_bool()?;
+        self.envelope_loop = state.read_bool()?;
+        self.envelope_period = state.read_u8()?;
+        self.envelope_counter = state.read_u8()?;
+        self.envelope_divider = state.read_u8()?;
+        self.envelope_start = state.read_bool()?;
+        self.timer_period = state.read_u16()?;
+        self.timer_counter = state.read_u16()?;
+        self.length_counter = state.read_u8()?;
+        self.shift_register = state.read_u16()?;
+        Ok(())
+    }
 }
 
 pub struct Dmc {
@@ -245,13 +346,14 @@ pub struct Dmc {
     direct_load: u8,
     sample_address: u16,
     sample_length: u16,
-    _current_address: u16,
+    current_address: u16,
     bytes_remaining: u16,
-    _sample_buffer: Option<u8>,
+    sample_buffer: Option<u8>,
     output_level: u8,
-    _shift_register: u8,
-    _bits_remaining: u8,
-    _silence_flag: bool,
+    shift_register: u8,
+    bits_remaining: u8,
+    silence_flag: bool,
+    timer_counter: u16,
     irq_enabled: bool,
     loop_flag: bool,
     interrupt: bool,
@@ -265,22 +367,125 @@ impl Dmc {
             direct_load: 0,
             sample_address: 0,
             sample_length: 0,
-            _current_address: 0,
+            current_address: 0,
             bytes_remaining: 0,
-            _sample_buffer: None,
+            sample_buffer: None,
             output_level: 0,
-            _shift_register: 0,
-            _bits_remaining: 0,
-            _silence_flag: true,
+            shift_register: 0,
+            bits_remaining: 8,
+            silence_flag: true,
+            timer_counter: 0,
             irq_enabled: false,
             loop_flag: false,
             interrupt: false,
         }
     }
 
+    fn restart(&mut self) {
+        self.current_address = self.sample_address;
+        self.bytes_remaining = self.sample_length;
+    }
+
+    fn clock_timer(&mut self) {
+        if self.timer_counter > 0 {
+            self.timer_counter -= 1;
+            return;
+        }
+        self.timer_counter = DMC_RATE_TABLE[self.rate as usize] - 1;
+
+        // Output unit: each bit nudges the level up or down by 2
+        if !self.silence_flag {
+            if self.shift_register & 1 != 0 {
+                if self.output_level <= 125 {
+                    self.output_level += 2;
+                }
+            } else if self.output_level >= 2 {
+                self.output_level -= 2;
+            }
+        }
+        self.shift_register >>= 1;
+        self.bits_remaining -= 1;
+
+        if self.bits_remaining == 0 {
+            self.bits_remaining = 8;
+            match self.sample_buffer.take() {
+                Some(sample) => {
+                    self.shift_register = sample;
+                    self.silence_flag = false;
+                }
+                None => self.silence_flag = true,
+            }
+        }
+    }
+
+    fn dma_address(&self) -> Option<u16> {
+        if self.sample_buffer.is_none() && self.bytes_remaining > 0 {
+            Some(self.current_address)
+        } else {
+            None
+        }
+    }
+
+    fn fill_sample_buffer(&mut self, value: u8) {
+        self.sample_buffer = Some(value);
+        // The address wraps from $FFFF back to $8000
+        self.current_address = if self.current_address == 0xFFFF { 0x8000 } else { self.current_address + 1 };
+        self.bytes_remaining -= 1;
+
+        if self.bytes_remaining == 0 {
+            if self.loop_flag {
+                self.restart();
+            } else if self.irq_enabled {
+                self.interrupt = true;
+            }
+        }
+    }
+
     fn _get_output(&self) -> u8 {
         self.output_level
     }
+
+    fn save_state(&self, state: &mut StateWriter) {
+        state.write_bool(self.enabled);
+        state.write_u8(self.rate);
+        state.write_u8(self.direct_load);
+        state.write_u16(self.sample_address);
+        state.write_u16(self.sample_length);
+        state.write_u16(self.current_address);
+        state.write_u16(self.bytes_remaining);
+        state.write_bool(self.sample_buffer.is_some());
+        state.write_u8(self.sample_buffer.unwrap_or(0));
+        state.write_u8(self.output_level);
+        state.write_u8(self.shift_register);
+        state.write_u8(self.bits_remaining);
+        state.write_bool(self.silence_flag);
+        state.write_u16(self.timer_counter);
+        state.write_bool(self.irq_enabled);
+        state.write_bool(self.loop_flag);
+        state.write_bool(self.interrupt);
+    }
+
+    fn load_state(&mut self, state: &mut StateReader) -> Result<()> {
+        self.enabled = state.read_bool()?;
+        self.rate = state.read_u8()?;
+        self.direct_load = state.read_u8()?;
+        self.sample_address = state.read_u16()?;
+        self.sample_length = state.read_u16()?;
+        self.current_address = state.read_u16()?;
+        self.bytes_remaining = state.read_u16()?;
+        let buffered = state.read_bool()?;
+        let value = state.read_u8()?;
+        self.sample_buffer = buffered.then_some(value);
+        self.output_level = state.read_u8()?;
+        self.shift_register = state.read_u8()?;
+        self.bits_remaining = state.read_u8()?;
+        self.silence_flag = state.read_bool()?;
+        self.timer_counter = state.read_u16()?;
+        self.irq_enabled = state.read_bool()?;
+        self.loop_flag = state.read_bool()?;
+        self.interrupt = state.read_bool()?;
+        Ok(())
+    }
 }
 
 pub struct Apu {
@@ -423,6 +628,9 @@ impl Apu {
                 self.dmc.irq_enabled = (value & 0x80) != 0;
                 self.dmc.loop_flag = (value & 0x40) != 0;
                 self.dmc.rate = value & 0x0F;
+                if !self.dmc.irq_enabled {
+                    self.dmc.interrupt = false;
+                }
             }
             0x4011 => {
                 self.dmc.direct_load = value & 0x7F;
@@ -446,6 +654,13 @@ impl Apu {
                 if !self.pulse2.enabled { self.pulse2.length_counter = 0; }
                 if !self.triangle.enabled { self.triangle.length_counter = 0; }
                 if !self.noise.enabled { self.noise.length_counter = 0; }
+
+                // Enabling the DMC starts a new sample only once the previous one finished
+                if !self.dmc.enabled {
+                    self.dmc.bytes_remaining = 0;
+                } else if self.dmc.bytes_remaining == 0 {
+                    self.dmc.restart();
+                }
                 
                 self.dmc.interrupt = false;
             }
@@ -470,6 +685,7 @@ impl Apu {
         }
         
         self.triangle.clock_timer();
+        self.dmc.clock_timer();
         
         if self.cycles % 7457 == 0 {
             self.clock_frame_counter();
@@ -478,6 +694,23 @@ impl Apu {
         self.cycles += 1;
     }
 
+    pub fn frame_irq(&self) -> bool {
+        self.frame_interrupt
+    }
+
+    pub fn dmc_irq(&self) -> bool {
+        self.dmc.interrupt
+    }
+
+    /// Address the DMC memory reader wants fetched, once its sample buffer has emptied.
+    pub fn dmc_dma_request(&self) -> Option<u16> {
+        self.dmc.dma_address()
+    }
+
+    pub fn dmc_dma_complete(&mut self, value: u8) {
+        self.dmc.fill_sample_buffer(value);
+    }
+
     fn clock_frame_counter(&mut self) {
         let mode = (self.frame_counter & 0x80) != 0;
         
@@ -576,6 +809,35 @@ impl Apu {
         let output = (pulse_out + tnd_out) * 2.0;
         output * 2.0 - 1.0
     }
+
+    pub fn save_state(&self, state: &mut StateWriter) {
+        self.pulse1.save_state(state);
+        self.pulse2.save_state(state);
+        self.triangle.save_state(state);
+        self.noise.save_state(state);
+        self.dmc.save_state(state);
+        state.write_u8(self.status.bits());
+        state.write_u8(self.frame_counter);
+        state.write_u8(self.frame_sequence);
+        state.write_bool(self.frame_interrupt);
+        state.write_bool(self.frame_interrupt_inhibit);
+        state.write_u64(self.cycles);
+    }
+
+    pub fn load_state(&mut self, state: &mut StateReader) -> Result<()> {
+        self.pulse1.load_state(state)?;
+        self.pulse2.load_state(state)?;
+        self.triangle.load_state(state)?;
+        self.noise.load_state(state)?;
+        self.dmc.load_state(state)?;
+        self.status = ApuStatus::from_bits_truncate(state.read_u8()?);
+        self.frame_counter = state.read_u8()?;
+        self.frame_sequence = state.read_u8()?;
+        self.frame_interrupt = state.read_bool()?;
+        self.frame_interrupt_inhibit = state.read_bool()?;
+        self.cycles = state.read_u64()?;
+        Ok(())
+    }
 }
 
 const LENGTH_TABLE: [u8; 32] = [
@@ -583,6 +845,11 @@ const LENGTH_TABLE: [u8; 32] = [
     12, 16, 24, 18, 48, 20, 96, 22, 192, 24, 72, 26, 16, 28, 32, 30,
 ];
 
+// DMC output rates in CPU cycles per bit (NTSC)
+const DMC_RATE_TABLE: [u16; 16] = [
+    428, 380, 340, 320, 286, 254, 226, 214, 190, 160, 142, 128, 106, 84, 72, 54,
+];
+
 const NOISE_PERIOD_TABLE: [u16; 16] = [
     4, 8, 16, 32, 64, 96, 128, 160, 202, 254, 380, 508, 762, 1016, 2034, 4068,
 ];
\ No newline at end of file
@@ -10,12 +10,12 @@ fn main() -> anyhow::Result<()> {
     let rom_path = "./roms/mario.nes";
     println!("Loading ROM: {}", rom_path);
 
-    let cartridge = Cartridge::load_from_file(rom_path)?;
+    let mut cartridge = Cartridge::load_from_file(rom_path)?;
     println!("ROM loaded successfully");
     println!("  Mapper: {}", cartridge.mapper);
     println!("  PRG ROM: {} KB", cartridge.prg_rom.len() / 1024);
     println!("  CHR ROM: {} KB", cartridge.chr_rom.len() / 1024);
-    println!("  CHR RAM: {} KB", cartridge.chr_ram.len() / 1024);
+    println!("  CHR RAM: {} KB", cartridge.header.total_chr_ram_size() / 1024);
 
     // Test CHR ROM reads
     println!("\nTesting CHR ROM reads:");
@@ -65,7 +65,7 @@ fn main() -> anyhow::Result<()> {
         print!("  ");
         for col in 0..16 {
             let idx = row * 16 + col;
-            print!("{:02X} ", system.ppu.vram[idx]);
+            print!("{:02X} ", system.ppu.ciram[idx]);
         }
         println!();
     }
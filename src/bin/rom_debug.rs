@@ -1,29 +1,39 @@
 use nes_emu::cartridge::Cartridge;
+use nes_emu::cpu::disasm;
+use std::collections::BTreeSet;
 use std::env;
 
 fn main() -> Result<(), Box<dyn std::error::Error>> {
     let args: Vec<String> = env::args().collect();
     if args.len() < 2 {
-        eprintln!("Usage: {} <rom_file>", args[0]);
+        eprintln!("Usage: {} <rom_file> [--disasm]", args[0]);
         std::process::exit(1);
     }
 
     let rom_path = &args[1];
     println!("Loading ROM: {}", rom_path);
 
-    let cartridge = Cartridge::load_from_file(rom_path)?;
+    let mut cartridge = Cartridge::load_from_file(rom_path)?;
+
+    if args.iter().any(|arg| arg == "--disasm") {
+        print_disassembly(&mut cartridge);
+        return Ok(());
+    }
 
     println!("\n=== ROM Information ===");
-    println!("Mapper: {}", cartridge.mapper);
+    let header = cartridge.header;
+    println!("Header format: {:?}", header.format);
+    println!("Mapper: {} (submapper {})", header.mapper, header.submapper);
+    println!("Console: {:?}, timing: {:?}", header.console_type, header.timing);
+    println!("Battery: {}, trainer: {}", header.battery, header.trainer);
+    println!("PRG RAM: {} bytes, PRG NVRAM: {} bytes", header.prg_ram_size, header.prg_nvram_size);
+    println!("CHR RAM: {} bytes, CHR NVRAM: {} bytes", header.chr_ram_size, header.chr_nvram_size);
     println!("Mirroring: {:?}", cartridge.get_mirroring());
     println!("PRG ROM size: {} bytes ({} KB)",
         cartridge.prg_rom.len(), cartridge.prg_rom.len() / 1024);
     println!("CHR ROM size: {} bytes ({} KB)",
         cartridge.chr_rom.len(), cartridge.chr_rom.len() / 1024);
-    println!("Has CHR RAM: {}", cartridge.chr_ram.len() > 0);
-    if cartridge.chr_ram.len() > 0 {
-        println!("CHR RAM size: {} bytes", cartridge.chr_ram.len());
-    }
+    println!("Has CHR RAM: {}", header.total_chr_ram_size() > 0);
 
     println!("\n=== First 16 bytes of PRG ROM ===");
     for (i, byte) in cartridge.prg_rom.iter().take(16).enumerate() {
@@ -35,21 +45,15 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
     println!();
 
     println!("\n=== First 64 bytes of CHR ROM/RAM ===");
-    if cartridge.chr_rom.len() > 0 {
+    if !cartridge.chr_rom.is_empty() {
         for (i, byte) in cartridge.chr_rom.iter().take(64).enumerate() {
             if i % 16 == 0 && i > 0 {
                 println!();
             }
             print!("{:02X} ", byte);
         }
-    } else if cartridge.chr_ram.len() > 0 {
+    } else {
         println!("(Using CHR RAM - initially all zeros)");
-        for i in 0..64 {
-            if i % 16 == 0 && i > 0 {
-                println!();
-            }
-            print!("{:02X} ", cartridge.chr_ram[i]);
-        }
     }
     println!();
 
@@ -95,3 +99,67 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
 
     Ok(())
 }
+
+/// Disassemble $8000-$FFFF as currently mapped, starting from the NMI, reset and IRQ vectors.
+/// Only bytes reached by following jumps and branches are decoded, everything else is listed as data.
+fn print_disassembly(cartridge: &mut Cartridge) {
+    let mut read = |addr: u16| cartridge.read_prg(addr);
+    let vector = |read: &mut dyn FnMut(u16) -> u8, addr: u16| read(addr) as u16 | (read(addr + 1) as u16) << 8;
+    let vectors = [
+        ("NMI", vector(&mut read, 0xFFFA)),
+        ("RESET", vector(&mut read, 0xFFFC)),
+        ("IRQ", vector(&mut read, 0xFFFE)),
+    ];
+
+    let entry_points: Vec<u16> = vectors.iter().map(|&(_, addr)| addr).collect();
+    let code = disasm::trace_code(&entry_points, &mut read);
+    let labels: BTreeSet<u16> = code.values().filter_map(|instruction| instruction.jump_target()).collect();
+
+    println!("\n=== Disassembly ===");
+    for (name, addr) in vectors {
+        println!("; {} vector -> ${:04X}", name, addr);
+    }
+
+    let mut addr: u32 = 0x8000;
+    let mut data: Vec<u8> = Vec::new();
+    let mut data_start = 0;
+    while addr <= 0xFFFF {
+        let pc = addr as u16;
+        let Some(instruction) = code.get(&pc) else {
+            if data.is_empty() {
+                data_start = pc;
+            }
+            data.push(read(pc));
+            if data.len() == 8 || code.contains_key(&pc.wrapping_add(1)) || pc == 0xFFFF {
+                print_data(data_start, &data);
+                data.clear();
+            }
+            addr += 1;
+            continue;
+        };
+
+        for (name, _) in vectors.iter().filter(|&&(_, vector)| vector == pc) {
+            println!("\n{}:", name);
+        }
+        if labels.contains(&pc) {
+            println!("L{:04X}:", pc);
+        }
+
+        let bytes: Vec<String> = (0..instruction.size)
+            .map(|i| format!("{:02X}", read(pc.wrapping_add(i))))
+            .collect();
+        println!(
+            "{:04X}  {:<8} {}{}",
+            pc,
+            bytes.join(" "),
+            if instruction.official { ' ' } else { '*' },
+            instruction
+        );
+        addr += instruction.size as u32;
+    }
+}
+
+fn print_data(addr: u16, data: &[u8]) {
+    let bytes: Vec<String> = data.iter().map(|byte| format!("${:02X}", byte)).collect();
+    println!("{:04X}            .byte {}", addr, bytes.join(","));
+}
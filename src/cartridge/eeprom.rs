@@ -0,0 +1,197 @@
+// Serial EEPROMs on Bandai FCG boards
+// The 24C01 (128 bytes) takes a 7-bit word address straight after the start condition and shifts
+// everything least significant bit first. The 24C02 (256 bytes) is a standard I2C device: a
+// device select byte, then the word address, most significant bit first. Both acknowledge each
+// byte they receive and wrap writes within a small page.
+
+use crate::state::{StateReader, StateWriter};
+use std::io::{Error, ErrorKind, Result};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Stage {
+    Idle,
+    // 24C02 device select byte
+    Device,
+    Address,
+    // Receiving bytes to store
+    Data,
+    // Sending bytes to the CPU
+    Transmit,
+}
+
+impl Stage {
+    fn from_state(value: u8) -> Result<Self> {
+        Ok(match value {
+            0 => Stage::Idle,
+            1 => Stage::Device,
+            2 => Stage::Address,
+            3 => Stage::Data,
+            4 => Stage::Transmit,
+            _ => return Err(Error::new(ErrorKind::InvalidData, "Invalid EEPROM stage")),
+        })
+    }
+}
+
+pub struct Eeprom {
+    data: Vec<u8>,
+    // 24C02: addressed through a device select byte and shifted most significant bit first
+    c02: bool,
+    stage: Stage,
+    address: u8,
+    shift: u8,
+    // Bits clocked in the current byte; 8 is the acknowledge clock
+    bit: u8,
+    output: bool,
+    scl: bool,
+    sda: bool,
+}
+
+impl Eeprom {
+    pub fn new_24c01() -> Self {
+        Self::new(0x80, false)
+    }
+
+    pub fn new_24c02() -> Self {
+        Self::new(0x100, true)
+    }
+
+    fn new(size: usize, c02: bool) -> Self {
+        Eeprom {
+            data: vec![0; size],
+            c02,
+            stage: Stage::Idle,
+            address: 0,
+            shift: 0,
+            bit: 0,
+            output: true,
+            scl: false,
+            sda: false,
+        }
+    }
+
+    pub fn data(&self) -> &[u8] {
+        &self.data
+    }
+
+    pub fn data_mut(&mut self) -> &mut [u8] {
+        &mut self.data
+    }
+
+    /// Level of the data line as driven by the EEPROM; high when it is not pulling it low.
+    pub fn output(&self) -> bool {
+        self.output
+    }
+
+    /// Drive the clock and data lines.
+    pub fn write(&mut self, scl: bool, sda: bool) {
+        if self.scl && scl && sda != self.sda {
+            // Data changing while the clock is high: start (falling) or stop (rising) condition
+            if sda {
+                self.stage = Stage::Idle;
+            } else {
+                self.stage = if self.c02 { Stage::Device } else { Stage::Address };
+                self.bit = 0;
+            }
+            self.output = true;
+        } else if !self.scl && scl {
+            self.clock_rise(sda);
+        } else if self.scl && !scl {
+            self.clock_fall();
+        }
+        self.scl = scl;
+        self.sda = sda;
+    }
+
+    fn clock_rise(&mut self, sda: bool) {
+        match self.stage {
+            Stage::Idle => {}
+            Stage::Transmit if self.bit < 8 => self.bit += 1,
+            Stage::Transmit => {
+                // The CPU acknowledges to ask for the next byte
+                self.bit = 0;
+                if sda {
+                    self.stage = Stage::Idle;
+                } else {
+                    self.address = self.wrap(self.address.wrapping_add(1));
+                }
+            }
+            _ if self.bit < 8 => {
+                self.shift = if self.c02 {
+                    self.shift << 1 | sda as u8
+                } else {
+                    self.shift >> 1 | (sda as u8) << 7
+                };
+                self.bit += 1;
+            }
+            _ => {
+                self.bit = 0;
+                self.receive(self.shift);
+            }
+        }
+    }
+
+    fn clock_fall(&mut self) {
+        self.output = match self.stage {
+            Stage::Idle => true,
+            Stage::Transmit if self.bit < 8 => {
+                let byte = self.data[self.address as usize % self.data.len()];
+                let bit = if self.c02 { 7 - self.bit } else { self.bit };
+                byte >> bit & 0x01 != 0
+            }
+            Stage::Transmit => true,
+            // Acknowledge each received byte by pulling the line low
+            _ => self.bit != 8,
+        };
+    }
+
+    fn receive(&mut self, byte: u8) {
+        match self.stage {
+            Stage::Device if byte & 0xF0 != 0xA0 => self.stage = Stage::Idle,
+            Stage::Device if byte & 0x01 != 0 => self.stage = Stage::Transmit,
+            Stage::Device => self.stage = Stage::Address,
+            // The 24C01's eighth address bit is the read/write flag
+            Stage::Address if !self.c02 => {
+                self.address = byte & 0x7F;
+                self.stage = if byte & 0x80 != 0 { Stage::Transmit } else { Stage::Data };
+            }
+            Stage::Address => {
+                self.address = byte;
+                self.stage = Stage::Data;
+            }
+            Stage::Data => {
+                let len = self.data.len();
+                self.data[self.address as usize % len] = byte;
+                let page_mask = if self.c02 { 0x07 } else { 0x03 };
+                self.address = (self.address & !page_mask) | (self.address.wrapping_add(1) & page_mask);
+            }
+            _ => {}
+        }
+    }
+
+    fn wrap(&self, address: u8) -> u8 {
+        (address as usize % self.data.len()) as u8
+    }
+
+    pub fn save_state(&self, state: &mut StateWriter) {
+        state.write_bytes(&self.data);
+        state.write_u8(self.stage as u8);
+        state.write_u8(self.address);
+        state.write_u8(self.shift);
+        state.write_u8(self.bit);
+        state.write_bool(self.output);
+        state.write_bool(self.scl);
+        state.write_bool(self.sda);
+    }
+
+    pub fn load_state(&mut self, state: &mut StateReader) -> Result<()> {
+        state.read_into(&mut self.data)?;
+        self.stage = Stage::from_state(state.read_u8()?)?;
+        self.address = state.read_u8()?;
+        self.shift = state.read_u8()?;
+        self.bit = state.read_u8()?;
+        self.output = state.read_bool()?;
+        self.scl = state.read_bool()?;
+        self.sda = state.read_bool()?;
+        Ok(())
+    }
+}
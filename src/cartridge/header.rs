@@ -0,0 +1,268 @@
+// iNES and NES 2.0 header decoding
+// NES 2.0 reuses iNES bytes 8-15 for wider mapper numbers, exact RAM sizes and console details.
+
+use super::Mirroring;
+use std::io::{Error, ErrorKind, Result};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HeaderFormat {
+    /// iNES whose bytes 7-15 hold garbage such as "DiskDude!"; only flags 6 can be trusted.
+    ArchaicINes,
+    INes,
+    Nes2,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ConsoleType {
+    Nes,
+    VsSystem { ppu_type: u8, hardware_type: u8 },
+    Playchoice10,
+    /// NES 2.0 extended console type from byte 13.
+    Extended(u8),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TimingRegion {
+    Ntsc,
+    Pal,
+    MultiRegion,
+    Dendy,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct RomHeader {
+    pub format: HeaderFormat,
+    pub mapper: u16,
+    pub submapper: u8,
+    pub prg_rom_size: usize,
+    pub chr_rom_size: usize,
+    pub prg_ram_size: usize,
+    pub prg_nvram_size: usize,
+    pub chr_ram_size: usize,
+    pub chr_nvram_size: usize,
+    /// Hardwired nametable arrangement from flags 6.
+    pub mirroring: Mirroring,
+    pub battery: bool,
+    pub trainer: bool,
+    pub console_type: ConsoleType,
+    pub timing: TimingRegion,
+    pub misc_roms: u8,
+    pub expansion_device: u8,
+}
+
+impl RomHeader {
+    pub const SIZE: usize = 16;
+
+    pub fn parse(data: &[u8]) -> Result<Self> {
+        if data.len() < Self::SIZE {
+            return Err(Error::new(ErrorKind::InvalidData, "ROM file too small"));
+        }
+        if &data[0..4] != b"NES\x1A" {
+            return Err(Error::new(ErrorKind::InvalidData, "Invalid NES header"));
+        }
+
+        let flags_6 = data[6];
+        let flags_7 = data[7];
+
+        let format = if flags_7 & 0x0C == 0x08 {
+            HeaderFormat::Nes2
+        } else if flags_7 & 0x0C == 0 && data[12..16].iter().all(|&byte| byte == 0) {
+            HeaderFormat::INes
+        } else {
+            if &data[7..16] == b"DiskDude!" {
+                log::warn!("Header contains \"DiskDude!\", ignoring bytes 7-15");
+            } else {
+                log::warn!("Header has garbage in bytes 7-15, ignoring them");
+            }
+            HeaderFormat::ArchaicINes
+        };
+
+        let mirroring = if flags_6 & 0x08 != 0 {
+            Mirroring::FourScreen
+        } else if flags_6 & 0x01 != 0 {
+            Mirroring::Vertical
+        } else {
+            Mirroring::Horizontal
+        };
+        let battery = flags_6 & 0x02 != 0;
+        let trainer = flags_6 & 0x04 != 0;
+
+        let mut header = RomHeader {
+            format,
+            mapper: (flags_6 >> 4) as u16,
+            submapper: 0,
+            prg_rom_size: data[4] as usize * 0x4000,
+            chr_rom_size: data[5] as usize * 0x2000,
+            prg_ram_size: 0,
+            prg_nvram_size: 0,
+            chr_ram_size: 0,
+            chr_nvram_size: 0,
+            mirroring,
+            battery,
+            trainer,
+            console_type: ConsoleType::Nes,
+            timing: TimingRegion::Ntsc,
+            misc_roms: 0,
+            expansion_device: 0,
+        };
+
+        match format {
+            HeaderFormat::Nes2 => header.parse_nes2(data),
+            HeaderFormat::INes => header.parse_ines(data),
+            HeaderFormat::ArchaicINes => header.apply_ines_ram_defaults(0x2000),
+        }
+
+        Ok(header)
+    }
+
+    fn parse_ines(&mut self, data: &[u8]) {
+        let flags_7 = data[7];
+        self.mapper |= (flags_7 & 0xF0) as u16;
+        self.console_type = if flags_7 & 0x01 != 0 {
+            ConsoleType::VsSystem { ppu_type: 0, hardware_type: 0 }
+        } else if flags_7 & 0x02 != 0 {
+            ConsoleType::Playchoice10
+        } else {
+            ConsoleType::Nes
+        };
+        if data[9] & 0x01 != 0 {
+            self.timing = TimingRegion::Pal;
+        }
+
+        // Byte 8 counts 8KB units of PRG RAM, with 0 meaning 8KB for compatibility
+        let prg_ram_size = if data[8] == 0 { 0x2000 } else { data[8] as usize * 0x2000 };
+        self.apply_ines_ram_defaults(prg_ram_size);
+    }
+
+    /// iNES cannot describe RAM precisely: the PRG RAM is battery backed if the battery bit
+    /// is set, and boards without CHR ROM have 8KB of CHR RAM.
+    fn apply_ines_ram_defaults(&mut self, prg_ram_size: usize) {
+        if self.battery {
+            self.prg_nvram_size = prg_ram_size;
+        } else {
+            self.prg_ram_size = prg_ram_size;
+        }
+        if self.chr_rom_size == 0 {
+            self.chr_ram_size = 0x2000;
+        }
+    }
+
+    fn parse_nes2(&mut self, data: &[u8]) {
+        let flags_7 = data[7];
+        self.mapper |= (flags_7 & 0xF0) as u16 | ((data[8] & 0x0F) as u16) << 8;
+        self.submapper = data[8] >> 4;
+
+        self.prg_rom_size = nes2_rom_size(data[4], data[9] & 0x0F, 0x4000);
+        self.chr_rom_size = nes2_rom_size(data[5], data[9] >> 4, 0x2000);
+
+        self.prg_ram_size = shift_size(data[10] & 0x0F);
+        self.prg_nvram_size = shift_size(data[10] >> 4);
+        self.chr_ram_size = shift_size(data[11] & 0x0F);
+        self.chr_nvram_size = shift_size(data[11] >> 4);
+
+        self.timing = match data[12] & 0x03 {
+            0 => TimingRegion::Ntsc,
+            1 => TimingRegion::Pal,
+            2 => TimingRegion::MultiRegion,
+            _ => TimingRegion::Dendy,
+        };
+        self.console_type = match flags_7 & 0x03 {
+            0 => ConsoleType::Nes,
+            1 => ConsoleType::VsSystem { ppu_type: data[13] & 0x0F, hardware_type: data[13] >> 4 },
+            2 => ConsoleType::Playchoice10,
+            _ => ConsoleType::Extended(data[13] & 0x0F),
+        };
+        self.misc_roms = data[14] & 0x03;
+        self.expansion_device = data[15] & 0x3F;
+    }
+
+    /// All PRG RAM on the board, volatile and battery backed.
+    pub fn total_prg_ram_size(&self) -> usize {
+        self.prg_ram_size + self.prg_nvram_size
+    }
+
+    /// All CHR RAM on the board, volatile and battery backed.
+    pub fn total_chr_ram_size(&self) -> usize {
+        self.chr_ram_size + self.chr_nvram_size
+    }
+}
+
+/// NES 2.0 ROM size from the iNES count byte and the MSB nibble in byte 9.
+/// An MSB nibble of $F switches the count byte to exponent-multiplier notation.
+fn nes2_rom_size(lsb: u8, msb: u8, unit: usize) -> usize {
+    if msb == 0x0F {
+        let exponent = (lsb >> 2) as u32;
+        let multiplier = (lsb & 0x03) as usize * 2 + 1;
+        2usize.saturating_pow(exponent).saturating_mul(multiplier)
+    } else {
+        ((msb as usize) << 8 | lsb as usize) * unit
+    }
+}
+
+/// NES 2.0 RAM sizes are stored as a shift count: 64 << shift bytes, with 0 meaning none.
+fn shift_size(shift: u8) -> usize {
+    if shift == 0 {
+        0
+    } else {
+        64 << shift
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{ConsoleType, HeaderFormat, RomHeader, TimingRegion};
+
+    fn header(bytes: [u8; 12]) -> [u8; 16] {
+        let mut data = [0; 16];
+        data[..4].copy_from_slice(b"NES\x1A");
+        data[4..].copy_from_slice(&bytes);
+        data
+    }
+
+    #[test]
+    fn parses_ines_headers() {
+        let header = RomHeader::parse(&header([2, 1, 0x13, 0x40, 0, 0, 0, 0, 0, 0, 0, 0])).unwrap();
+        assert_eq!(header.format, HeaderFormat::INes);
+        assert_eq!(header.mapper, 0x41);
+        assert_eq!(header.prg_rom_size, 0x8000);
+        assert_eq!(header.chr_rom_size, 0x2000);
+        assert!(header.battery);
+        assert_eq!(header.prg_nvram_size, 0x2000);
+        assert_eq!(header.chr_ram_size, 0);
+    }
+
+    #[test]
+    fn parses_nes2_headers() {
+        // Mapper 0x1A5 submapper 3, 512KB PRG, CHR RAM 32KB, PRG NVRAM 8KB, PAL, Vs. System
+        let header =
+            RomHeader::parse(&header([0x20, 0, 0x52, 0xA9, 0x31, 0x00, 0x70, 0x09, 0x01, 0x21, 0, 0x02])).unwrap();
+        assert_eq!(header.format, HeaderFormat::Nes2);
+        assert_eq!(header.mapper, 0x1A5);
+        assert_eq!(header.submapper, 3);
+        assert_eq!(header.prg_rom_size, 0x80000);
+        assert_eq!(header.prg_ram_size, 0);
+        assert_eq!(header.prg_nvram_size, 0x2000);
+        assert_eq!(header.chr_ram_size, 0x8000);
+        assert_eq!(header.timing, TimingRegion::Pal);
+        assert_eq!(header.console_type, ConsoleType::VsSystem { ppu_type: 1, hardware_type: 2 });
+        assert_eq!(header.expansion_device, 0x02);
+    }
+
+    #[test]
+    fn parses_exponent_multiplier_sizes() {
+        // 2^10 * 3 bytes of PRG ROM
+        let header = RomHeader::parse(&header([0x29, 0, 0, 0x08, 0, 0x0F, 0, 0, 0, 0, 0, 0])).unwrap();
+        assert_eq!(header.prg_rom_size, 3072);
+    }
+
+    #[test]
+    fn ignores_diskdude_garbage() {
+        let mut data = header([1, 1, 0x20, 0, 0, 0, 0, 0, 0, 0, 0, 0]);
+        data[7..16].copy_from_slice(b"DiskDude!");
+        let header = RomHeader::parse(&data).unwrap();
+        assert_eq!(header.format, HeaderFormat::ArchaicINes);
+        // 'D' in byte 7 would otherwise turn mapper 2 into mapper 0x42
+        assert_eq!(header.mapper, 2);
+        assert_eq!(header.console_type, ConsoleType::Nes);
+    }
+}
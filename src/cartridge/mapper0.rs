@@ -0,0 +1,81 @@
+// NROM (Mapper 0) implementation
+// No bank switching: 16KB or 32KB PRG ROM and 8KB CHR. Super Mario Bros, Donkey Kong, etc.
+
+use super::{ChrMemory, Mapper, Mirroring};
+use crate::state::{StateReader, StateWriter};
+use std::io::Result;
+
+pub struct Mapper0 {
+    prg_rom: Vec<u8>,
+    chr: ChrMemory,
+    prg_ram: Vec<u8>,
+    mirroring: Mirroring,
+}
+
+impl Mapper0 {
+    pub fn new(prg_rom: Vec<u8>, chr: ChrMemory, prg_ram_size: usize, mirroring: Mirroring) -> Self {
+        Mapper0 {
+            prg_rom,
+            chr,
+            prg_ram: vec![0; prg_ram_size],
+            mirroring,
+        }
+    }
+}
+
+impl Mapper for Mapper0 {
+    fn read_prg(&mut self, addr: u16) -> u8 {
+        match addr {
+            0x6000..=0x7FFF if !self.prg_ram.is_empty() => {
+                self.prg_ram[(addr as usize - 0x6000) % self.prg_ram.len()]
+            }
+            // 16KB images are mirrored at $C000, 32KB images map directly
+            0x8000..=0xFFFF => self.prg_rom[(addr as usize - 0x8000) % self.prg_rom.len()],
+            _ => 0,
+        }
+    }
+
+    fn write_prg(&mut self, addr: u16, value: u8) {
+        match addr {
+            0x6000..=0x7FFF if !self.prg_ram.is_empty() => {
+                let len = self.prg_ram.len();
+                self.prg_ram[(addr as usize - 0x6000) % len] = value;
+            }
+            0x8000..=0xFFFF => {
+                log::warn!("Attempting to write to ROM at {:04X}", addr);
+            }
+            _ => {}
+        }
+    }
+
+    fn read_chr(&mut self, addr: u16) -> u8 {
+        self.chr.read((addr & 0x1FFF) as usize)
+    }
+
+    fn write_chr(&mut self, addr: u16, value: u8) {
+        self.chr.write((addr & 0x1FFF) as usize, value);
+    }
+
+    fn mirroring(&self) -> Mirroring {
+        self.mirroring
+    }
+
+    fn prg_ram(&self) -> Option<&[u8]> {
+        Some(&self.prg_ram)
+    }
+
+    fn prg_ram_mut(&mut self) -> Option<&mut [u8]> {
+        Some(&mut self.prg_ram)
+    }
+
+    fn save_state(&self, state: &mut StateWriter) {
+        state.write_bytes(&self.prg_ram);
+        self.chr.save_state(state);
+    }
+
+    fn load_state(&mut self, state: &mut StateReader) -> Result<()> {
+        state.read_into(&mut self.prg_ram)?;
+        self.chr.load_state(state)?;
+        Ok(())
+    }
+}
@@ -0,0 +1,239 @@
+// MMC1 (Mapper 1) implementation
+// Serial-loaded bank registers. Used by Zelda, Metroid, Mega Man 2, etc.
+// SUROM/SXROM boards reuse the CHR bank registers for a 256KB PRG outer bank and PRG RAM banks.
+
+use super::{ChrMemory, Mapper, Mirroring};
+use crate::state::{StateReader, StateWriter};
+use std::io::Result;
+
+pub struct Mapper1 {
+    prg_rom: Vec<u8>,
+    chr: ChrMemory,
+    prg_ram: Vec<u8>,
+
+    // Serial load register
+    shift_register: u8,
+    shift_count: u8,
+    // The serial port ignores a write on the cycle right after another, which
+    // makes read-modify-write instructions only load their first write
+    cycles_since_write: u8,
+
+    // Internal registers
+    control: u8,
+    chr_bank_0: u8,
+    chr_bank_1: u8,
+    prg_bank: u8,
+
+    // In 4KB CHR mode the board lines driven by CHR bank bits follow PPU A12
+    chr_a12: bool,
+}
+
+impl Mapper1 {
+    pub fn new(prg_rom: Vec<u8>, chr: ChrMemory, prg_ram_size: usize, mirroring: Mirroring) -> Self {
+        // Power-on mirroring is undefined; start with the header's so early frames look right
+        let mirroring_bits = match mirroring {
+            Mirroring::Vertical => 0x02,
+            _ => 0x03,
+        };
+        Mapper1 {
+            prg_rom,
+            chr,
+            prg_ram: vec![0; prg_ram_size],
+            shift_register: 0,
+            shift_count: 0,
+            cycles_since_write: u8::MAX,
+            control: 0x0C | mirroring_bits, // 16KB PRG mode, fixed high bank
+            chr_bank_0: 0,
+            chr_bank_1: 0,
+            prg_bank: 0,
+            chr_a12: false,
+        }
+    }
+
+    fn read_prg_rom(&self, offset: usize) -> u8 {
+        if offset < self.prg_rom.len() {
+            self.prg_rom[offset]
+        } else {
+            0
+        }
+    }
+
+    /// CHR bank register currently driving the board's extra lines.
+    fn active_chr_bank(&self) -> u8 {
+        if self.control & 0x10 != 0 && self.chr_a12 {
+            self.chr_bank_1
+        } else {
+            self.chr_bank_0
+        }
+    }
+
+    /// Translate a PPU pattern address to a CHR offset using the 4KB/8KB CHR mode.
+    fn chr_offset(&self, addr: u16) -> usize {
+        let addr = (addr & 0x1FFF) as usize;
+        if self.control & 0x10 == 0 {
+            // 8KB mode: low bit of CHR bank 0 is ignored
+            ((self.chr_bank_0 & 0x1E) as usize) * 0x1000 + addr
+        } else if addr < 0x1000 {
+            (self.chr_bank_0 as usize) * 0x1000 + addr
+        } else {
+            (self.chr_bank_1 as usize) * 0x1000 + (addr & 0x0FFF)
+        }
+    }
+
+    /// Offset of a $6000-$7FFF address in PRG RAM, or None while the RAM is disabled.
+    fn prg_ram_offset(&self, addr: u16) -> Option<usize> {
+        // MMC1B: bit 4 of the PRG bank register disables PRG RAM
+        if self.prg_ram.is_empty() || self.prg_bank & 0x10 != 0 {
+            return None;
+        }
+        // SOROM (16KB) and SXROM (32KB) pick an 8KB RAM bank with CHR bank bits 3 and 2-3
+        let bank = match self.prg_ram.len() {
+            0x8000 => (self.active_chr_bank() >> 2) & 0x03,
+            0x4000 => (self.active_chr_bank() >> 3) & 0x01,
+            _ => 0,
+        };
+        Some((bank as usize * 0x2000 + (addr as usize - 0x6000)) % self.prg_ram.len())
+    }
+
+    fn read_banked_prg(&self, addr: u16) -> u8 {
+        let addr = (addr - 0x8000) as usize;
+        // SUROM/SXROM: 512KB of PRG ROM, CHR bank bit 4 selects which 256KB half is visible
+        let outer = if self.prg_rom.len() > 0x40000 {
+            (self.active_chr_bank() & 0x10) as usize
+        } else {
+            0
+        };
+        let bank = (self.prg_bank & 0x0F) as usize;
+        let prg_mode = (self.control >> 2) & 0x03;
+
+        let bank = match prg_mode {
+            // 32KB mode: ignore low bit of bank number
+            0 | 1 => (bank & 0x0E) + addr / 0x4000,
+            // Fix first bank at $8000, switch 16KB bank at $C000
+            2 => if addr < 0x4000 { 0 } else { bank },
+            // Switch 16KB bank at $8000, fix last bank at $C000
+            _ => if addr < 0x4000 { bank } else { 0x0F },
+        };
+        let offset = ((outer | bank) * 0x4000 + (addr & 0x3FFF)) % self.prg_rom.len().max(1);
+        self.read_prg_rom(offset)
+    }
+
+    fn write_register(&mut self, addr: u16, value: u8) {
+        if value & 0x80 != 0 {
+            // Reset sequence
+            self.shift_register = 0;
+            self.shift_count = 0;
+            self.control |= 0x0C; // Set to mode 3
+            return;
+        }
+
+        self.shift_register = (self.shift_register >> 1) | ((value & 1) << 4);
+        self.shift_count += 1;
+
+        if self.shift_count == 5 {
+            // Complete write, register selected by address bits 13-14
+            match addr & 0x6000 {
+                0x0000 => self.control = self.shift_register,     // $8000-$9FFF
+                0x2000 => self.chr_bank_0 = self.shift_register,  // $A000-$BFFF
+                0x4000 => self.chr_bank_1 = self.shift_register,  // $C000-$DFFF
+                _ => self.prg_bank = self.shift_register,         // $E000-$FFFF
+            }
+            self.shift_register = 0;
+            self.shift_count = 0;
+        }
+    }
+}
+
+impl Mapper for Mapper1 {
+    fn read_prg(&mut self, addr: u16) -> u8 {
+        match addr {
+            0x6000..=0x7FFF => match self.prg_ram_offset(addr) {
+                Some(offset) => self.prg_ram[offset],
+                None => 0,
+            },
+            0x8000..=0xFFFF => self.read_banked_prg(addr),
+            _ => 0,
+        }
+    }
+
+    fn write_prg(&mut self, addr: u16, value: u8) {
+        match addr {
+            0x6000..=0x7FFF => {
+                if let Some(offset) = self.prg_ram_offset(addr) {
+                    self.prg_ram[offset] = value;
+                }
+            }
+            0x8000..=0xFFFF => {
+                let consecutive = self.cycles_since_write < 2;
+                self.cycles_since_write = 0;
+                if !consecutive {
+                    self.write_register(addr, value);
+                }
+            }
+            _ => {}
+        }
+    }
+
+    fn read_chr(&mut self, addr: u16) -> u8 {
+        self.chr_a12 = addr & 0x1000 != 0;
+        self.chr.read(self.chr_offset(addr))
+    }
+
+    fn peek_chr(&mut self, addr: u16) -> u8 {
+        self.chr.read(self.chr_offset(addr))
+    }
+
+    fn write_chr(&mut self, addr: u16, value: u8) {
+        self.chr_a12 = addr & 0x1000 != 0;
+        let offset = self.chr_offset(addr);
+        self.chr.write(offset, value);
+    }
+
+    fn mirroring(&self) -> Mirroring {
+        match self.control & 0x03 {
+            0 => Mirroring::SingleScreenLower,
+            1 => Mirroring::SingleScreenUpper,
+            2 => Mirroring::Vertical,
+            _ => Mirroring::Horizontal,
+        }
+    }
+
+    fn clock_cpu(&mut self) {
+        self.cycles_since_write = self.cycles_since_write.saturating_add(1);
+    }
+
+    fn prg_ram(&self) -> Option<&[u8]> {
+        Some(&self.prg_ram)
+    }
+
+    fn prg_ram_mut(&mut self) -> Option<&mut [u8]> {
+        Some(&mut self.prg_ram)
+    }
+
+    fn save_state(&self, state: &mut StateWriter) {
+        state.write_bytes(&self.prg_ram);
+        self.chr.save_state(state);
+        state.write_u8(self.shift_register);
+        state.write_u8(self.shift_count);
+        state.write_u8(self.cycles_since_write);
+        state.write_u8(self.control);
+        state.write_u8(self.chr_bank_0);
+        state.write_u8(self.chr_bank_1);
+        state.write_u8(self.prg_bank);
+        state.write_bool(self.chr_a12);
+    }
+
+    fn load_state(&mut self, state: &mut StateReader) -> Result<()> {
+        state.read_into(&mut self.prg_ram)?;
+        self.chr.load_state(state)?;
+        self.shift_register = state.read_u8()?;
+        self.shift_count = state.read_u8()?;
+        self.cycles_since_write = state.read_u8()?;
+        self.control = state.read_u8()?;
+        self.chr_bank_0 = state.read_u8()?;
+        self.chr_bank_1 = state.read_u8()?;
+        self.prg_bank = state.read_u8()?;
+        self.chr_a12 = state.read_bool()?;
+        Ok(())
+    }
+}
@@ -0,0 +1,224 @@
+// Bandai FCG (Mappers 16, 153 and 159) implementation
+// A 16KB switchable PRG bank, eight 1KB CHR banks and a 16-bit IRQ counter that counts down
+// every CPU cycle. The FCG-1/2 chips decode their registers at $6000-$7FFF; the later LZ93D50
+// at $8000-$FFFF, with a serial EEPROM for saves (24C02 on mapper 16, 24C01 on mapper 159).
+// Mapper 153 swaps the EEPROM for 8KB of PRG RAM and uses the CHR registers as a 256KB
+// outer PRG bank. Dragon Ball Z, SD Gundam Gaiden, Famicom Jump II.
+// Without a submapper, mapper 16 decodes both register ranges.
+
+use super::eeprom::Eeprom;
+use super::{ChrMemory, Mapper, Mirroring};
+use crate::state::{StateReader, StateWriter};
+use std::io::Result;
+
+pub struct Mapper16 {
+    prg_rom: Vec<u8>,
+    chr: ChrMemory,
+    prg_ram: Vec<u8>,
+    eeprom: Option<Eeprom>,
+
+    // Register ranges the chip decodes
+    fcg_registers: bool,
+    lz93d50_registers: bool,
+    // Mapper 153: CHR register bit 0 selects the 256KB PRG half
+    outer_prg_bank: bool,
+
+    prg_bank: u8,
+    chr_banks: [u8; 8],
+    mirroring: u8,
+    // $xD: LZ93D50 EEPROM clock/data lines, or the PRG RAM enable on mapper 153
+    control: u8,
+
+    irq_enabled: bool,
+    irq_counter: u16,
+    // The LZ93D50 loads the counter from a latch; FCG writes the counter directly
+    irq_latch: u16,
+    irq_pending: bool,
+}
+
+impl Mapper16 {
+    pub fn new(mapper: u16, submapper: u8, prg_rom: Vec<u8>, chr: ChrMemory, prg_ram_size: usize) -> Self {
+        let (fcg_registers, lz93d50_registers) = match (mapper, submapper) {
+            (16, 4) => (true, false),
+            (16, 5) => (false, true),
+            (16, _) => (true, true),
+            _ => (false, true),
+        };
+        let eeprom = match (mapper, submapper) {
+            (16, 4) => None,
+            (16, _) => Some(Eeprom::new_24c02()),
+            (159, _) => Some(Eeprom::new_24c01()),
+            _ => None,
+        };
+        let prg_ram_size = if mapper == 153 { prg_ram_size.max(0x2000) } else { 0 };
+        Mapper16 {
+            prg_rom,
+            chr,
+            prg_ram: vec![0; prg_ram_size],
+            eeprom,
+            fcg_registers,
+            lz93d50_registers,
+            outer_prg_bank: mapper == 153,
+            prg_bank: 0,
+            chr_banks: [0; 8],
+            mirroring: 0,
+            control: 0,
+            irq_enabled: false,
+            irq_counter: 0,
+            irq_latch: 0,
+            irq_pending: false,
+        }
+    }
+
+    fn read_prg_rom(&self, bank: usize, addr: u16) -> u8 {
+        let outer = if self.outer_prg_bank {
+            (self.chr_banks.iter().fold(0, |bits, &bank| bits | bank) & 0x01) as usize * 0x10
+        } else {
+            0
+        };
+        let offset = (outer | bank) * 0x4000 + (addr & 0x3FFF) as usize;
+        self.prg_rom[offset % self.prg_rom.len()]
+    }
+
+    fn prg_ram_enabled(&self) -> bool {
+        !self.prg_ram.is_empty() && self.control & 0x20 != 0
+    }
+
+    /// Write register $x0-$xF. The LZ93D50 range loads the IRQ counter through a latch.
+    fn write_register(&mut self, register: u16, value: u8, latched_irq: bool) {
+        match register {
+            0x0..=0x7 => self.chr_banks[register as usize] = value,
+            0x8 => self.prg_bank = value & 0x0F,
+            0x9 => self.mirroring = value & 0x03,
+            0xA => {
+                self.irq_enabled = value & 0x01 != 0;
+                self.irq_pending = false;
+                if latched_irq {
+                    self.irq_counter = self.irq_latch;
+                }
+            }
+            0xB if latched_irq => self.irq_latch = (self.irq_latch & 0xFF00) | value as u16,
+            0xC if latched_irq => self.irq_latch = (self.irq_latch & 0x00FF) | (value as u16) << 8,
+            0xB => self.irq_counter = (self.irq_counter & 0xFF00) | value as u16,
+            0xC => self.irq_counter = (self.irq_counter & 0x00FF) | (value as u16) << 8,
+            0xD => {
+                self.control = value;
+                if let Some(eeprom) = &mut self.eeprom {
+                    eeprom.write(value & 0x20 != 0, value & 0x40 != 0);
+                }
+            }
+            _ => {}
+        }
+    }
+}
+
+impl Mapper for Mapper16 {
+    fn read_prg(&mut self, addr: u16) -> u8 {
+        match addr {
+            0x6000..=0x7FFF if self.prg_ram_enabled() => self.prg_ram[(addr as usize - 0x6000) % self.prg_ram.len()],
+            // The EEPROM's data line shows up on bit 4
+            0x6000..=0x7FFF => match &self.eeprom {
+                Some(eeprom) => (eeprom.output() as u8) << 4,
+                None => 0,
+            },
+            0x8000..=0xBFFF => self.read_prg_rom(self.prg_bank as usize, addr),
+            0xC000..=0xFFFF => self.read_prg_rom(0x0F, addr),
+            _ => 0,
+        }
+    }
+
+    fn write_prg(&mut self, addr: u16, value: u8) {
+        match addr {
+            0x6000..=0x7FFF if self.prg_ram_enabled() => {
+                let len = self.prg_ram.len();
+                self.prg_ram[(addr as usize - 0x6000) % len] = value;
+            }
+            0x6000..=0x7FFF if self.fcg_registers => self.write_register(addr & 0x0F, value, false),
+            0x8000..=0xFFFF if self.lz93d50_registers => self.write_register(addr & 0x0F, value, true),
+            _ => {}
+        }
+    }
+
+    fn read_chr(&mut self, addr: u16) -> u8 {
+        if self.chr.is_ram() {
+            return self.chr.read(addr as usize);
+        }
+        let bank = self.chr_banks[((addr >> 10) & 0x07) as usize] as usize;
+        self.chr.read(bank * 0x400 + (addr & 0x3FF) as usize)
+    }
+
+    fn write_chr(&mut self, addr: u16, value: u8) {
+        // Only mapper 153 boards carry CHR RAM, and it is not banked
+        self.chr.write(addr as usize, value);
+    }
+
+    fn mirroring(&self) -> Mirroring {
+        match self.mirroring {
+            0 => Mirroring::Vertical,
+            1 => Mirroring::Horizontal,
+            2 => Mirroring::SingleScreenLower,
+            _ => Mirroring::SingleScreenUpper,
+        }
+    }
+
+    fn irq_pending(&self) -> bool {
+        self.irq_pending
+    }
+
+    fn clock_cpu(&mut self) {
+        if self.irq_enabled {
+            self.irq_counter = self.irq_counter.wrapping_sub(1);
+            if self.irq_counter == 0 {
+                self.irq_pending = true;
+            }
+        }
+    }
+
+    /// The EEPROM, or PRG RAM on mapper 153, holds the save.
+    fn prg_ram(&self) -> Option<&[u8]> {
+        match &self.eeprom {
+            Some(eeprom) => Some(eeprom.data()),
+            None => Some(&self.prg_ram),
+        }
+    }
+
+    fn prg_ram_mut(&mut self) -> Option<&mut [u8]> {
+        match &mut self.eeprom {
+            Some(eeprom) => Some(eeprom.data_mut()),
+            None => Some(&mut self.prg_ram),
+        }
+    }
+
+    fn save_state(&self, state: &mut StateWriter) {
+        state.write_bytes(&self.prg_ram);
+        self.chr.save_state(state);
+        if let Some(eeprom) = &self.eeprom {
+            eeprom.save_state(state);
+        }
+        state.write_u8(self.prg_bank);
+        state.write_bytes(&self.chr_banks);
+        state.write_u8(self.mirroring);
+        state.write_u8(self.control);
+        state.write_bool(self.irq_enabled);
+        state.write_u16(self.irq_counter);
+        state.write_u16(self.irq_latch);
+        state.write_bool(self.irq_pending);
+    }
+
+    fn load_state(&mut self, state: &mut StateReader) -> Result<()> {
+        state.read_into(&mut self.prg_ram)?;
+        self.chr.load_state(state)?;
+        if let Some(eeprom) = &mut self.eeprom {
+            eeprom.load_state(state)?;
+        }
+        self.prg_bank = state.read_u8()?;
+        state.read_into(&mut self.chr_banks)?;
+        self.mirroring = state.read_u8()?;
+        self.control = state.read_u8()?;
+        self.irq_enabled = state.read_bool()?;
+        self.irq_counter = state.read_u16()?;
+        self.irq_latch = state.read_u16()?;
+        self.irq_pending = state.read_bool()?;
+        Ok(())
+    }
+}
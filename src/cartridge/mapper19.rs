@@ -0,0 +1,226 @@
+// Namco 163 (Mapper 19) implementation
+// Three switchable 8KB PRG banks, eight 1KB CHR banks, and four nametable registers that map
+// either a CIRAM page (values $E0-$FF) or a 1KB CHR ROM bank into each nametable. The chip has
+// 128 bytes of internal RAM behind an address/data port and a 15-bit IRQ counter that counts
+// up every CPU cycle. Megami Tensei II, Splatterhouse: Wanpaku Graffiti, Final Lap.
+// The expansion audio that plays from the internal RAM is not emulated. Pattern table banks of
+// $E0 and up can also select CIRAM on the chip; here they always read CHR.
+
+use super::{ChrMemory, Mapper, Mirroring};
+use crate::state::{StateReader, StateWriter};
+use std::io::Result;
+
+const INTERNAL_RAM_SIZE: usize = 0x80;
+
+pub struct Mapper19 {
+    prg_rom: Vec<u8>,
+    chr: ChrMemory,
+    // PRG RAM followed by the chip's internal RAM; a battery keeps both alive
+    ram: Vec<u8>,
+
+    prg_banks: [u8; 3],
+    chr_banks: [u8; 8],
+    nametable_banks: [u8; 4],
+    // $F800: internal RAM address (bits 0-6) with auto-increment (bit 7), and the PRG RAM
+    // write protection; writes need $4x in the high nibble and a clear bit for the 2KB block
+    ram_port: u8,
+
+    irq_enabled: bool,
+    irq_counter: u16,
+    irq_pending: bool,
+}
+
+impl Mapper19 {
+    pub fn new(prg_rom: Vec<u8>, chr: ChrMemory, prg_ram_size: usize) -> Self {
+        Mapper19 {
+            prg_rom,
+            chr,
+            ram: vec![0; prg_ram_size + INTERNAL_RAM_SIZE],
+            prg_banks: [0, 1, 2],
+            chr_banks: [0; 8],
+            nametable_banks: [0xE0, 0xE1, 0xE0, 0xE1],
+            ram_port: 0,
+            irq_enabled: false,
+            irq_counter: 0,
+            irq_pending: false,
+        }
+    }
+
+    fn prg_ram_size(&self) -> usize {
+        self.ram.len() - INTERNAL_RAM_SIZE
+    }
+
+    fn internal_ram_offset(&self) -> usize {
+        self.prg_ram_size() + (self.ram_port & 0x7F) as usize
+    }
+
+    /// Step the internal RAM address after a data port access when auto-increment is on.
+    fn advance_ram_port(&mut self) {
+        if self.ram_port & 0x80 != 0 {
+            self.ram_port = 0x80 | (self.ram_port.wrapping_add(1) & 0x7F);
+        }
+    }
+
+    fn prg_ram_writable(&self, addr: u16) -> bool {
+        let block = (addr - 0x6000) >> 11;
+        self.ram_port & 0xF0 == 0x40 && self.ram_port >> block & 0x01 == 0
+    }
+
+    fn chr_offset(&self, addr: u16) -> usize {
+        let bank = self.chr_banks[((addr >> 10) & 0x07) as usize] as usize;
+        bank * 0x400 + (addr & 0x3FF) as usize
+    }
+
+    /// CIRAM page selected for a nametable, or None when it maps CHR ROM.
+    fn ciram_page(&self, addr: u16) -> Option<usize> {
+        match self.nametable_banks[((addr >> 10) & 0x03) as usize] {
+            bank @ 0xE0..=0xFF => Some((bank & 0x01) as usize),
+            _ => None,
+        }
+    }
+
+    fn read_prg_rom(&self, bank: usize, addr: u16) -> u8 {
+        self.prg_rom[(bank * 0x2000 + (addr & 0x1FFF) as usize) % self.prg_rom.len()]
+    }
+}
+
+impl Mapper for Mapper19 {
+    fn read_prg(&mut self, addr: u16) -> u8 {
+        match addr {
+            0x4800..=0x4FFF => {
+                let value = self.ram[self.internal_ram_offset()];
+                self.advance_ram_port();
+                value
+            }
+            0x5000..=0x57FF => self.irq_counter as u8,
+            0x5800..=0x5FFF => (self.irq_enabled as u8) << 7 | (self.irq_counter >> 8) as u8,
+            0x6000..=0x7FFF if self.prg_ram_size() > 0 => self.ram[(addr as usize - 0x6000) % self.prg_ram_size()],
+            0x8000..=0xDFFF => {
+                let bank = self.prg_banks[((addr - 0x8000) >> 13) as usize] as usize;
+                self.read_prg_rom(bank, addr)
+            }
+            0xE000..=0xFFFF => self.read_prg_rom((self.prg_rom.len() / 0x2000).saturating_sub(1), addr),
+            _ => 0,
+        }
+    }
+
+    fn write_prg(&mut self, addr: u16, value: u8) {
+        match addr {
+            0x4800..=0x4FFF => {
+                let offset = self.internal_ram_offset();
+                self.ram[offset] = value;
+                self.advance_ram_port();
+            }
+            // Writing either half of the counter acknowledges the IRQ
+            0x5000..=0x57FF => {
+                self.irq_counter = (self.irq_counter & 0x7F00) | value as u16;
+                self.irq_pending = false;
+            }
+            0x5800..=0x5FFF => {
+                self.irq_counter = (self.irq_counter & 0x00FF) | ((value & 0x7F) as u16) << 8;
+                self.irq_enabled = value & 0x80 != 0;
+                self.irq_pending = false;
+            }
+            0x6000..=0x7FFF if self.prg_ram_size() > 0 && self.prg_ram_writable(addr) => {
+                let offset = (addr as usize - 0x6000) % self.prg_ram_size();
+                self.ram[offset] = value;
+            }
+            0x8000..=0xBFFF => self.chr_banks[((addr - 0x8000) >> 11) as usize] = value,
+            0xC000..=0xDFFF => self.nametable_banks[((addr - 0xC000) >> 11) as usize] = value,
+            // Bit 6 of $E000 mutes the sound; bits 6-7 of $E800 only matter for CIRAM pattern banks
+            0xE000..=0xF7FF => self.prg_banks[((addr - 0xE000) >> 11) as usize] = value & 0x3F,
+            0xF800..=0xFFFF => self.ram_port = value,
+            _ => {}
+        }
+    }
+
+    fn read_chr(&mut self, addr: u16) -> u8 {
+        self.chr.read(self.chr_offset(addr))
+    }
+
+    fn write_chr(&mut self, addr: u16, value: u8) {
+        let offset = self.chr_offset(addr);
+        self.chr.write(offset, value);
+    }
+
+    fn read_nametable(&mut self, addr: u16, ciram: &[u8; 0x800]) -> u8 {
+        let offset = (addr & 0x3FF) as usize;
+        match self.ciram_page(addr) {
+            Some(page) => ciram[page * 0x400 + offset],
+            None => {
+                let bank = self.nametable_banks[((addr >> 10) & 0x03) as usize] as usize;
+                self.chr.read(bank * 0x400 + offset)
+            }
+        }
+    }
+
+    fn write_nametable(&mut self, addr: u16, value: u8, ciram: &mut [u8; 0x800]) {
+        let offset = (addr & 0x3FF) as usize;
+        match self.ciram_page(addr) {
+            Some(page) => ciram[page * 0x400 + offset] = value,
+            None => {
+                let bank = self.nametable_banks[((addr >> 10) & 0x03) as usize] as usize;
+                self.chr.write(bank * 0x400 + offset, value);
+            }
+        }
+    }
+
+    fn mirroring(&self) -> Mirroring {
+        // Only for reporting: nametable accesses follow the registers through read_nametable
+        let pages = [0x000, 0x400, 0x800, 0xC00].map(|addr| self.ciram_page(addr));
+        match pages {
+            [Some(0), Some(1), Some(0), Some(1)] => Mirroring::Vertical,
+            [Some(0), Some(0), Some(1), Some(1)] => Mirroring::Horizontal,
+            [Some(1), Some(1), Some(1), Some(1)] => Mirroring::SingleScreenUpper,
+            [Some(0), Some(0), Some(0), Some(0)] => Mirroring::SingleScreenLower,
+            _ => Mirroring::Vertical,
+        }
+    }
+
+    fn irq_pending(&self) -> bool {
+        self.irq_pending
+    }
+
+    fn clock_cpu(&mut self) {
+        // The counter stops once it reaches $7FFF
+        if self.irq_enabled && self.irq_counter < 0x7FFF {
+            self.irq_counter += 1;
+            if self.irq_counter == 0x7FFF {
+                self.irq_pending = true;
+            }
+        }
+    }
+
+    fn prg_ram(&self) -> Option<&[u8]> {
+        Some(&self.ram)
+    }
+
+    fn prg_ram_mut(&mut self) -> Option<&mut [u8]> {
+        Some(&mut self.ram)
+    }
+
+    fn save_state(&self, state: &mut StateWriter) {
+        state.write_bytes(&self.ram);
+        self.chr.save_state(state);
+        state.write_bytes(&self.prg_banks);
+        state.write_bytes(&self.chr_banks);
+        state.write_bytes(&self.nametable_banks);
+        state.write_u8(self.ram_port);
+        state.write_bool(self.irq_enabled);
+        state.write_u16(self.irq_counter);
+        state.write_bool(self.irq_pending);
+    }
+
+    fn load_state(&mut self, state: &mut StateReader) -> Result<()> {
+        state.read_into(&mut self.ram)?;
+        self.chr.load_state(state)?;
+        state.read_into(&mut self.prg_banks)?;
+        state.read_into(&mut self.chr_banks)?;
+        state.read_into(&mut self.nametable_banks)?;
+        self.ram_port = state.read_u8()?;
+        self.irq_enabled = state.read_bool()?;
+        self.irq_counter = state.read_u16()?;
+        self.irq_pending = state.read_bool()?;
+        Ok(())
+    }
+}
@@ -0,0 +1,69 @@
+// UxROM (Mapper 2) implementation
+// 16KB switchable PRG bank at $8000 with the last bank fixed at $C000. Mega Man, Castlevania, Contra, etc.
+
+use super::{ChrMemory, Mapper, Mirroring};
+use crate::state::{StateReader, StateWriter};
+use std::io::Result;
+
+pub struct Mapper2 {
+    prg_rom: Vec<u8>,
+    chr: ChrMemory,
+    mirroring: Mirroring,
+    bus_conflicts: bool,
+
+    prg_bank: u8,
+}
+
+impl Mapper2 {
+    pub fn new(prg_rom: Vec<u8>, chr: ChrMemory, mirroring: Mirroring, bus_conflicts: bool) -> Self {
+        Mapper2 {
+            prg_rom,
+            chr,
+            mirroring,
+            bus_conflicts,
+            prg_bank: 0,
+        }
+    }
+}
+
+impl Mapper for Mapper2 {
+    fn read_prg(&mut self, addr: u16) -> u8 {
+        let offset = (addr & 0x3FFF) as usize;
+        match addr {
+            0x8000..=0xBFFF => self.prg_rom[(self.prg_bank as usize * 0x4000 + offset) % self.prg_rom.len()],
+            // Images smaller than the 16KB window repeat in it
+            0xC000..=0xFFFF => self.prg_rom[(self.prg_rom.len().saturating_sub(0x4000) + offset) % self.prg_rom.len()],
+            _ => 0,
+        }
+    }
+
+    fn write_prg(&mut self, addr: u16, value: u8) {
+        if addr >= 0x8000 {
+            // The ROM drives the data bus too, so the latch sees both values ANDed
+            self.prg_bank = if self.bus_conflicts { value & self.read_prg(addr) } else { value };
+        }
+    }
+
+    fn read_chr(&mut self, addr: u16) -> u8 {
+        self.chr.read((addr & 0x1FFF) as usize)
+    }
+
+    fn write_chr(&mut self, addr: u16, value: u8) {
+        self.chr.write((addr & 0x1FFF) as usize, value);
+    }
+
+    fn mirroring(&self) -> Mirroring {
+        self.mirroring
+    }
+
+    fn save_state(&self, state: &mut StateWriter) {
+        self.chr.save_state(state);
+        state.write_u8(self.prg_bank);
+    }
+
+    fn load_state(&mut self, state: &mut StateReader) -> Result<()> {
+        self.chr.load_state(state)?;
+        self.prg_bank = state.read_u8()?;
+        Ok(())
+    }
+}
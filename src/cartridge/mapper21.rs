@@ -0,0 +1,196 @@
+// Konami VRC2 and VRC4 (Mappers 21, 22, 23 and 25) implementation
+// Two switchable 8KB PRG banks and eight 1KB CHR banks. The four mapper numbers differ in which
+// CPU address lines drive the chip's two register select inputs, and NES 2.0 submappers tell
+// the board variants apart. Without a submapper both candidate lines are decoded at once.
+// Ganbare Goemon Gaiden, Gradius II, Teenage Mutant Ninja Turtles (Japan), etc.
+
+use super::vrc::{self, VrcIrq};
+use super::{ChrMemory, Mapper, Mirroring};
+use crate::state::{StateReader, StateWriter};
+use std::io::Result;
+
+pub struct Mapper21 {
+    prg_rom: Vec<u8>,
+    chr: ChrMemory,
+    prg_ram: Vec<u8>,
+
+    // CPU address lines wired to the register select inputs A0 and A1
+    a0_lines: u16,
+    a1_lines: u16,
+    // VRC2 lacks the IRQ, PRG swap mode and single-screen mirroring
+    vrc2: bool,
+    // VRC2a ignores the low bit of CHR bank numbers
+    chr_shift: u8,
+
+    prg_banks: [u8; 2],
+    prg_swap: bool,
+    chr_banks: [u16; 8],
+    mirroring: u8,
+    // Boards without PRG RAM have a one-bit latch at $6000-$7FFF instead
+    latch: u8,
+    irq: VrcIrq,
+}
+
+impl Mapper21 {
+    pub fn new(mapper: u16, submapper: u8, prg_rom: Vec<u8>, chr: ChrMemory, prg_ram_size: usize) -> Self {
+        let (a0_lines, a1_lines, vrc2) = match (mapper, submapper) {
+            (21, 1) => (0x02, 0x04, false), // VRC4a
+            (21, 2) => (0x40, 0x80, false), // VRC4c
+            (21, _) => (0x42, 0x84, false),
+            (22, _) => (0x02, 0x01, true), // VRC2a
+            (23, 1) => (0x01, 0x02, false), // VRC4f
+            (23, 2) => (0x04, 0x08, false), // VRC4e
+            (23, 3) => (0x01, 0x02, true),  // VRC2b
+            (23, _) => (0x05, 0x0A, false),
+            (25, 1) => (0x02, 0x01, false), // VRC4b
+            (25, 2) => (0x08, 0x04, false), // VRC4d
+            (25, 3) => (0x02, 0x01, true),  // VRC2c
+            _ => (0x0A, 0x05, false),
+        };
+        Mapper21 {
+            prg_rom,
+            chr,
+            prg_ram: vec![0; prg_ram_size],
+            a0_lines,
+            a1_lines,
+            vrc2,
+            chr_shift: if mapper == 22 { 1 } else { 0 },
+            prg_banks: [0, 0],
+            prg_swap: false,
+            chr_banks: [0; 8],
+            mirroring: 0,
+            latch: 0,
+            irq: VrcIrq::new(),
+        }
+    }
+
+    /// Collapse a CPU address to $x000-$x003 by its register select lines.
+    fn register(&self, addr: u16) -> u16 {
+        let a0 = (addr & self.a0_lines != 0) as u16;
+        let a1 = (addr & self.a1_lines != 0) as u16;
+        (addr & 0xF000) | a1 << 1 | a0
+    }
+
+    fn chr_offset(&self, addr: u16) -> usize {
+        let bank = (self.chr_banks[((addr >> 10) & 0x07) as usize] >> self.chr_shift) as usize;
+        bank * 0x400 + (addr & 0x3FF) as usize
+    }
+
+    fn read_prg_rom(&self, bank: usize, addr: u16) -> u8 {
+        self.prg_rom[(bank * 0x2000 + (addr & 0x1FFF) as usize) % self.prg_rom.len()]
+    }
+}
+
+impl Mapper for Mapper21 {
+    fn read_prg(&mut self, addr: u16) -> u8 {
+        // Images under 16KB repeat in both fixed banks
+        let last_bank = (self.prg_rom.len() / 0x2000).saturating_sub(1);
+        let second_last_bank = last_bank.saturating_sub(1);
+        match addr {
+            0x6000..=0x7FFF if !self.prg_ram.is_empty() => {
+                self.prg_ram[(addr as usize - 0x6000) % self.prg_ram.len()]
+            }
+            // Only bit 0 is driven, the rest is open bus
+            0x6000..=0x7FFF => 0x60 | self.latch,
+            0x8000..=0x9FFF if self.prg_swap => self.read_prg_rom(second_last_bank, addr),
+            0x8000..=0x9FFF => self.read_prg_rom(self.prg_banks[0] as usize, addr),
+            0xA000..=0xBFFF => self.read_prg_rom(self.prg_banks[1] as usize, addr),
+            0xC000..=0xDFFF if self.prg_swap => self.read_prg_rom(self.prg_banks[0] as usize, addr),
+            0xC000..=0xDFFF => self.read_prg_rom(second_last_bank, addr),
+            0xE000..=0xFFFF => self.read_prg_rom(last_bank, addr),
+            _ => 0,
+        }
+    }
+
+    fn write_prg(&mut self, addr: u16, value: u8) {
+        if (0x6000..=0x7FFF).contains(&addr) {
+            if self.prg_ram.is_empty() {
+                self.latch = value & 0x01;
+            } else {
+                let len = self.prg_ram.len();
+                self.prg_ram[(addr as usize - 0x6000) % len] = value;
+            }
+            return;
+        }
+
+        match self.register(addr) {
+            0x8000..=0x8003 => self.prg_banks[0] = value & 0x1F,
+            0x9000 | 0x9001 if self.vrc2 => self.mirroring = value & 0x01,
+            0x9000 | 0x9001 => self.mirroring = value & 0x03,
+            0x9002 | 0x9003 if !self.vrc2 => self.prg_swap = value & 0x02 != 0,
+            0xA000..=0xA003 => self.prg_banks[1] = value & 0x1F,
+            register @ 0xB000..=0xE003 => {
+                // Each 1KB bank takes two registers: low nibble, then the high bits
+                let index = (((register >> 12) - 0xB) * 2 + ((register >> 1) & 0x01)) as usize;
+                let bank = &mut self.chr_banks[index];
+                if register & 0x01 == 0 {
+                    *bank = (*bank & !0x0F) | (value & 0x0F) as u16;
+                } else {
+                    let high_mask = if self.vrc2 { 0x0F } else { 0x1F };
+                    *bank = (*bank & 0x0F) | ((value & high_mask) as u16) << 4;
+                }
+            }
+            0xF000 if !self.vrc2 => self.irq.write_latch_low(value),
+            0xF001 if !self.vrc2 => self.irq.write_latch_high(value),
+            0xF002 if !self.vrc2 => self.irq.write_control(value),
+            0xF003 if !self.vrc2 => self.irq.acknowledge(),
+            _ => {}
+        }
+    }
+
+    fn read_chr(&mut self, addr: u16) -> u8 {
+        self.chr.read(self.chr_offset(addr))
+    }
+
+    fn write_chr(&mut self, addr: u16, value: u8) {
+        let offset = self.chr_offset(addr);
+        self.chr.write(offset, value);
+    }
+
+    fn mirroring(&self) -> Mirroring {
+        vrc::mirroring(self.mirroring)
+    }
+
+    fn irq_pending(&self) -> bool {
+        self.irq.pending()
+    }
+
+    fn clock_cpu(&mut self) {
+        self.irq.clock();
+    }
+
+    fn prg_ram(&self) -> Option<&[u8]> {
+        Some(&self.prg_ram)
+    }
+
+    fn prg_ram_mut(&mut self) -> Option<&mut [u8]> {
+        Some(&mut self.prg_ram)
+    }
+
+    fn save_state(&self, state: &mut StateWriter) {
+        state.write_bytes(&self.prg_ram);
+        self.chr.save_state(state);
+        state.write_bytes(&self.prg_banks);
+        state.write_bool(self.prg_swap);
+        for &bank in &self.chr_banks {
+            state.write_u16(bank);
+        }
+        state.write_u8(self.mirroring);
+        state.write_u8(self.latch);
+        self.irq.save_state(state);
+    }
+
+    fn load_state(&mut self, state: &mut StateReader) -> Result<()> {
+        state.read_into(&mut self.prg_ram)?;
+        self.chr.load_state(state)?;
+        state.read_into(&mut self.prg_banks)?;
+        self.prg_swap = state.read_bool()?;
+        for bank in &mut self.chr_banks {
+            *bank = state.read_u16()?;
+        }
+        self.mirroring = state.read_u8()?;
+        self.latch = state.read_u8()?;
+        self.irq.load_state(state)?;
+        Ok(())
+    }
+}
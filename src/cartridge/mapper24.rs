@@ -0,0 +1,168 @@
+// Konami VRC6 (Mappers 24 and 26) implementation
+// A 16KB and an 8KB switchable PRG bank plus eight CHR bank registers. Mapper 26 swaps the
+// A0 and A1 register select lines. Akumajou Densetsu, Madara, Esper Dream 2.
+// The expansion audio registers are accepted and ignored, and nametables always come from
+// CIRAM; no released game maps CHR ROM as nametables.
+
+use super::vrc::{self, VrcIrq};
+use super::{ChrMemory, Mapper, Mirroring};
+use crate::state::{StateReader, StateWriter};
+use std::io::Result;
+
+pub struct Mapper24 {
+    prg_rom: Vec<u8>,
+    chr: ChrMemory,
+    prg_ram: Vec<u8>,
+    swap_select_lines: bool,
+
+    prg_bank_16k: u8,
+    prg_bank_8k: u8,
+    chr_banks: [u8; 8],
+    // $B003: bit 7 PRG RAM enable, bit 5 CHR A10 from PPU A10, bits 2-3 mirroring, bits 0-1 CHR mode
+    banking_control: u8,
+    irq: VrcIrq,
+}
+
+impl Mapper24 {
+    pub fn new(prg_rom: Vec<u8>, chr: ChrMemory, prg_ram_size: usize, swap_select_lines: bool) -> Self {
+        Mapper24 {
+            prg_rom,
+            chr,
+            prg_ram: vec![0; prg_ram_size],
+            swap_select_lines,
+            prg_bank_16k: 0,
+            prg_bank_8k: 0,
+            chr_banks: [0; 8],
+            banking_control: 0,
+            irq: VrcIrq::new(),
+        }
+    }
+
+    fn register(&self, addr: u16) -> u16 {
+        if self.swap_select_lines {
+            (addr & 0xF000) | (addr & 0x01) << 1 | (addr & 0x02) >> 1
+        } else {
+            addr & 0xF003
+        }
+    }
+
+    fn prg_ram_enabled(&self) -> bool {
+        !self.prg_ram.is_empty() && self.banking_control & 0x80 != 0
+    }
+
+    /// 1KB CHR bank for a PPU address under the $B003 banking mode.
+    fn chr_bank(&self, addr: u16) -> usize {
+        let slot = ((addr >> 10) & 0x07) as usize;
+        // In the 2KB modes A10 comes from the PPU, or from the register when bit 5 is clear
+        let two_kb = |register: u8| {
+            if self.banking_control & 0x20 != 0 {
+                (register & 0xFE) as usize | slot & 0x01
+            } else {
+                register as usize
+            }
+        };
+        match self.banking_control & 0x03 {
+            0 => self.chr_banks[slot] as usize,
+            1 => two_kb(self.chr_banks[slot / 2]),
+            _ if slot < 4 => self.chr_banks[slot] as usize,
+            _ => two_kb(self.chr_banks[4 + (slot - 4) / 2]),
+        }
+    }
+
+    fn chr_offset(&self, addr: u16) -> usize {
+        self.chr_bank(addr) * 0x400 + (addr & 0x3FF) as usize
+    }
+
+    fn read_prg_rom(&self, offset: usize) -> u8 {
+        self.prg_rom[offset % self.prg_rom.len()]
+    }
+}
+
+impl Mapper for Mapper24 {
+    fn read_prg(&mut self, addr: u16) -> u8 {
+        match addr {
+            0x6000..=0x7FFF if self.prg_ram_enabled() => {
+                self.prg_ram[(addr as usize - 0x6000) % self.prg_ram.len()]
+            }
+            0x8000..=0xBFFF => self.read_prg_rom(self.prg_bank_16k as usize * 0x4000 + (addr & 0x3FFF) as usize),
+            0xC000..=0xDFFF => self.read_prg_rom(self.prg_bank_8k as usize * 0x2000 + (addr & 0x1FFF) as usize),
+            0xE000..=0xFFFF => self.read_prg_rom(self.prg_rom.len().saturating_sub(0x2000) + (addr & 0x1FFF) as usize),
+            _ => 0,
+        }
+    }
+
+    fn write_prg(&mut self, addr: u16, value: u8) {
+        if (0x6000..=0x7FFF).contains(&addr) {
+            if self.prg_ram_enabled() {
+                let len = self.prg_ram.len();
+                self.prg_ram[(addr as usize - 0x6000) % len] = value;
+            }
+            return;
+        }
+
+        match self.register(addr) {
+            0x8000..=0x8003 => self.prg_bank_16k = value & 0x0F,
+            0xB003 => self.banking_control = value,
+            0xC000..=0xC003 => self.prg_bank_8k = value & 0x1F,
+            register @ (0xD000..=0xD003 | 0xE000..=0xE003) => {
+                let index = ((register >> 12) - 0xD) * 4 + (register & 0x03);
+                self.chr_banks[index as usize] = value;
+            }
+            0xF000 => self.irq.write_latch(value),
+            0xF001 => self.irq.write_control(value),
+            0xF002 => self.irq.acknowledge(),
+            // $9000-$B002: expansion audio
+            _ => {}
+        }
+    }
+
+    fn read_chr(&mut self, addr: u16) -> u8 {
+        self.chr.read(self.chr_offset(addr))
+    }
+
+    fn write_chr(&mut self, addr: u16, value: u8) {
+        let offset = self.chr_offset(addr);
+        self.chr.write(offset, value);
+    }
+
+    fn mirroring(&self) -> Mirroring {
+        vrc::mirroring(self.banking_control >> 2)
+    }
+
+    fn irq_pending(&self) -> bool {
+        self.irq.pending()
+    }
+
+    fn clock_cpu(&mut self) {
+        self.irq.clock();
+    }
+
+    fn prg_ram(&self) -> Option<&[u8]> {
+        Some(&self.prg_ram)
+    }
+
+    fn prg_ram_mut(&mut self) -> Option<&mut [u8]> {
+        Some(&mut self.prg_ram)
+    }
+
+    fn save_state(&self, state: &mut StateWriter) {
+        state.write_bytes(&self.prg_ram);
+        self.chr.save_state(state);
+        state.write_u8(self.prg_bank_16k);
+        state.write_u8(self.prg_bank_8k);
+        state.write_bytes(&self.chr_banks);
+        state.write_u8(self.banking_control);
+        self.irq.save_state(state);
+    }
+
+    fn load_state(&mut self, state: &mut StateReader) -> Result<()> {
+        state.read_into(&mut self.prg_ram)?;
+        self.chr.load_state(state)?;
+        self.prg_bank_16k = state.read_u8()?;
+        self.prg_bank_8k = state.read_u8()?;
+        state.read_into(&mut self.chr_banks)?;
+        self.banking_control = state.read_u8()?;
+        self.irq.load_state(state)?;
+        Ok(())
+    }
+}
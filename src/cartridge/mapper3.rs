@@ -0,0 +1,72 @@
+// CNROM (Mapper 3) implementation
+// Fixed PRG ROM with a switchable 8KB CHR bank. Arkanoid, Gradius, Solomon's Key, etc.
+
+use super::{ChrMemory, Mapper, Mirroring};
+use crate::state::{StateReader, StateWriter};
+use std::io::Result;
+
+pub struct Mapper3 {
+    prg_rom: Vec<u8>,
+    chr: ChrMemory,
+    mirroring: Mirroring,
+    bus_conflicts: bool,
+
+    chr_bank: u8,
+}
+
+impl Mapper3 {
+    pub fn new(prg_rom: Vec<u8>, chr: ChrMemory, mirroring: Mirroring, bus_conflicts: bool) -> Self {
+        Mapper3 {
+            prg_rom,
+            chr,
+            mirroring,
+            bus_conflicts,
+            chr_bank: 0,
+        }
+    }
+
+    fn chr_offset(&self, addr: u16) -> usize {
+        self.chr_bank as usize * 0x2000 + (addr & 0x1FFF) as usize
+    }
+}
+
+impl Mapper for Mapper3 {
+    fn read_prg(&mut self, addr: u16) -> u8 {
+        match addr {
+            // 16KB images are mirrored at $C000, 32KB images map directly
+            0x8000..=0xFFFF => self.prg_rom[(addr as usize - 0x8000) % self.prg_rom.len()],
+            _ => 0,
+        }
+    }
+
+    fn write_prg(&mut self, addr: u16, value: u8) {
+        if addr >= 0x8000 {
+            // The ROM drives the data bus too, so the latch sees both values ANDed
+            self.chr_bank = if self.bus_conflicts { value & self.read_prg(addr) } else { value };
+        }
+    }
+
+    fn read_chr(&mut self, addr: u16) -> u8 {
+        self.chr.read(self.chr_offset(addr))
+    }
+
+    fn write_chr(&mut self, addr: u16, value: u8) {
+        let offset = self.chr_offset(addr);
+        self.chr.write(offset, value);
+    }
+
+    fn mirroring(&self) -> Mirroring {
+        self.mirroring
+    }
+
+    fn save_state(&self, state: &mut StateWriter) {
+        self.chr.save_state(state);
+        state.write_u8(self.chr_bank);
+    }
+
+    fn load_state(&mut self, state: &mut StateReader) -> Result<()> {
+        self.chr.load_state(state)?;
+        self.chr_bank = state.read_u8()?;
+        Ok(())
+    }
+}
@@ -0,0 +1,114 @@
+// BNROM and NINA-001 (Mapper 34) implementation
+// BNROM latches a 32KB PRG bank at $8000-$FFFF (Deadly Towers). NINA-001 has registers at
+// $7FFD-$7FFF for the PRG bank and two 4KB CHR banks, plus 8KB of PRG RAM (Impossible Mission II).
+
+use super::{ChrMemory, Mapper, Mirroring};
+use crate::state::{StateReader, StateWriter};
+use std::io::Result;
+
+pub struct Mapper34 {
+    prg_rom: Vec<u8>,
+    chr: ChrMemory,
+    prg_ram: Vec<u8>,
+    mirroring: Mirroring,
+    nina001: bool,
+
+    prg_bank: u8,
+    chr_banks: [u8; 2],
+}
+
+impl Mapper34 {
+    pub fn new(prg_rom: Vec<u8>, chr: ChrMemory, prg_ram_size: usize, mirroring: Mirroring, nina001: bool) -> Self {
+        Mapper34 {
+            prg_rom,
+            chr,
+            // NINA-001 always has PRG RAM, it holds the bank registers' address range
+            prg_ram: vec![0; if nina001 { prg_ram_size.max(0x2000) } else { prg_ram_size }],
+            mirroring,
+            nina001,
+            prg_bank: 0,
+            chr_banks: [0, 1],
+        }
+    }
+
+    fn chr_offset(&self, addr: u16) -> usize {
+        if self.nina001 {
+            let bank = self.chr_banks[((addr >> 12) & 0x01) as usize] as usize;
+            bank * 0x1000 + (addr & 0x0FFF) as usize
+        } else {
+            (addr & 0x1FFF) as usize
+        }
+    }
+}
+
+impl Mapper for Mapper34 {
+    fn read_prg(&mut self, addr: u16) -> u8 {
+        match addr {
+            0x6000..=0x7FFF if !self.prg_ram.is_empty() => {
+                self.prg_ram[(addr as usize - 0x6000) % self.prg_ram.len()]
+            }
+            0x8000..=0xFFFF => {
+                let bank = self.prg_bank as usize;
+                self.prg_rom[(bank * 0x8000 + (addr & 0x7FFF) as usize) % self.prg_rom.len()]
+            }
+            _ => 0,
+        }
+    }
+
+    fn write_prg(&mut self, addr: u16, value: u8) {
+        match addr {
+            0x6000..=0x7FFF if !self.prg_ram.is_empty() => {
+                let len = self.prg_ram.len();
+                self.prg_ram[(addr as usize - 0x6000) % len] = value;
+                // NINA-001 registers are written through to the RAM underneath
+                if self.nina001 {
+                    match addr {
+                        0x7FFD => self.prg_bank = value & 0x01,
+                        0x7FFE => self.chr_banks[0] = value & 0x0F,
+                        0x7FFF => self.chr_banks[1] = value & 0x0F,
+                        _ => {}
+                    }
+                }
+            }
+            // BNROM's latch has no write enable, so the ROM's byte is ANDed in
+            0x8000..=0xFFFF if !self.nina001 => self.prg_bank = value & self.read_prg(addr),
+            _ => {}
+        }
+    }
+
+    fn read_chr(&mut self, addr: u16) -> u8 {
+        self.chr.read(self.chr_offset(addr))
+    }
+
+    fn write_chr(&mut self, addr: u16, value: u8) {
+        let offset = self.chr_offset(addr);
+        self.chr.write(offset, value);
+    }
+
+    fn mirroring(&self) -> Mirroring {
+        self.mirroring
+    }
+
+    fn prg_ram(&self) -> Option<&[u8]> {
+        Some(&self.prg_ram)
+    }
+
+    fn prg_ram_mut(&mut self) -> Option<&mut [u8]> {
+        Some(&mut self.prg_ram)
+    }
+
+    fn save_state(&self, state: &mut StateWriter) {
+        state.write_bytes(&self.prg_ram);
+        self.chr.save_state(state);
+        state.write_u8(self.prg_bank);
+        state.write_bytes(&self.chr_banks);
+    }
+
+    fn load_state(&mut self, state: &mut StateReader) -> Result<()> {
+        state.read_into(&mut self.prg_ram)?;
+        self.chr.load_state(state)?;
+        self.prg_bank = state.read_u8()?;
+        state.read_into(&mut self.chr_banks)?;
+        Ok(())
+    }
+}
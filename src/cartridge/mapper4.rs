@@ -1,10 +1,20 @@
 // MMC3 (Mapper 4) implementation
 // Used by many popular games like Super Mario Bros 2 & 3, Mega Man 3-6, etc.
+// The scanline counter is clocked by rises of PPU A12, which the PPU's pattern fetches produce
+// once per line when background and sprites use different pattern tables.
+// NES 2.0 submapper 1 is the MMC6 (StarTropics), submapper 4 the MMC3A with its older IRQ behavior.
+
+use super::{ChrMemory, Mapper, Mirroring};
+use crate::state::{StateReader, StateWriter};
+use std::io::Result;
 
 pub struct Mapper4 {
     prg_rom: Vec<u8>,
-    chr_rom: Vec<u8>,
-    prg_ram: [u8; 0x2000],
+    chr: ChrMemory,
+    prg_ram: Vec<u8>,
+    mmc6: bool,
+    // MMC3A only raises the IRQ when the counter is decremented or explicitly reloaded to 0
+    revision_a: bool,
     
     // Bank registers
     bank_select: u8,
@@ -12,6 +22,8 @@ pub struct Mapper4 {
     
     // PRG RAM protect
     prg_ram_protect: bool,
+    // MMC6: $A001 bits 4-7 enable writing and reading each 512-byte half of its internal RAM
+    mmc6_ram_control: u8,
     
     // Mirroring
     mirroring: u8,
@@ -21,7 +33,11 @@ pub struct Mapper4 {
     irq_counter: u8,
     irq_latch: u8,
     irq_reload: bool,
-    pub irq_pending: bool,
+    irq_pending: bool,
+    // A rise of A12 only clocks the counter after it has been low for a few CPU cycles,
+    // which hides the short dips between sprite fetches
+    a12: bool,
+    a12_low_cycles: u8,
     
     // Current PRG banks
     prg_banks: [usize; 4],
@@ -31,97 +47,182 @@ pub struct Mapper4 {
 }
 
 impl Mapper4 {
-    pub fn new(prg_rom: Vec<u8>, chr_rom: Vec<u8>) -> Self {
+    pub fn new(submapper: u8, prg_rom: Vec<u8>, chr: ChrMemory) -> Self {
+        let mmc6 = submapper == 1;
         let prg_banks = [
             0,
             0x2000,
-            prg_rom.len() - 0x4000,
-            prg_rom.len() - 0x2000,
+            prg_rom.len().saturating_sub(0x4000),
+            prg_rom.len().saturating_sub(0x2000),
         ];
         
         let chr_banks = [0; 8];
         
         Self {
             prg_rom,
-            chr_rom,
-            prg_ram: [0; 0x2000],
+            chr,
+            prg_ram: vec![0; if mmc6 { 0x400 } else { 0x2000 }],
+            mmc6,
+            revision_a: submapper == 4,
             bank_select: 0,
             bank_data: [0; 8],
             prg_ram_protect: false,
+            mmc6_ram_control: 0,
             mirroring: 0,
             irq_enabled: false,
             irq_counter: 0,
             irq_latch: 0,
             irq_reload: false,
             irq_pending: false,
+            a12: false,
+            a12_low_cycles: 0,
             prg_banks,
             chr_banks,
         }
     }
     
-    pub fn read_prg(&self, addr: u16) -> u8 {
-        match addr {
-            0x0000..=0x1FFF => {
-                if self.prg_ram_protect {
-                    self.prg_ram[(addr & 0x1FFF) as usize]
-                } else {
-                    0
-                }
-            }
-            0x2000..=0x3FFF => {
-                self.prg_rom[self.prg_banks[0] + (addr as usize & 0x1FFF)]
+    /// MMC6: offset into the internal RAM at $7000-$7FFF and whether its half may be read and
+    /// written. The whole RAM is disabled unless $8000 bit 5 is set.
+    fn mmc6_ram_access(&self, addr: u16) -> Option<(usize, bool, bool)> {
+        if addr < 0x7000 || self.bank_select & 0x20 == 0 {
+            return None;
+        }
+        let offset = (addr & 0x03FF) as usize;
+        let shift = if offset < 0x200 { 4 } else { 6 };
+        let write = self.mmc6_ram_control >> shift & 0x01 != 0;
+        let read = self.mmc6_ram_control >> (shift + 1) & 0x01 != 0;
+        Some((offset, read, write))
+    }
+
+    fn clock_irq_counter(&mut self) {
+        let reloading = self.irq_counter == 0 || self.irq_reload;
+        let explicit_reload = self.irq_reload;
+        if reloading {
+            self.irq_counter = self.irq_latch;
+            self.irq_reload = false;
+        } else {
+            self.irq_counter -= 1;
+        }
+
+        // MMC3A stays quiet when a zero latch reloads itself every clock
+        let fire = self.irq_counter == 0 && (!self.revision_a || !reloading || explicit_reload);
+        if fire && self.irq_enabled {
+            self.irq_pending = true;
+        }
+    }
+
+    fn update_banks(&mut self) {
+        let prg_mode = (self.bank_select >> 6) & 0x01;
+        let chr_mode = (self.bank_select >> 7) & 0x01;
+        
+        // Update PRG banks
+        if prg_mode == 0 {
+            self.prg_banks[0] = (self.bank_data[6] as usize) * 0x2000 % self.prg_rom.len();
+            self.prg_banks[1] = (self.bank_data[7] as usize) * 0x2000 % self.prg_rom.len();
+            self.prg_banks[2] = self.prg_rom.len().saturating_sub(0x4000);
+            self.prg_banks[3] = self.prg_rom.len().saturating_sub(0x2000);
+        } else {
+            self.prg_banks[0] = self.prg_rom.len().saturating_sub(0x4000);
+            self.prg_banks[1] = (self.bank_data[7] as usize) * 0x2000 % self.prg_rom.len();
+            self.prg_banks[2] = (self.bank_data[6] as usize) * 0x2000 % self.prg_rom.len();
+            self.prg_banks[3] = self.prg_rom.len().saturating_sub(0x2000);
+        }
+        
+        // Update CHR banks
+        if !self.chr.is_empty() {
+            if chr_mode == 0 {
+                self.chr_banks[0] = ((self.bank_data[0] & 0xFE) as usize) * 0x400 % self.chr.len();
+                self.chr_banks[1] = ((self.bank_data[0] | 0x01) as usize) * 0x400 % self.chr.len();
+                self.chr_banks[2] = ((self.bank_data[1] & 0xFE) as usize) * 0x400 % self.chr.len();
+                self.chr_banks[3] = ((self.bank_data[1] | 0x01) as usize) * 0x400 % self.chr.len();
+                self.chr_banks[4] = (self.bank_data[2] as usize) * 0x400 % self.chr.len();
+                self.chr_banks[5] = (self.bank_data[3] as usize) * 0x400 % self.chr.len();
+                self.chr_banks[6] = (self.bank_data[4] as usize) * 0x400 % self.chr.len();
+                self.chr_banks[7] = (self.bank_data[5] as usize) * 0x400 % self.chr.len();
+            } else {
+                self.chr_banks[0] = (self.bank_data[2] as usize) * 0x400 % self.chr.len();
+                self.chr_banks[1] = (self.bank_data[3] as usize) * 0x400 % self.chr.len();
+                self.chr_banks[2] = (self.bank_data[4] as usize) * 0x400 % self.chr.len();
+                self.chr_banks[3] = (self.bank_data[5] as usize) * 0x400 % self.chr.len();
+                self.chr_banks[4] = ((self.bank_data[0] & 0xFE) as usize) * 0x400 % self.chr.len();
+                self.chr_banks[5] = ((self.bank_data[0] | 0x01) as usize) * 0x400 % self.chr.len();
+                self.chr_banks[6] = ((self.bank_data[1] & 0xFE) as usize) * 0x400 % self.chr.len();
+                self.chr_banks[7] = ((self.bank_data[1] | 0x01) as usize) * 0x400 % self.chr.len();
             }
-            0x4000..=0x5FFF => {
-                self.prg_rom[self.prg_banks[1] + (addr as usize & 0x1FFF)]
+        }
+    }
+}
+
+impl Mapper for Mapper4 {
+    fn read_prg(&mut self, addr: u16) -> u8 {
+        match addr {
+            0x6000..=0x7FFF if self.mmc6 => match self.mmc6_ram_access(addr) {
+                Some((offset, true, _)) => self.prg_ram[offset],
+                // One half readable: the other half reads back as 0; neither is open bus
+                _ => 0,
+            },
+            0x6000..=0x7FFF if self.prg_ram_protect => {
+                self.prg_ram[(addr & 0x1FFF) as usize]
             }
-            0x6000..=0x7FFF => {
-                self.prg_rom[self.prg_banks[2] + (addr as usize & 0x1FFF)]
+            0x8000..=0xFFFF => {
+                let slot = ((addr - 0x8000) / 0x2000) as usize;
+                // Images smaller than 8KB repeat in each window
+                self.prg_rom[(self.prg_banks[slot] + (addr as usize & 0x1FFF)) % self.prg_rom.len()]
             }
             _ => 0,
         }
     }
     
-    pub fn write_prg(&mut self, addr: u16, value: u8) {
+    fn write_prg(&mut self, addr: u16, value: u8) {
         match addr {
-            0x0000..=0x1FFF => {
-                if self.prg_ram_protect {
-                    self.prg_ram[(addr & 0x1FFF) as usize] = value;
+            0x6000..=0x7FFF if self.mmc6 => {
+                if let Some((offset, true, true)) = self.mmc6_ram_access(addr) {
+                    self.prg_ram[offset] = value;
                 }
             }
-            0x2000..=0x3FFF if addr & 0x01 == 0 => {
+            0x6000..=0x7FFF if self.prg_ram_protect => {
+                self.prg_ram[(addr & 0x1FFF) as usize] = value;
+            }
+            0x8000..=0x9FFF if addr & 0x01 == 0 => {
                 // Bank select ($8000-$9FFE, even)
                 self.bank_select = value;
                 self.update_banks();
             }
-            0x2000..=0x3FFF => {
+            0x8000..=0x9FFF => {
                 // Bank data ($8001-$9FFF, odd)
                 let bank = self.bank_select & 0x07;
                 self.bank_data[bank as usize] = value;
                 self.update_banks();
             }
-            0x4000..=0x5FFF if addr & 0x01 == 0 => {
+            0xA000..=0xBFFF if addr & 0x01 == 0 => {
                 // Mirroring ($A000-$BFFE, even)
                 self.mirroring = value & 0x01;
             }
-            0x4000..=0x5FFF => {
+            0xA000..=0xBFFF if self.mmc6 && self.bank_select & 0x20 != 0 => {
+                // MMC6 RAM half enables
+                self.mmc6_ram_control = value & 0xF0;
+            }
+            // Ignored while the MMC6 RAM is disabled
+            0xA000..=0xBFFF if self.mmc6 => {}
+            0xA000..=0xBFFF => {
                 // PRG RAM protect ($A001-$BFFF, odd)
                 self.prg_ram_protect = (value & 0x80) != 0;
             }
-            0x6000..=0x7FFF if addr & 0x01 == 0 => {
+            0xC000..=0xDFFF if addr & 0x01 == 0 => {
                 // IRQ latch ($C000-$DFFE, even)
                 self.irq_latch = value;
             }
-            0x6000..=0x7FFF => {
+            0xC000..=0xDFFF => {
                 // IRQ reload ($C001-$DFFF, odd)
                 self.irq_reload = true;
                 self.irq_counter = 0;
             }
-            0x8000..=0x9FFF if addr & 0x01 == 0 => {
+            0xE000..=0xFFFF if addr & 0x01 == 0 => {
                 // IRQ disable ($E000-$FFFE, even)
                 self.irq_enabled = false;
                 self.irq_pending = false;
             }
-            0x8000..=0x9FFF => {
+            0xE000..=0xFFFF => {
                 // IRQ enable ($E001-$FFFF, odd)
                 self.irq_enabled = true;
             }
@@ -129,82 +230,88 @@ impl Mapper4 {
         }
     }
     
-    pub fn read_chr(&self, addr: u16) -> u8 {
-        if self.chr_rom.is_empty() {
-            return 0; // CHR RAM
-        }
-        
+    fn read_chr(&mut self, addr: u16) -> u8 {
         let bank = (addr / 0x400) as usize;
         let offset = (addr & 0x3FF) as usize;
-        let bank_addr = self.chr_banks[bank] + offset;
-        
-        if bank_addr < self.chr_rom.len() {
-            self.chr_rom[bank_addr]
-        } else {
-            0
-        }
+        self.chr.read(self.chr_banks[bank] + offset)
     }
     
-    pub fn write_chr(&mut self, _addr: u16, _value: u8) {
-        // CHR ROM is read-only, but some games have CHR RAM
-        // TODO: Implement CHR RAM support
+    fn write_chr(&mut self, addr: u16, value: u8) {
+        let bank = (addr / 0x400) as usize;
+        let offset = (addr & 0x3FF) as usize;
+        self.chr.write(self.chr_banks[bank] + offset, value);
     }
     
-    pub fn clock_scanline(&mut self) {
-        if self.irq_counter == 0 || self.irq_reload {
-            self.irq_counter = self.irq_latch;
-            self.irq_reload = false;
+    fn mirroring(&self) -> Mirroring {
+        if self.mirroring == 0 {
+            Mirroring::Vertical
         } else {
-            self.irq_counter -= 1;
-        }
-        
-        if self.irq_counter == 0 && self.irq_enabled {
-            self.irq_pending = true;
+            Mirroring::Horizontal
         }
     }
     
-    fn update_banks(&mut self) {
-        let prg_mode = (self.bank_select >> 6) & 0x01;
-        let chr_mode = (self.bank_select >> 7) & 0x01;
-        
-        // Update PRG banks
-        if prg_mode == 0 {
-            self.prg_banks[0] = (self.bank_data[6] as usize) * 0x2000 % self.prg_rom.len();
-            self.prg_banks[1] = (self.bank_data[7] as usize) * 0x2000 % self.prg_rom.len();
-            self.prg_banks[2] = self.prg_rom.len() - 0x4000;
-            self.prg_banks[3] = self.prg_rom.len() - 0x2000;
-        } else {
-            self.prg_banks[0] = self.prg_rom.len() - 0x4000;
-            self.prg_banks[1] = (self.bank_data[7] as usize) * 0x2000 % self.prg_rom.len();
-            self.prg_banks[2] = (self.bank_data[6] as usize) * 0x2000 % self.prg_rom.len();
-            self.prg_banks[3] = self.prg_rom.len() - 0x2000;
-        }
-        
-        // Update CHR banks
-        if !self.chr_rom.is_empty() {
-            if chr_mode == 0 {
-                self.chr_banks[0] = ((self.bank_data[0] & 0xFE) as usize) * 0x400 % self.chr_rom.len();
-                self.chr_banks[1] = ((self.bank_data[0] | 0x01) as usize) * 0x400 % self.chr_rom.len();
-                self.chr_banks[2] = ((self.bank_data[1] & 0xFE) as usize) * 0x400 % self.chr_rom.len();
-                self.chr_banks[3] = ((self.bank_data[1] | 0x01) as usize) * 0x400 % self.chr_rom.len();
-                self.chr_banks[4] = (self.bank_data[2] as usize) * 0x400 % self.chr_rom.len();
-                self.chr_banks[5] = (self.bank_data[3] as usize) * 0x400 % self.chr_rom.len();
-                self.chr_banks[6] = (self.bank_data[4] as usize) * 0x400 % self.chr_rom.len();
-                self.chr_banks[7] = (self.bank_data[5] as usize) * 0x400 % self.chr_rom.len();
-            } else {
-                self.chr_banks[0] = (self.bank_data[2] as usize) * 0x400 % self.chr_rom.len();
-                self.chr_banks[1] = (self.bank_data[3] as usize) * 0x400 % self.chr_rom.len();
-                self.chr_banks[2] = (self.bank_data[4] as usize) * 0x400 % self.chr_rom.len();
-                self.chr_banks[3] = (self.bank_data[5] as usize) * 0x400 % self.chr_rom.len();
-                self.chr_banks[4] = ((self.bank_data[0] & 0xFE) as usize) * 0x400 % self.chr_rom.len();
-                self.chr_banks[5] = ((self.bank_data[0] | 0x01) as usize) * 0x400 % self.chr_rom.len();
-                self.chr_banks[6] = ((self.bank_data[1] & 0xFE) as usize) * 0x400 % self.chr_rom.len();
-                self.chr_banks[7] = ((self.bank_data[1] | 0x01) as usize) * 0x400 % self.chr_rom.len();
+    fn irq_pending(&self) -> bool {
+        self.irq_pending
+    }
+    
+    fn notify_ppu_addr(&mut self, addr: u16) {
+        let a12 = addr & 0x1000 != 0;
+        if a12 {
+            if !self.a12 && self.a12_low_cycles >= 3 {
+                self.clock_irq_counter();
             }
+            self.a12_low_cycles = 0;
         }
+        self.a12 = a12;
     }
-    
-    pub fn get_mirroring(&self) -> u8 {
-        self.mirroring
+
+    fn clock_cpu(&mut self) {
+        if !self.a12 {
+            self.a12_low_cycles = self.a12_low_cycles.saturating_add(1);
+        }
+    }
+
+    fn prg_ram(&self) -> Option<&[u8]> {
+        Some(&self.prg_ram)
+    }
+
+    fn prg_ram_mut(&mut self) -> Option<&mut [u8]> {
+        Some(&mut self.prg_ram)
     }
-}
\ No newline at end of file
+
+    fn save_state(&self, state: &mut StateWriter) {
+        state.write_bytes(&self.prg_ram);
+        self.chr.save_state(state);
+        state.write_u8(self.bank_select);
+        state.write_bytes(&self.bank_data);
+        state.write_bool(self.prg_ram_protect);
+        state.write_u8(self.mmc6_ram_control);
+        state.write_u8(self.mirroring);
+        state.write_bool(self.irq_enabled);
+        state.write_u8(self.irq_counter);
+        state.write_u8(self.irq_latch);
+        state.write_bool(self.irq_reload);
+        state.write_bool(self.irq_pending);
+        state.write_bool(self.a12);
+        state.write_u8(self.a12_low_cycles);
+    }
+
+    fn load_state(&mut self, state: &mut StateReader) -> Result<()> {
+        state.read_into(&mut self.prg_ram)?;
+        self.chr.load_state(state)?;
+        self.bank_select = state.read_u8()?;
+        state.read_into(&mut self.bank_data)?;
+        self.prg_ram_protect = state.read_bool()?;
+        self.mmc6_ram_control = state.read_u8()?;
+        self.mirroring = state.read_u8()?;
+        self.irq_enabled = state.read_bool()?;
+        self.irq_counter = state.read_u8()?;
+        self.irq_latch = state.read_u8()?;
+        self.irq_reload = state.read_bool()?;
+        self.irq_pending = state.read_bool()?;
+        self.a12 = state.read_bool()?;
+        self.a12_low_cycles = state.read_u8()?;
+        self.update_banks();
+        Ok(())
+    }
+}
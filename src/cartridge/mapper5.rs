@@ -1,185 +1,190 @@
+// MMC5 (Mapper 5) implementation
+// Used by Castlevania III, Just Breed, Uncharted Waters, etc.
+
+use super::{ChrMemory, Mapper, Mirroring};
+use crate::state::{StateReader, StateWriter};
+use std::io::Result;
 
 pub struct Mapper5 {
     prg_rom: Vec<u8>,
-    chr_rom: Vec<u8>,
-    
+    chr: ChrMemory,
+    prg_ram: Vec<u8>,
+
     // ExRAM - 1KB internal RAM for extended attributes and nametables
     exram: [u8; 0x400],
     exram_mode: u8,
-    
+
     // PRG banking
     prg_mode: u8,
-    prg_banks: [u8; 5],  // Up to 5 banks depending on mode
+    prg_banks: [u8; 5],  // $5113-$5117, bit 7 selects ROM over RAM
     prg_ram_protect: [u8; 2],
-    
+
     // CHR banking
     chr_mode: u8,
-    chr_banks: [u16; 12],  // Up to 12 banks depending on mode
+    chr_banks: [u16; 12],  // $5120-$5127 (set A) and $5128-$512B (set B)
     upper_chr_bank_bits: u8,
-    
+    last_chr_set_b: bool,
+
     // Nametable control
     nametable_mapping: [u8; 4],
     fill_mode_tile: u8,
     fill_mode_attr: u8,
-    
+
     // Split screen control
-    vsplit_enabled: bool,
-    vsplit_side: bool,  // false = left, true = right
-    vsplit_tile: u8,
-    vsplit_scroll: u8,
-    vsplit_bank: u8,
-    in_split_area: bool,
-    
+    _vsplit_enabled: bool,
+    _vsplit_side: bool,  // false = left, true = right
+    _vsplit_tile: u8,
+    _vsplit_scroll: u8,
+    _vsplit_bank: u8,
+
     // IRQ control
     irq_scanline: u8,
     irq_enabled: bool,
     irq_pending: bool,
     irq_in_frame: bool,
     scanline_counter: u8,
-    
-    // PPU monitoring
-    ppu_is_rendering: bool,
-    last_ppu_read: u16,
-    consecutive_nametable_reads: u8,
-    
+
     // Multiplication unit
     multiplicand_a: u8,
     multiplicand_b: u8,
 }
 
 impl Mapper5 {
-    pub fn new(prg_rom: Vec<u8>, chr_rom: Vec<u8>) -> Self {
-        let mut mapper = Mapper5 {
+    pub fn new(prg_rom: Vec<u8>, chr: ChrMemory) -> Self {
+        Mapper5 {
             prg_rom,
-            chr_rom,
+            chr,
+            // The largest MMC5 boards carry 64KB of PRG RAM
+            prg_ram: vec![0; 0x10000],
             exram: [0; 0x400],
             exram_mode: 0,
-            
+
             prg_mode: 3,  // Default to mode 3
-            prg_banks: [0xFF, 0xFF, 0xFF, 0xFF, 0xFF],  // Default to last bank
+            prg_banks: [0, 0xFF, 0xFF, 0xFF, 0xFF],  // Default to last bank
             prg_ram_protect: [0; 2],
-            
+
             chr_mode: 0,
             chr_banks: [0; 12],
             upper_chr_bank_bits: 0,
-            
+            last_chr_set_b: false,
+
             nametable_mapping: [0; 4],
             fill_mode_tile: 0,
             fill_mode_attr: 0,
-            
-            vsplit_enabled: false,
-            vsplit_side: false,
-            vsplit_tile: 0,
-            vsplit_scroll: 0,
-            vsplit_bank: 0,
-            in_split_area: false,
-            
+
+            _vsplit_enabled: false,
+            _vsplit_side: false,
+            _vsplit_tile: 0,
+            _vsplit_scroll: 0,
+            _vsplit_bank: 0,
+
             irq_scanline: 0,
             irq_enabled: false,
             irq_pending: false,
             irq_in_frame: false,
             scanline_counter: 0,
-            
-            ppu_is_rendering: false,
-            last_ppu_read: 0,
-            consecutive_nametable_reads: 0,
-            
+
             multiplicand_a: 0,
             multiplicand_b: 0,
-        };
-        
-        // Initialize PRG banks to point to last bank
-        let last_bank = (mapper.prg_rom.len() / 0x2000 - 1) as u8;
-        mapper.prg_banks[4] = last_bank;
-        
-        mapper
+        }
     }
-    
-    pub fn read_prg(&self, addr: u16) -> u8 {
+
+    /// Resolve a $8000-$FFFF address to an 8KB bank register value for the current PRG mode.
+    fn prg_bank_register(&self, addr: u16) -> u8 {
+        let slot = ((addr - 0x8000) / 0x2000) as u8;
         match self.prg_mode {
             0 => {
                 // Mode 0: 32KB switchable
-                let bank = (self.prg_banks[4] >> 2) as usize;
-                let offset = bank * 0x8000 + (addr as usize);
-                if offset < self.prg_rom.len() {
-                    self.prg_rom[offset]
-                } else {
-                    0
-                }
+                (self.prg_banks[4] & 0xFC) | slot
             }
             1 => {
                 // Mode 1: 16KB + 16KB
-                match addr {
-                    0x0000..=0x3FFF => {
-                        let bank = (self.prg_banks[2] >> 1) as usize;
-                        let offset = bank * 0x4000 + (addr as usize);
-                        if offset < self.prg_rom.len() {
-                            self.prg_rom[offset]
-                        } else {
-                            0
-                        }
-                    }
-                    0x4000..=0x7FFF => {
-                        let bank = (self.prg_banks[4] >> 1) as usize;
-                        let offset = bank * 0x4000 + ((addr - 0x4000) as usize);
-                        if offset < self.prg_rom.len() {
-                            self.prg_rom[offset]
-                        } else {
-                            0
-                        }
-                    }
-                    _ => 0
+                if addr < 0xC000 {
+                    (self.prg_banks[2] & 0xFE) | (slot & 1)
+                } else {
+                    (self.prg_banks[4] & 0xFE) | (slot & 1)
                 }
             }
             2 => {
                 // Mode 2: 16KB + 8KB + 8KB
                 match addr {
-                    0x0000..=0x3FFF => {
-                        let bank = (self.prg_banks[2] >> 1) as usize;
-                        let offset = bank * 0x4000 + (addr as usize);
-                        if offset < self.prg_rom.len() {
-                            self.prg_rom[offset]
-                        } else {
-                            0
-                        }
-                    }
-                    0x4000..=0x5FFF => {
-                        let bank = self.prg_banks[3] as usize;
-                        let offset = bank * 0x2000 + ((addr - 0x4000) as usize);
-                        if offset < self.prg_rom.len() {
-                            self.prg_rom[offset]
-                        } else {
-                            0
-                        }
-                    }
-                    0x6000..=0x7FFF => {
-                        let bank = self.prg_banks[4] as usize;
-                        let offset = bank * 0x2000 + ((addr - 0x6000) as usize);
-                        if offset < self.prg_rom.len() {
-                            self.prg_rom[offset]
-                        } else {
-                            0
-                        }
-                    }
-                    _ => 0
+                    0x8000..=0xBFFF => (self.prg_banks[2] & 0xFE) | (slot & 1),
+                    0xC000..=0xDFFF => self.prg_banks[3],
+                    _ => self.prg_banks[4],
                 }
             }
-            3 => {
+            _ => {
                 // Mode 3: 8KB + 8KB + 8KB + 8KB
-                let bank_index = (addr / 0x2000) as usize;
-                let bank = self.prg_banks[bank_index + 1] as usize;
-                let offset = bank * 0x2000 + ((addr & 0x1FFF) as usize);
-                if offset < self.prg_rom.len() {
-                    self.prg_rom[offset]
+                self.prg_banks[slot as usize + 1]
+            }
+        }
+    }
+
+    /// Translate a PPU pattern address using the CHR mode and the most recently written bank set.
+    fn chr_offset(&self, addr: u16) -> usize {
+        let addr = addr & 0x1FFF;
+
+        // Set B only covers 4KB and is mirrored into both pattern tables
+        let (bank_index, bank_size): (usize, usize) = match (self.chr_mode, self.last_chr_set_b) {
+            (0, false) => (7, 0x2000),
+            (1, false) => (3 + (addr / 0x1000) as usize * 4, 0x1000),
+            (2, false) => (1 + (addr / 0x800) as usize * 2, 0x800),
+            (_, false) => ((addr / 0x400) as usize, 0x400),
+            (0, true) => (11, 0x2000),
+            (1, true) => (11, 0x1000),
+            (2, true) => (9 + ((addr / 0x800) % 2) as usize * 2, 0x800),
+            (_, true) => (8 + ((addr / 0x400) % 4) as usize, 0x400),
+        };
+
+        let bank = self.chr_banks[bank_index] as usize;
+        (bank * bank_size) + ((addr as usize) & (bank_size - 1))
+    }
+
+    fn prg_ram_writable(&self) -> bool {
+        self.prg_ram_protect[0] == 0x02 && self.prg_ram_protect[1] == 0x01
+    }
+
+    fn prg_ram_offset(&self, bank: u8, addr: u16) -> usize {
+        ((bank & 0x07) as usize * 0x2000 + (addr & 0x1FFF) as usize) % self.prg_ram.len()
+    }
+
+    pub fn get_multiplication_result(&self) -> u16 {
+        (self.multiplicand_a as u16) * (self.multiplicand_b as u16)
+    }
+}
+
+impl Mapper for Mapper5 {
+    fn read_prg(&mut self, addr: u16) -> u8 {
+        match addr {
+            0x5204 => {
+                // IRQ status, reading acknowledges the pending IRQ
+                let status = if self.irq_pending { 0x80 } else { 0 }
+                    | if self.irq_in_frame { 0x40 } else { 0 };
+                self.irq_pending = false;
+                status
+            }
+            0x5205 => self.get_multiplication_result() as u8,
+            0x5206 => (self.get_multiplication_result() >> 8) as u8,
+            0x5C00..=0x5FFF if self.exram_mode >= 2 => {
+                // ExRAM is only readable by the CPU in modes 2 and 3
+                self.exram[(addr - 0x5C00) as usize]
+            }
+            0x6000..=0x7FFF => self.prg_ram[self.prg_ram_offset(self.prg_banks[0], addr)],
+            0x8000..=0xFFFF => {
+                let bank = self.prg_bank_register(addr);
+                // $E000-$FFFF is always ROM
+                if bank & 0x80 != 0 || addr >= 0xE000 {
+                    let offset = (bank & 0x7F) as usize * 0x2000 + (addr & 0x1FFF) as usize;
+                    self.prg_rom[offset % self.prg_rom.len()]
                 } else {
-                    0
+                    self.prg_ram[self.prg_ram_offset(bank, addr)]
                 }
             }
-            _ => 0
+            _ => 0,
         }
     }
-    
-    pub fn write_prg(&mut self, addr: u16, value: u8) {
+
+    fn write_prg(&mut self, addr: u16, value: u8) {
         match addr {
             0x5000..=0x5015 => {
                 // Audio registers (not implemented)
@@ -216,8 +221,8 @@ impl Mapper5 {
                 self.fill_mode_tile = value;
             }
             0x5107 => {
-                // Fill mode attribute
-                self.fill_mode_attr = value & 0x03;
+                // Fill mode attribute, replicated into all four quadrants
+                self.fill_mode_attr = (value & 0x03) * 0x55;
             }
             0x5113..=0x5117 => {
                 // PRG banks
@@ -228,6 +233,7 @@ impl Mapper5 {
                 // CHR banks
                 let bank_index = (addr - 0x5120) as usize;
                 self.chr_banks[bank_index] = value as u16 | ((self.upper_chr_bank_bits as u16) << 8);
+                self.last_chr_set_b = bank_index >= 8;
             }
             0x5130 => {
                 // Upper CHR bank bits
@@ -235,17 +241,17 @@ impl Mapper5 {
             }
             0x5200 => {
                 // Vertical split mode
-                self.vsplit_enabled = (value & 0x80) != 0;
-                self.vsplit_side = (value & 0x40) != 0;
-                self.vsplit_tile = value & 0x1F;
+                self._vsplit_enabled = (value & 0x80) != 0;
+                self._vsplit_side = (value & 0x40) != 0;
+                self._vsplit_tile = value & 0x1F;
             }
             0x5201 => {
                 // Vertical split scroll
-                self.vsplit_scroll = value;
+                self._vsplit_scroll = value;
             }
             0x5202 => {
                 // Vertical split bank
-                self.vsplit_bank = value;
+                self._vsplit_bank = value;
             }
             0x5203 => {
                 // IRQ scanline
@@ -254,9 +260,6 @@ impl Mapper5 {
             0x5204 => {
                 // IRQ enable
                 self.irq_enabled = (value & 0x80) != 0;
-                if self.irq_enabled && self.irq_pending {
-                    // IRQ should fire
-                }
             }
             0x5205 => {
                 // Multiplicand A
@@ -266,114 +269,161 @@ impl Mapper5 {
                 // Multiplicand B
                 self.multiplicand_b = value;
             }
-            0x5C00..=0x5FFF => {
-                // ExRAM write
-                if self.exram_mode < 3 {  // Mode 3 is read-only
-                    self.exram[(addr - 0x5C00) as usize] = value;
+            0x5C00..=0x5FFF if self.exram_mode < 3 => {
+                // ExRAM write (mode 3 is read-only)
+                self.exram[(addr - 0x5C00) as usize] = value;
+            }
+            0x6000..=0x7FFF if self.prg_ram_writable() => {
+                let offset = self.prg_ram_offset(self.prg_banks[0], addr);
+                self.prg_ram[offset] = value;
+            }
+            0x8000..=0xDFFF => {
+                let bank = self.prg_bank_register(addr);
+                if bank & 0x80 == 0 && self.prg_ram_writable() {
+                    let offset = self.prg_ram_offset(bank, addr);
+                    self.prg_ram[offset] = value;
                 }
             }
             _ => {}
         }
     }
-    
-    pub fn read_chr(&self, addr: u16) -> u8 {
-        // Handle CHR banking based on mode
-        let bank_index = match self.chr_mode {
-            0 => {
-                // 8KB mode
-                (addr / 0x2000) as usize * 8
-            }
-            1 => {
-                // 4KB mode
-                (addr / 0x1000) as usize * 4
-            }
-            2 => {
-                // 2KB mode
-                (addr / 0x800) as usize * 2
-            }
-            3 => {
-                // 1KB mode
-                (addr / 0x400) as usize
-            }
-            _ => 0
-        };
-        
-        let bank_size: usize = match self.chr_mode {
-            0 => 0x2000,
-            1 => 0x1000,
-            2 => 0x800,
-            3 => 0x400,
-            _ => 0x2000
-        };
-        
-        let bank = self.chr_banks[bank_index] as usize;
-        let offset = (bank * bank_size) + ((addr as usize) & (bank_size - 1));
-        
-        if offset < self.chr_rom.len() {
-            self.chr_rom[offset]
-        } else {
-            0
+
+    fn read_chr(&mut self, addr: u16) -> u8 {
+        let offset = self.chr_offset(addr);
+        self.chr.read(offset)
+    }
+
+    fn write_chr(&mut self, addr: u16, value: u8) {
+        let offset = self.chr_offset(addr);
+        self.chr.write(offset, value);
+    }
+
+    fn read_nametable(&mut self, addr: u16, ciram: &[u8; 0x800]) -> u8 {
+        let offset = (addr & 0x3FF) as usize;
+        match self.nametable_mapping[((addr >> 10) & 0x03) as usize] {
+            page @ (0 | 1) => ciram[page as usize * 0x400 + offset],
+            // ExRAM only works as a nametable in modes 0 and 1
+            2 if self.exram_mode < 2 => self.exram[offset],
+            2 => 0,
+            // Fill mode
+            _ if offset < 0x3C0 => self.fill_mode_tile,
+            _ => self.fill_mode_attr,
         }
     }
-    
-    pub fn read_nametable(&self, addr: u16) -> u8 {
-        // Handle special nametable modes
-        let table = ((addr - 0x2000) / 0x400) as usize;
-        match self.nametable_mapping[table] {
-            0 | 1 => {
-                // Use internal VRAM (handled by PPU)
-                0
-            }
-            2 => {
-                // Use ExRAM as nametable
-                if self.exram_mode == 0 || self.exram_mode == 1 {
-                    self.exram[(addr & 0x3FF) as usize]
-                } else {
-                    0
-                }
-            }
-            3 => {
-                // Fill mode
-                if (addr & 0x3FF) < 0x3C0 {
-                    // Tile data
-                    self.fill_mode_tile
-                } else {
-                    // Attribute data
-                    self.fill_mode_attr
-                }
-            }
-            _ => 0
+
+    fn write_nametable(&mut self, addr: u16, value: u8, ciram: &mut [u8; 0x800]) {
+        let offset = (addr & 0x3FF) as usize;
+        match self.nametable_mapping[((addr >> 10) & 0x03) as usize] {
+            page @ (0 | 1) => ciram[page as usize * 0x400 + offset] = value,
+            2 if self.exram_mode < 2 => self.exram[offset] = value,
+            _ => {}
         }
     }
-    
-    pub fn get_multiplication_result(&self) -> u16 {
-        (self.multiplicand_a as u16) * (self.multiplicand_b as u16)
+
+    fn mirroring(&self) -> Mirroring {
+        // Only for reporting: nametable accesses follow $5105 exactly through read_nametable
+        match self.nametable_mapping {
+            [0, 1, 0, 1] => Mirroring::Vertical,
+            [0, 0, 1, 1] => Mirroring::Horizontal,
+            [1, 1, 1, 1] => Mirroring::SingleScreenUpper,
+            [0, 0, 0, 0] => Mirroring::SingleScreenLower,
+            _ => Mirroring::Vertical,
+        }
     }
-    
-    pub fn clock_scanline(&mut self) {
-        if self.ppu_is_rendering {
-            if self.scanline_counter == self.irq_scanline {
-                if self.irq_enabled {
-                    self.irq_pending = true;
-                }
-            }
-            self.scanline_counter = self.scanline_counter.wrapping_add(1);
+
+    fn irq_pending(&self) -> bool {
+        self.irq_pending && self.irq_enabled
+    }
+
+    fn clock_scanline(&mut self) {
+        // The first clock of a frame marks the start of rendering
+        if !self.irq_in_frame {
+            self.irq_in_frame = true;
+            self.scanline_counter = 0;
+            return;
+        }
+
+        self.scanline_counter = self.scanline_counter.wrapping_add(1);
+        if self.scanline_counter == self.irq_scanline {
+            self.irq_pending = true;
         }
     }
-    
-    pub fn notify_ppu_state(&mut self, rendering: bool) {
-        self.ppu_is_rendering = rendering;
+
+    fn notify_ppu_state(&mut self, rendering: bool) {
         if !rendering {
             self.scanline_counter = 0;
             self.irq_in_frame = false;
         }
     }
-    
-    pub fn get_irq_pending(&self) -> bool {
-        self.irq_pending
+
+    fn prg_ram(&self) -> Option<&[u8]> {
+        Some(&self.prg_ram)
     }
-    
-    pub fn clear_irq(&mut self) {
-        self.irq_pending = false;
+
+    fn prg_ram_mut(&mut self) -> Option<&mut [u8]> {
+        Some(&mut self.prg_ram)
     }
-}
\ No newline at end of file
+
+    fn save_state(&self, state: &mut StateWriter) {
+        state.write_bytes(&self.prg_ram);
+        self.chr.save_state(state);
+        state.write_bytes(&self.exram);
+        state.write_u8(self.exram_mode);
+        state.write_u8(self.prg_mode);
+        state.write_bytes(&self.prg_banks);
+        state.write_bytes(&self.prg_ram_protect);
+        state.write_u8(self.chr_mode);
+        for &bank in &self.chr_banks {
+            state.write_u16(bank);
+        }
+        state.write_u8(self.upper_chr_bank_bits);
+        state.write_bool(self.last_chr_set_b);
+        state.write_bytes(&self.nametable_mapping);
+        state.write_u8(self.fill_mode_tile);
+        state.write_u8(self.fill_mode_attr);
+        state.write_bool(self._vsplit_enabled);
+        state.write_bool(self._vsplit_side);
+        state.write_u8(self._vsplit_tile);
+        state.write_u8(self._vsplit_scroll);
+        state.write_u8(self._vsplit_bank);
+        state.write_u8(self.irq_scanline);
+        state.write_bool(self.irq_enabled);
+        state.write_bool(self.irq_pending);
+        state.write_bool(self.irq_in_frame);
+        state.write_u8(self.scanline_counter);
+        state.write_u8(self.multiplicand_a);
+        state.write_u8(self.multiplicand_b);
+    }
+
+    fn load_state(&mut self, state: &mut StateReader) -> Result<()> {
+        state.read_into(&mut self.prg_ram)?;
+        self.chr.load_state(state)?;
+        state.read_into(&mut self.exram)?;
+        self.exram_mode = state.read_u8()?;
+        self.prg_mode = state.read_u8()?;
+        state.read_into(&mut self.prg_banks)?;
+        state.read_into(&mut self.prg_ram_protect)?;
+        self.chr_mode = state.read_u8()?;
+        for bank in &mut self.chr_banks {
+            *bank = state.read_u16()?;
+        }
+        self.upper_chr_bank_bits = state.read_u8()?;
+        self.last_chr_set_b = state.read_bool()?;
+        state.read_into(&mut self.nametable_mapping)?;
+        self.fill_mode_tile = state.read_u8()?;
+        self.fill_mode_attr = state.read_u8()?;
+        self._vsplit_enabled = state.read_bool()?;
+        self._vsplit_side = state.read_bool()?;
+        self._vsplit_tile = state.read_u8()?;
+        self._vsplit_scroll = state.read_u8()?;
+        self._vsplit_bank = state.read_u8()?;
+        self.irq_scanline = state.read_u8()?;
+        self.irq_enabled = state.read_bool()?;
+        self.irq_pending = state.read_bool()?;
+        self.irq_in_frame = state.read_bool()?;
+        self.scanline_counter = state.read_u8()?;
+        self.multiplicand_a = state.read_u8()?;
+        self.multiplicand_b = state.read_u8()?;
+        Ok(())
+    }
+}
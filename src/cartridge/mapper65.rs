@@ -0,0 +1,172 @@
+// Irem H3001 (Mapper 65) implementation
+// Used by Daiku no Gen San 2, Spartan X 2, Kaiketsu Yanchamaru 3
+// Three 8KB PRG registers, where $9000 can swap the $8000 and $C000 windows, and a fixed last
+// bank. Its IRQ is a 16-bit counter that counts down once per CPU cycle.
+
+use super::{ChrMemory, Mapper, Mirroring};
+use crate::state::{StateReader, StateWriter};
+use std::io::Result;
+
+pub struct Mapper65 {
+    prg_rom: Vec<u8>,
+    chr: ChrMemory,
+    prg_ram: Vec<u8>,
+    mirroring: Mirroring,
+
+    // $8000, $A000 and $C000 registers; bank numbers wrap around the PRG ROM size
+    prg_banks: [u8; 3],
+    // $9000 bit 7: $8000 reg at $C000 and $C000 reg at $8000
+    prg_swap: bool,
+    chr_banks: [u8; 8],
+
+    irq_enabled: bool,
+    irq_counter: u16,
+    irq_reload: u16,
+    irq_pending: bool,
+}
+
+impl Mapper65 {
+    pub fn new(prg_rom: Vec<u8>, chr: ChrMemory, prg_ram_size: usize, mirroring: Mirroring) -> Self {
+        Mapper65 {
+            prg_rom,
+            chr,
+            prg_ram: vec![0; prg_ram_size],
+            mirroring,
+            // $C000 powers on holding the second-to-last bank
+            prg_banks: [0, 1, 0xFE],
+            prg_swap: false,
+            chr_banks: [0, 1, 2, 3, 4, 5, 6, 7],
+            irq_enabled: false,
+            irq_counter: 0,
+            irq_reload: 0,
+            irq_pending: false,
+        }
+    }
+
+    /// Eight 1KB CHR banks selected by $B000-$B007.
+    fn chr_offset(&self, addr: u16) -> usize {
+        let bank = self.chr_banks[((addr & 0x1FFF) / 0x400) as usize] as usize;
+        bank * 0x400 + (addr & 0x3FF) as usize
+    }
+
+    fn read_prg_rom(&self, bank: usize, offset: u16) -> u8 {
+        self.prg_rom[(bank * 0x2000 + (offset & 0x1FFF) as usize) % self.prg_rom.len()]
+    }
+}
+
+impl Mapper for Mapper65 {
+    fn read_prg(&mut self, addr: u16) -> u8 {
+        match addr {
+            0x6000..=0x7FFF if !self.prg_ram.is_empty() => {
+                self.prg_ram[(addr as usize - 0x6000) % self.prg_ram.len()]
+            }
+            // Three switchable 8KB banks
+            0x8000..=0x9FFF if self.prg_swap => self.read_prg_rom(self.prg_banks[2] as usize, addr),
+            0x8000..=0x9FFF => self.read_prg_rom(self.prg_banks[0] as usize, addr),
+            0xA000..=0xBFFF => self.read_prg_rom(self.prg_banks[1] as usize, addr),
+            0xC000..=0xDFFF if self.prg_swap => self.read_prg_rom(self.prg_banks[0] as usize, addr),
+            0xC000..=0xDFFF => self.read_prg_rom(self.prg_banks[2] as usize, addr),
+            // Last bank: fixed to last 8KB
+            0xE000..=0xFFFF => {
+                let last_bank = (self.prg_rom.len() / 0x2000).saturating_sub(1);
+                self.read_prg_rom(last_bank, addr)
+            }
+            _ => 0,
+        }
+    }
+
+    fn write_prg(&mut self, addr: u16, value: u8) {
+        match addr {
+            0x6000..=0x7FFF if !self.prg_ram.is_empty() => {
+                let len = self.prg_ram.len();
+                self.prg_ram[(addr as usize - 0x6000) % len] = value;
+            }
+            0x8000 => self.prg_banks[0] = value,
+            0x9000 => self.prg_swap = value & 0x80 != 0,
+            0x9001 => {
+                self.mirroring = if value & 0x80 == 0 {
+                    Mirroring::Vertical
+                } else {
+                    Mirroring::Horizontal
+                };
+            }
+            // Changing the enable or reloading the counter acknowledges a pending IRQ
+            0x9003 => {
+                self.irq_enabled = value & 0x80 != 0;
+                self.irq_pending = false;
+            }
+            0x9004 => {
+                self.irq_counter = self.irq_reload;
+                self.irq_pending = false;
+            }
+            0x9005 => self.irq_reload = (self.irq_reload & 0x00FF) | (value as u16) << 8,
+            0x9006 => self.irq_reload = (self.irq_reload & 0xFF00) | value as u16,
+            0xA000 => self.prg_banks[1] = value,
+            0xC000 => self.prg_banks[2] = value,
+            0xB000..=0xB007 => self.chr_banks[(addr & 0x07) as usize] = value,
+            _ => {}
+        }
+    }
+
+    fn read_chr(&mut self, addr: u16) -> u8 {
+        self.chr.read(self.chr_offset(addr))
+    }
+
+    fn write_chr(&mut self, addr: u16, value: u8) {
+        let offset = self.chr_offset(addr);
+        self.chr.write(offset, value);
+    }
+
+    fn mirroring(&self) -> Mirroring {
+        self.mirroring
+    }
+
+    fn irq_pending(&self) -> bool {
+        self.irq_pending
+    }
+
+    fn clock_cpu(&mut self) {
+        // The counter stops at zero and stays there until reloaded
+        if self.irq_enabled && self.irq_counter > 0 {
+            self.irq_counter -= 1;
+            if self.irq_counter == 0 {
+                self.irq_pending = true;
+            }
+        }
+    }
+
+    fn prg_ram(&self) -> Option<&[u8]> {
+        Some(&self.prg_ram)
+    }
+
+    fn prg_ram_mut(&mut self) -> Option<&mut [u8]> {
+        Some(&mut self.prg_ram)
+    }
+
+    fn save_state(&self, state: &mut StateWriter) {
+        state.write_bytes(&self.prg_ram);
+        self.chr.save_state(state);
+        state.write_bytes(&self.prg_banks);
+        state.write_bool(self.prg_swap);
+        state.write_bytes(&self.chr_banks);
+        state.write_u8(self.mirroring as u8);
+        state.write_bool(self.irq_enabled);
+        state.write_u16(self.irq_counter);
+        state.write_u16(self.irq_reload);
+        state.write_bool(self.irq_pending);
+    }
+
+    fn load_state(&mut self, state: &mut StateReader) -> Result<()> {
+        state.read_into(&mut self.prg_ram)?;
+        self.chr.load_state(state)?;
+        state.read_into(&mut self.prg_banks)?;
+        self.prg_swap = state.read_bool()?;
+        state.read_into(&mut self.chr_banks)?;
+        self.mirroring = Mirroring::from_state(state.read_u8()?)?;
+        self.irq_enabled = state.read_bool()?;
+        self.irq_counter = state.read_u16()?;
+        self.irq_reload = state.read_u16()?;
+        self.irq_pending = state.read_bool()?;
+        Ok(())
+    }
+}
@@ -0,0 +1,72 @@
+// GxROM (Mapper 66) implementation
+// One latch selects a 32KB PRG bank (bits 4-5) and an 8KB CHR bank (bits 0-1). Super Mario Bros./Duck Hunt, Dragon Power, etc.
+
+use super::{ChrMemory, Mapper, Mirroring};
+use crate::state::{StateReader, StateWriter};
+use std::io::Result;
+
+pub struct Mapper66 {
+    prg_rom: Vec<u8>,
+    chr: ChrMemory,
+    mirroring: Mirroring,
+
+    register: u8,
+}
+
+impl Mapper66 {
+    pub fn new(prg_rom: Vec<u8>, chr: ChrMemory, mirroring: Mirroring) -> Self {
+        Mapper66 {
+            prg_rom,
+            chr,
+            mirroring,
+            register: 0,
+        }
+    }
+
+    fn chr_offset(&self, addr: u16) -> usize {
+        (self.register & 0x03) as usize * 0x2000 + (addr & 0x1FFF) as usize
+    }
+}
+
+impl Mapper for Mapper66 {
+    fn read_prg(&mut self, addr: u16) -> u8 {
+        match addr {
+            0x8000..=0xFFFF => {
+                let bank = ((self.register >> 4) & 0x03) as usize;
+                self.prg_rom[(bank * 0x8000 + (addr & 0x7FFF) as usize) % self.prg_rom.len()]
+            }
+            _ => 0,
+        }
+    }
+
+    fn write_prg(&mut self, addr: u16, value: u8) {
+        if addr >= 0x8000 {
+            // The latch has no write enable, so the ROM's byte is ANDed in
+            self.register = value & self.read_prg(addr);
+        }
+    }
+
+    fn read_chr(&mut self, addr: u16) -> u8 {
+        self.chr.read(self.chr_offset(addr))
+    }
+
+    fn write_chr(&mut self, addr: u16, value: u8) {
+        let offset = self.chr_offset(addr);
+        self.chr.write(offset, value);
+    }
+
+    fn mirroring(&self) -> Mirroring {
+        self.mirroring
+    }
+
+    fn save_state(&self, state: &mut StateWriter) {
+        self.chr.save_state(state);
+        state.write_u8(self.register);
+    }
+
+    fn load_state(&mut self, state: &mut StateReader) -> Result<()> {
+        self.chr.load_state(state)?;
+        self.register = state.read_u8()?;
+        Ok(())
+    }
+}
@@ -0,0 +1,183 @@
+// Sunsoft FME-7 (Mapper 69) implementation
+// A command/parameter register pair selects four 8KB PRG banks (the one at $6000 can map PRG
+// RAM instead), eight 1KB CHR banks, mirroring, and a 16-bit IRQ counter that counts down every
+// CPU cycle. Batman: Return of the Joker, Gimmick!, Hebereke.
+// The Sunsoft 5B expansion audio registers at $C000-$FFFF are accepted and ignored.
+
+use super::{ChrMemory, Mapper, Mirroring};
+use crate::state::{StateReader, StateWriter};
+use std::io::Result;
+
+pub struct Mapper69 {
+    prg_rom: Vec<u8>,
+    chr: ChrMemory,
+    prg_ram: Vec<u8>,
+
+    command: u8,
+    // $6000 bank: bit 7 RAM enable, bit 6 RAM instead of ROM, bits 0-5 bank
+    prg_bank_6000: u8,
+    prg_banks: [u8; 3],
+    chr_banks: [u8; 8],
+    mirroring: u8,
+
+    irq_enabled: bool,
+    irq_counter_enabled: bool,
+    irq_counter: u16,
+    irq_pending: bool,
+}
+
+impl Mapper69 {
+    pub fn new(prg_rom: Vec<u8>, chr: ChrMemory, prg_ram_size: usize) -> Self {
+        Mapper69 {
+            prg_rom,
+            chr,
+            prg_ram: vec![0; prg_ram_size],
+            command: 0,
+            prg_bank_6000: 0,
+            prg_banks: [0, 1, 2],
+            chr_banks: [0; 8],
+            mirroring: 0,
+            irq_enabled: false,
+            irq_counter_enabled: false,
+            irq_counter: 0,
+            irq_pending: false,
+        }
+    }
+
+    fn chr_offset(&self, addr: u16) -> usize {
+        let bank = self.chr_banks[((addr >> 10) & 0x07) as usize] as usize;
+        bank * 0x400 + (addr & 0x3FF) as usize
+    }
+
+    fn read_prg_rom(&self, bank: usize, addr: u16) -> u8 {
+        self.prg_rom[(bank * 0x2000 + (addr & 0x1FFF) as usize) % self.prg_rom.len()]
+    }
+
+    /// Offset into PRG RAM for $6000-$7FFF, when RAM is mapped and enabled there.
+    fn prg_ram_offset(&self, addr: u16) -> Option<usize> {
+        if self.prg_ram.is_empty() || self.prg_bank_6000 & 0xC0 != 0xC0 {
+            return None;
+        }
+        let bank = (self.prg_bank_6000 & 0x3F) as usize;
+        Some((bank * 0x2000 + (addr as usize - 0x6000)) % self.prg_ram.len())
+    }
+
+    fn write_parameter(&mut self, value: u8) {
+        match self.command {
+            0x0..=0x7 => self.chr_banks[self.command as usize] = value,
+            0x8 => self.prg_bank_6000 = value,
+            0x9..=0xB => self.prg_banks[(self.command - 0x9) as usize] = value & 0x3F,
+            0xC => self.mirroring = value & 0x03,
+            0xD => {
+                self.irq_enabled = value & 0x01 != 0;
+                self.irq_counter_enabled = value & 0x80 != 0;
+                self.irq_pending = false;
+            }
+            0xE => self.irq_counter = (self.irq_counter & 0xFF00) | value as u16,
+            _ => self.irq_counter = (self.irq_counter & 0x00FF) | (value as u16) << 8,
+        }
+    }
+}
+
+impl Mapper for Mapper69 {
+    fn read_prg(&mut self, addr: u16) -> u8 {
+        match addr {
+            0x6000..=0x7FFF => match self.prg_ram_offset(addr) {
+                Some(offset) => self.prg_ram[offset],
+                // ROM mode; RAM mode while disabled is open bus
+                None if self.prg_bank_6000 & 0x40 == 0 => {
+                    self.read_prg_rom((self.prg_bank_6000 & 0x3F) as usize, addr)
+                }
+                None => 0,
+            },
+            0x8000..=0xDFFF => {
+                let bank = self.prg_banks[((addr - 0x8000) >> 13) as usize] as usize;
+                self.read_prg_rom(bank, addr)
+            }
+            0xE000..=0xFFFF => self.read_prg_rom((self.prg_rom.len() / 0x2000).saturating_sub(1), addr),
+            _ => 0,
+        }
+    }
+
+    fn write_prg(&mut self, addr: u16, value: u8) {
+        match addr {
+            0x6000..=0x7FFF => {
+                if let Some(offset) = self.prg_ram_offset(addr) {
+                    self.prg_ram[offset] = value;
+                }
+            }
+            0x8000..=0x9FFF => self.command = value & 0x0F,
+            0xA000..=0xBFFF => self.write_parameter(value),
+            _ => {}
+        }
+    }
+
+    fn read_chr(&mut self, addr: u16) -> u8 {
+        self.chr.read(self.chr_offset(addr))
+    }
+
+    fn write_chr(&mut self, addr: u16, value: u8) {
+        let offset = self.chr_offset(addr);
+        self.chr.write(offset, value);
+    }
+
+    fn mirroring(&self) -> Mirroring {
+        match self.mirroring {
+            0 => Mirroring::Vertical,
+            1 => Mirroring::Horizontal,
+            2 => Mirroring::SingleScreenLower,
+            _ => Mirroring::SingleScreenUpper,
+        }
+    }
+
+    fn irq_pending(&self) -> bool {
+        self.irq_pending
+    }
+
+    fn clock_cpu(&mut self) {
+        // The IRQ fires when the counter wraps from $0000 to $FFFF
+        if self.irq_counter_enabled {
+            self.irq_counter = self.irq_counter.wrapping_sub(1);
+            if self.irq_counter == 0xFFFF && self.irq_enabled {
+                self.irq_pending = true;
+            }
+        }
+    }
+
+    fn prg_ram(&self) -> Option<&[u8]> {
+        Some(&self.prg_ram)
+    }
+
+    fn prg_ram_mut(&mut self) -> Option<&mut [u8]> {
+        Some(&mut self.prg_ram)
+    }
+
+    fn save_state(&self, state: &mut StateWriter) {
+        state.write_bytes(&self.prg_ram);
+        self.chr.save_state(state);
+        state.write_u8(self.command);
+        state.write_u8(self.prg_bank_6000);
+        state.write_bytes(&self.prg_banks);
+        state.write_bytes(&self.chr_banks);
+        state.write_u8(self.mirroring);
+        state.write_bool(self.irq_enabled);
+        state.write_bool(self.irq_counter_enabled);
+        state.write_u16(self.irq_counter);
+        state.write_bool(self.irq_pending);
+    }
+
+    fn load_state(&mut self, state: &mut StateReader) -> Result<()> {
+        state.read_into(&mut self.prg_ram)?;
+        self.chr.load_state(state)?;
+        self.command = state.read_u8()?;
+        self.prg_bank_6000 = state.read_u8()?;
+        state.read_into(&mut self.prg_banks)?;
+        state.read_into(&mut self.chr_banks)?;
+        self.mirroring = state.read_u8()?;
+        self.irq_enabled = state.read_bool()?;
+        self.irq_counter_enabled = state.read_bool()?;
+        self.irq_counter = state.read_u16()?;
+        self.irq_pending = state.read_bool()?;
+        Ok(())
+    }
+}
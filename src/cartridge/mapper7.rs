@@ -0,0 +1,72 @@
+// AxROM (Mapper 7) implementation
+// 32KB PRG banks and a single-screen nametable select. Battletoads, Marble Madness, Wizards & Warriors, etc.
+
+use super::{ChrMemory, Mapper, Mirroring};
+use crate::state::{StateReader, StateWriter};
+use std::io::Result;
+
+pub struct Mapper7 {
+    prg_rom: Vec<u8>,
+    chr: ChrMemory,
+    bus_conflicts: bool,
+
+    // Bits 0-2 select the PRG bank, bit 4 the CIRAM page
+    register: u8,
+}
+
+impl Mapper7 {
+    pub fn new(prg_rom: Vec<u8>, chr: ChrMemory, bus_conflicts: bool) -> Self {
+        Mapper7 {
+            prg_rom,
+            chr,
+            bus_conflicts,
+            register: 0,
+        }
+    }
+}
+
+impl Mapper for Mapper7 {
+    fn read_prg(&mut self, addr: u16) -> u8 {
+        match addr {
+            0x8000..=0xFFFF => {
+                let bank = (self.register & 0x07) as usize;
+                self.prg_rom[(bank * 0x8000 + (addr & 0x7FFF) as usize) % self.prg_rom.len()]
+            }
+            _ => 0,
+        }
+    }
+
+    fn write_prg(&mut self, addr: u16, value: u8) {
+        if addr >= 0x8000 {
+            // ANROM and AMROM have bus conflicts, AOROM does not
+            self.register = if self.bus_conflicts { value & self.read_prg(addr) } else { value };
+        }
+    }
+
+    fn read_chr(&mut self, addr: u16) -> u8 {
+        self.chr.read((addr & 0x1FFF) as usize)
+    }
+
+    fn write_chr(&mut self, addr: u16, value: u8) {
+        self.chr.write((addr & 0x1FFF) as usize, value);
+    }
+
+    fn mirroring(&self) -> Mirroring {
+        if self.register & 0x10 == 0 {
+            Mirroring::SingleScreenLower
+        } else {
+            Mirroring::SingleScreenUpper
+        }
+    }
+
+    fn save_state(&self, state: &mut StateWriter) {
+        self.chr.save_state(state);
+        state.write_u8(self.register);
+    }
+
+    fn load_state(&mut self, state: &mut StateReader) -> Result<()> {
+        self.chr.load_state(state)?;
+        self.register = state.read_u8()?;
+        Ok(())
+    }
+}
@@ -0,0 +1,78 @@
+// Camerica BF909x (Mapper 71) implementation
+// UxROM-like 16KB PRG banking through $C000-$FFFF. Micro Machines, Bee 52, Fire Hawk, etc.
+// The BF9097 used by Fire Hawk also selects a single-screen nametable through $9000-$9FFF.
+
+use super::{ChrMemory, Mapper, Mirroring};
+use crate::state::{StateReader, StateWriter};
+use std::io::Result;
+
+pub struct Mapper71 {
+    prg_rom: Vec<u8>,
+    chr: ChrMemory,
+    mirroring: Mirroring,
+
+    prg_bank: u8,
+}
+
+impl Mapper71 {
+    pub fn new(prg_rom: Vec<u8>, chr: ChrMemory, mirroring: Mirroring) -> Self {
+        Mapper71 {
+            prg_rom,
+            chr,
+            mirroring,
+            prg_bank: 0,
+        }
+    }
+}
+
+impl Mapper for Mapper71 {
+    fn read_prg(&mut self, addr: u16) -> u8 {
+        let offset = (addr & 0x3FFF) as usize;
+        match addr {
+            0x8000..=0xBFFF => self.prg_rom[(self.prg_bank as usize * 0x4000 + offset) % self.prg_rom.len()],
+            // Images smaller than the 16KB window repeat in it
+            0xC000..=0xFFFF => self.prg_rom[(self.prg_rom.len().saturating_sub(0x4000) + offset) % self.prg_rom.len()],
+            _ => 0,
+        }
+    }
+
+    fn write_prg(&mut self, addr: u16, value: u8) {
+        match addr {
+            0x9000..=0x9FFF => {
+                self.mirroring = if value & 0x10 == 0 {
+                    Mirroring::SingleScreenLower
+                } else {
+                    Mirroring::SingleScreenUpper
+                };
+            }
+            // No bus conflicts: the Camerica chip disables the ROM during writes
+            0xC000..=0xFFFF => self.prg_bank = value,
+            _ => {}
+        }
+    }
+
+    fn read_chr(&mut self, addr: u16) -> u8 {
+        self.chr.read((addr & 0x1FFF) as usize)
+    }
+
+    fn write_chr(&mut self, addr: u16, value: u8) {
+        self.chr.write((addr & 0x1FFF) as usize, value);
+    }
+
+    fn mirroring(&self) -> Mirroring {
+        self.mirroring
+    }
+
+    fn save_state(&self, state: &mut StateWriter) {
+        self.chr.save_state(state);
+        state.write_u8(self.prg_bank);
+        state.write_u8(self.mirroring as u8);
+    }
+
+    fn load_state(&mut self, state: &mut StateReader) -> Result<()> {
+        self.chr.load_state(state)?;
+        self.prg_bank = state.read_u8()?;
+        self.mirroring = Mirroring::from_state(state.read_u8()?)?;
+        Ok(())
+    }
+}
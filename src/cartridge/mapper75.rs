@@ -0,0 +1,97 @@
+// Konami VRC1 (Mapper 75) implementation
+// Three switchable 8KB PRG banks and two 4KB CHR banks. Ganbare Goemon!, Exciting Boxing, etc.
+
+use super::{ChrMemory, Mapper, Mirroring};
+use crate::state::{StateReader, StateWriter};
+use std::io::Result;
+
+pub struct Mapper75 {
+    prg_rom: Vec<u8>,
+    chr: ChrMemory,
+
+    prg_banks: [u8; 3],
+    // Low four bits of each CHR bank; the fifth comes from $9000
+    chr_banks: [u8; 2],
+    // $9000: bit 0 mirroring, bits 1-2 CHR bank high bits
+    control: u8,
+}
+
+impl Mapper75 {
+    pub fn new(prg_rom: Vec<u8>, chr: ChrMemory) -> Self {
+        Mapper75 {
+            prg_rom,
+            chr,
+            prg_banks: [0, 1, 2],
+            chr_banks: [0, 1],
+            control: 0,
+        }
+    }
+
+    fn chr_offset(&self, addr: u16) -> usize {
+        let index = ((addr >> 12) & 0x01) as usize;
+        let high = (self.control >> (1 + index)) & 0x01;
+        let bank = (high << 4 | self.chr_banks[index]) as usize;
+        bank * 0x1000 + (addr & 0x0FFF) as usize
+    }
+
+    fn read_prg_rom(&self, bank: usize, addr: u16) -> u8 {
+        self.prg_rom[(bank * 0x2000 + (addr & 0x1FFF) as usize) % self.prg_rom.len()]
+    }
+}
+
+impl Mapper for Mapper75 {
+    fn read_prg(&mut self, addr: u16) -> u8 {
+        match addr {
+            0x8000..=0xDFFF => {
+                let bank = self.prg_banks[((addr - 0x8000) >> 13) as usize] as usize;
+                self.read_prg_rom(bank, addr)
+            }
+            0xE000..=0xFFFF => self.read_prg_rom((self.prg_rom.len() / 0x2000).saturating_sub(1), addr),
+            _ => 0,
+        }
+    }
+
+    fn write_prg(&mut self, addr: u16, value: u8) {
+        match addr & 0xF000 {
+            0x8000 => self.prg_banks[0] = value & 0x0F,
+            0x9000 => self.control = value & 0x07,
+            0xA000 => self.prg_banks[1] = value & 0x0F,
+            0xC000 => self.prg_banks[2] = value & 0x0F,
+            0xE000 => self.chr_banks[0] = value & 0x0F,
+            0xF000 => self.chr_banks[1] = value & 0x0F,
+            _ => {}
+        }
+    }
+
+    fn read_chr(&mut self, addr: u16) -> u8 {
+        self.chr.read(self.chr_offset(addr))
+    }
+
+    fn write_chr(&mut self, addr: u16, value: u8) {
+        let offset = self.chr_offset(addr);
+        self.chr.write(offset, value);
+    }
+
+    fn mirroring(&self) -> Mirroring {
+        if self.control & 0x01 == 0 {
+            Mirroring::Vertical
+        } else {
+            Mirroring::Horizontal
+        }
+    }
+
+    fn save_state(&self, state: &mut StateWriter) {
+        self.chr.save_state(state);
+        state.write_bytes(&self.prg_banks);
+        state.write_bytes(&self.chr_banks);
+        state.write_u8(self.control);
+    }
+
+    fn load_state(&mut self, state: &mut StateReader) -> Result<()> {
+        self.chr.load_state(state)?;
+        state.read_into(&mut self.prg_banks)?;
+        state.read_into(&mut self.chr_banks)?;
+        self.control = state.read_u8()?;
+        Ok(())
+    }
+}
@@ -0,0 +1,147 @@
+// Konami VRC7 (Mapper 85) implementation
+// Three switchable 8KB PRG banks and eight 1KB CHR banks. Lagrange Point, Tiny Toon Adventures 2.
+// VRC7a selects the second register of each pair with A4, VRC7b with A3. The FM audio
+// registers are accepted and ignored.
+
+use super::vrc::{self, VrcIrq};
+use super::{ChrMemory, Mapper, Mirroring};
+use crate::state::{StateReader, StateWriter};
+use std::io::Result;
+
+pub struct Mapper85 {
+    prg_rom: Vec<u8>,
+    chr: ChrMemory,
+    prg_ram: Vec<u8>,
+    // CPU address lines that select the second register of each pair
+    select_lines: u16,
+
+    prg_banks: [u8; 3],
+    chr_banks: [u8; 8],
+    // $E000: bit 7 PRG RAM enable, bits 0-1 mirroring
+    control: u8,
+    irq: VrcIrq,
+}
+
+impl Mapper85 {
+    pub fn new(submapper: u8, prg_rom: Vec<u8>, chr: ChrMemory, prg_ram_size: usize) -> Self {
+        let select_lines = match submapper {
+            1 => 0x08, // VRC7b
+            2 => 0x10, // VRC7a
+            _ => 0x18,
+        };
+        Mapper85 {
+            prg_rom,
+            chr,
+            prg_ram: vec![0; prg_ram_size],
+            select_lines,
+            prg_banks: [0; 3],
+            chr_banks: [0; 8],
+            control: 0,
+            irq: VrcIrq::new(),
+        }
+    }
+
+    fn prg_ram_enabled(&self) -> bool {
+        !self.prg_ram.is_empty() && self.control & 0x80 != 0
+    }
+
+    fn chr_offset(&self, addr: u16) -> usize {
+        let bank = self.chr_banks[((addr >> 10) & 0x07) as usize] as usize;
+        bank * 0x400 + (addr & 0x3FF) as usize
+    }
+
+    fn read_prg_rom(&self, bank: usize, addr: u16) -> u8 {
+        self.prg_rom[(bank * 0x2000 + (addr & 0x1FFF) as usize) % self.prg_rom.len()]
+    }
+}
+
+impl Mapper for Mapper85 {
+    fn read_prg(&mut self, addr: u16) -> u8 {
+        match addr {
+            0x6000..=0x7FFF if self.prg_ram_enabled() => {
+                self.prg_ram[(addr as usize - 0x6000) % self.prg_ram.len()]
+            }
+            0x8000..=0xDFFF => {
+                let bank = self.prg_banks[((addr - 0x8000) >> 13) as usize] as usize;
+                self.read_prg_rom(bank, addr)
+            }
+            0xE000..=0xFFFF => self.read_prg_rom((self.prg_rom.len() / 0x2000).saturating_sub(1), addr),
+            _ => 0,
+        }
+    }
+
+    fn write_prg(&mut self, addr: u16, value: u8) {
+        if (0x6000..=0x7FFF).contains(&addr) {
+            if self.prg_ram_enabled() {
+                let len = self.prg_ram.len();
+                self.prg_ram[(addr as usize - 0x6000) % len] = value;
+            }
+            return;
+        }
+
+        let second = addr & self.select_lines != 0;
+        match (addr & 0xF000, second) {
+            (0x8000, false) => self.prg_banks[0] = value & 0x3F,
+            (0x8000, true) => self.prg_banks[1] = value & 0x3F,
+            (0x9000, false) => self.prg_banks[2] = value & 0x3F,
+            (register @ 0xA000..=0xD000, second) => {
+                let index = ((register >> 12) - 0xA) * 2 + second as u16;
+                self.chr_banks[index as usize] = value;
+            }
+            (0xE000, false) => self.control = value,
+            (0xE000, true) => self.irq.write_latch(value),
+            (0xF000, false) => self.irq.write_control(value),
+            (0xF000, true) => self.irq.acknowledge(),
+            // $9010/$9030: FM audio
+            _ => {}
+        }
+    }
+
+    fn read_chr(&mut self, addr: u16) -> u8 {
+        self.chr.read(self.chr_offset(addr))
+    }
+
+    fn write_chr(&mut self, addr: u16, value: u8) {
+        let offset = self.chr_offset(addr);
+        self.chr.write(offset, value);
+    }
+
+    fn mirroring(&self) -> Mirroring {
+        vrc::mirroring(self.control)
+    }
+
+    fn irq_pending(&self) -> bool {
+        self.irq.pending()
+    }
+
+    fn clock_cpu(&mut self) {
+        self.irq.clock();
+    }
+
+    fn prg_ram(&self) -> Option<&[u8]> {
+        Some(&self.prg_ram)
+    }
+
+    fn prg_ram_mut(&mut self) -> Option<&mut [u8]> {
+        Some(&mut self.prg_ram)
+    }
+
+    fn save_state(&self, state: &mut StateWriter) {
+        state.write_bytes(&self.prg_ram);
+        self.chr.save_state(state);
+        state.write_bytes(&self.prg_banks);
+        state.write_bytes(&self.chr_banks);
+        state.write_u8(self.control);
+        self.irq.save_state(state);
+    }
+
+    fn load_state(&mut self, state: &mut StateReader) -> Result<()> {
+        state.read_into(&mut self.prg_ram)?;
+        self.chr.load_state(state)?;
+        state.read_into(&mut self.prg_banks)?;
+        state.read_into(&mut self.chr_banks)?;
+        self.control = state.read_u8()?;
+        self.irq.load_state(state)?;
+        Ok(())
+    }
+}
@@ -0,0 +1,149 @@
+// MMC2 and MMC4 (Mappers 9 and 10) implementation
+// Each 4KB CHR half has two banks, and a latch picks between them whenever the PPU fetches
+// tile $FD or $FE from that half. The switch takes effect after the triggering fetch.
+// MMC2: Punch-Out!! (8KB PRG banks). MMC4: Fire Emblem, Famicom Wars (16KB PRG banks, PRG RAM).
+
+use super::{ChrMemory, Mapper, Mirroring};
+use crate::state::{StateReader, StateWriter};
+use std::io::Result;
+
+pub struct Mapper9 {
+    prg_rom: Vec<u8>,
+    chr: ChrMemory,
+    prg_ram: Vec<u8>,
+    mmc4: bool,
+
+    prg_bank: u8,
+    // [half][latch]: banks used while the latch holds $FD (0) or $FE (1)
+    chr_banks: [[u8; 2]; 2],
+    latches: [u8; 2],
+    mirroring: u8,
+}
+
+impl Mapper9 {
+    pub fn new(prg_rom: Vec<u8>, chr: ChrMemory, prg_ram_size: usize, mmc4: bool) -> Self {
+        Mapper9 {
+            prg_rom,
+            chr,
+            prg_ram: vec![0; prg_ram_size],
+            mmc4,
+            prg_bank: 0,
+            chr_banks: [[0; 2]; 2],
+            latches: [1, 1],
+            mirroring: 0,
+        }
+    }
+
+    fn chr_offset(&self, addr: u16) -> usize {
+        let half = ((addr >> 12) & 0x01) as usize;
+        let bank = self.chr_banks[half][self.latches[half] as usize] as usize;
+        bank * 0x1000 + (addr & 0x0FFF) as usize
+    }
+
+    /// Flip the latches on fetches of the high plane of tiles $FD and $FE.
+    /// MMC2 only watches the first row for the lower pattern table.
+    fn update_latches(&mut self, addr: u16) {
+        let half = ((addr >> 12) & 0x01) as usize;
+        let exact = half == 0 && !self.mmc4;
+        let tile_addr = if exact { addr & 0x1FFF } else { addr & 0x1FF8 };
+        match tile_addr & 0x0FFF {
+            0x0FD8 => self.latches[half] = 0,
+            0x0FE8 => self.latches[half] = 1,
+            _ => {}
+        }
+    }
+
+    fn read_prg_rom(&self, offset: usize) -> u8 {
+        self.prg_rom[offset % self.prg_rom.len()]
+    }
+}
+
+impl Mapper for Mapper9 {
+    fn read_prg(&mut self, addr: u16) -> u8 {
+        let len = self.prg_rom.len();
+        match addr {
+            0x6000..=0x7FFF if !self.prg_ram.is_empty() => {
+                self.prg_ram[(addr as usize - 0x6000) % self.prg_ram.len()]
+            }
+            // MMC4: 16KB switchable bank and the last 16KB fixed
+            0x8000..=0xBFFF if self.mmc4 => {
+                self.read_prg_rom(self.prg_bank as usize * 0x4000 + (addr & 0x3FFF) as usize)
+            }
+            0xC000..=0xFFFF if self.mmc4 => self.read_prg_rom(len.saturating_sub(0x4000) + (addr & 0x3FFF) as usize),
+            // MMC2: 8KB switchable bank and the last three 8KB fixed
+            0x8000..=0x9FFF => self.read_prg_rom(self.prg_bank as usize * 0x2000 + (addr & 0x1FFF) as usize),
+            0xA000..=0xFFFF => self.read_prg_rom(len.saturating_sub(0x8000) + (addr & 0x7FFF) as usize),
+            _ => 0,
+        }
+    }
+
+    fn write_prg(&mut self, addr: u16, value: u8) {
+        match addr {
+            0x6000..=0x7FFF if !self.prg_ram.is_empty() => {
+                let len = self.prg_ram.len();
+                self.prg_ram[(addr as usize - 0x6000) % len] = value;
+            }
+            0xA000..=0xAFFF => self.prg_bank = value & 0x0F,
+            0xB000..=0xBFFF => self.chr_banks[0][0] = value & 0x1F,
+            0xC000..=0xCFFF => self.chr_banks[0][1] = value & 0x1F,
+            0xD000..=0xDFFF => self.chr_banks[1][0] = value & 0x1F,
+            0xE000..=0xEFFF => self.chr_banks[1][1] = value & 0x1F,
+            0xF000..=0xFFFF => self.mirroring = value & 0x01,
+            _ => {}
+        }
+    }
+
+    fn read_chr(&mut self, addr: u16) -> u8 {
+        let value = self.chr.read(self.chr_offset(addr));
+        self.update_latches(addr);
+        value
+    }
+
+    fn peek_chr(&mut self, addr: u16) -> u8 {
+        self.chr.read(self.chr_offset(addr))
+    }
+
+    fn write_chr(&mut self, addr: u16, value: u8) {
+        let offset = self.chr_offset(addr);
+        self.chr.write(offset, value);
+    }
+
+    fn mirroring(&self) -> Mirroring {
+        if self.mirroring == 0 {
+            Mirroring::Vertical
+        } else {
+            Mirroring::Horizontal
+        }
+    }
+
+    fn prg_ram(&self) -> Option<&[u8]> {
+        Some(&self.prg_ram)
+    }
+
+    fn prg_ram_mut(&mut self) -> Option<&mut [u8]> {
+        Some(&mut self.prg_ram)
+    }
+
+    fn save_state(&self, state: &mut StateWriter) {
+        state.write_bytes(&self.prg_ram);
+        self.chr.save_state(state);
+        state.write_u8(self.prg_bank);
+        for banks in &self.chr_banks {
+            state.write_bytes(banks);
+        }
+        state.write_bytes(&self.latches);
+        state.write_u8(self.mirroring);
+    }
+
+    fn load_state(&mut self, state: &mut StateReader) -> Result<()> {
+        state.read_into(&mut self.prg_ram)?;
+        self.chr.load_state(state)?;
+        self.prg_bank = state.read_u8()?;
+        for banks in &mut self.chr_banks {
+            state.read_into(banks)?;
+        }
+        state.read_into(&mut self.latches)?;
+        self.mirroring = state.read_u8()?;
+        Ok(())
+    }
+}
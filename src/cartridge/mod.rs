@@ -1,35 +1,236 @@
+mod eeprom;
+mod header;
+mod mapper0;
+mod mapper1;
+mod mapper11;
+mod mapper16;
+mod mapper19;
+mod mapper2;
+mod mapper21;
+mod mapper24;
+mod mapper3;
+mod mapper34;
+mod mapper4;
+mod mapper5;
+mod mapper65;
+mod mapper66;
+mod mapper69;
+mod mapper7;
+mod mapper71;
+mod mapper75;
+mod mapper85;
+mod mapper9;
+mod vrc;
+
+pub use header::{ConsoleType, HeaderFormat, RomHeader, TimingRegion};
+pub use mapper0::Mapper0;
+pub use mapper1::Mapper1;
+pub use mapper11::Mapper11;
+pub use mapper16::Mapper16;
+pub use mapper19::Mapper19;
+pub use mapper2::Mapper2;
+pub use mapper21::Mapper21;
+pub use mapper24::Mapper24;
+pub use mapper3::Mapper3;
+pub use mapper34::Mapper34;
+pub use mapper4::Mapper4;
+pub use mapper5::Mapper5;
+pub use mapper65::Mapper65;
+pub use mapper66::Mapper66;
+pub use mapper69::Mapper69;
+pub use mapper7::Mapper7;
+pub use mapper71::Mapper71;
+pub use mapper75::Mapper75;
+pub use mapper85::Mapper85;
+pub use mapper9::Mapper9;
+
+#[cfg(test)]
+mod tests;
+
+use crate::state::{self, StateReader, StateWriter};
 use std::fs::File;
 use std::io::{Read, Result, Error, ErrorKind};
 use std::path::Path;
 
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum Mirroring {
     Horizontal,
     Vertical,
     FourScreen,
-    _SingleScreenLower,
-    _SingleScreenUpper,
+    SingleScreenLower,
+    SingleScreenUpper,
+}
+
+impl Mirroring {
+    /// Offset into the console's 2KB of nametable RAM (CIRAM) for a $2000-$2FFF address.
+    pub fn ciram_offset(self, addr: u16) -> usize {
+        let table = (addr as usize >> 10) & 0x03;
+        let page = match self {
+            Mirroring::Horizontal => table >> 1,
+            // CIRAM holds the first two tables, the cartridge supplies the others
+            Mirroring::Vertical | Mirroring::FourScreen => table & 0x01,
+            Mirroring::SingleScreenLower => 0,
+            Mirroring::SingleScreenUpper => 1,
+        };
+        page * 0x400 + (addr as usize & 0x3FF)
+    }
+
+    /// Inverse of `mirroring as u8`, for mappers that save a switchable arrangement.
+    pub fn from_state(value: u8) -> Result<Self> {
+        match value {
+            0 => Ok(Mirroring::Horizontal),
+            1 => Ok(Mirroring::Vertical),
+            2 => Ok(Mirroring::FourScreen),
+            3 => Ok(Mirroring::SingleScreenLower),
+            4 => Ok(Mirroring::SingleScreenUpper),
+            _ => Err(Error::new(ErrorKind::InvalidData, format!("Invalid mirroring {} in save state", value))),
+        }
+    }
+}
+
+/// Pattern table storage behind PPU $0000-$1FFF.
+///
+/// Boards either carry CHR ROM or CHR RAM that the game fills through $2007. The RAM size
+/// comes from the header, up to 32KB, and mappers bank-switch it exactly like ROM.
+pub struct ChrMemory {
+    rom: Vec<u8>,
+    ram: Vec<u8>,
+}
+
+impl ChrMemory {
+    pub fn new(chr_rom: Vec<u8>, chr_ram_size: usize) -> Self {
+        // A board without CHR ROM needs RAM even if the header does not say how much
+        let chr_ram_size = if chr_rom.is_empty() && chr_ram_size == 0 { 0x2000 } else { chr_ram_size };
+        ChrMemory { rom: chr_rom, ram: vec![0; chr_ram_size] }
+    }
+
+    /// True if pattern data comes from CHR RAM.
+    pub fn is_ram(&self) -> bool {
+        self.rom.is_empty()
+    }
+
+    fn active(&self) -> &[u8] {
+        if self.is_ram() { &self.ram } else { &self.rom }
+    }
+
+    pub fn len(&self) -> usize {
+        self.active().len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.active().is_empty()
+    }
+
+    /// Read at an absolute offset, wrapping around the available memory.
+    pub fn read(&self, offset: usize) -> u8 {
+        let data = self.active();
+        data[offset % data.len()]
+    }
+
+    /// Write at an absolute offset; ignored for CHR ROM.
+    pub fn write(&mut self, offset: usize, value: u8) {
+        if self.is_ram() {
+            let len = self.ram.len();
+            self.ram[offset % len] = value;
+        }
+    }
+
+    /// Only CHR RAM is saved, CHR ROM comes back with the cartridge.
+    pub fn save_state(&self, state: &mut StateWriter) {
+        state.write_bytes(&self.ram);
+    }
+
+    pub fn load_state(&mut self, state: &mut StateReader) -> Result<()> {
+        state.read_into(&mut self.ram)
+    }
+}
+
+/// Board-specific logic behind the cartridge connector.
+///
+/// CPU addresses are passed through unmodified ($4020-$FFFF) so mappers can expose
+/// registers and PRG RAM anywhere in cartridge space; PPU addresses are $0000-$1FFF.
+pub trait Mapper {
+    fn read_prg(&mut self, addr: u16) -> u8;
+    fn write_prg(&mut self, addr: u16, value: u8);
+    /// Pattern reads from $2007 and the fetches the PPU makes while rendering, in the order
+    /// and on the dots it makes them: each background tile's low then high plane eight dots
+    /// before it is drawn, then the sprite fetches of dots 257-320. Boards can react to the
+    /// addresses as well as serve them.
+    fn read_chr(&mut self, addr: u16) -> u8;
+    fn write_chr(&mut self, addr: u16, value: u8);
+
+    /// Pattern read for fetches the real PPU never makes, such as sprites past the eighth.
+    /// Boards whose `read_chr` watches the addresses serve these without reacting to them.
+    fn peek_chr(&mut self, addr: u16) -> u8 {
+        self.read_chr(addr)
+    }
+
+    /// Current nametable arrangement.
+    fn mirroring(&self) -> Mirroring;
+
+    /// Read a nametable byte ($2000-$2FFF). CIRAM's address and enable lines run through the
+    /// cartridge, so boards can arrange it freely or answer with their own memory instead.
+    fn read_nametable(&mut self, addr: u16, ciram: &[u8; 0x800]) -> u8 {
+        ciram[self.mirroring().ciram_offset(addr)]
+    }
+
+    fn write_nametable(&mut self, addr: u16, value: u8, ciram: &mut [u8; 0x800]) {
+        ciram[self.mirroring().ciram_offset(addr)] = value;
+    }
+
+    /// State of the mapper's IRQ output; it stays asserted until the game acknowledges it.
+    fn irq_pending(&self) -> bool {
+        false
+    }
+
+    /// Called once per rendered scanline (dot 260) while rendering is enabled.
+    fn clock_scanline(&mut self) {}
+
+    /// Called with `false` when the PPU enters vertical blank.
+    fn notify_ppu_state(&mut self, _rendering: bool) {}
+
+    /// Called with every address the PPU puts on its bus: pattern and nametable accesses,
+    /// and changes to v outside of rendering.
+    fn notify_ppu_addr(&mut self, _addr: u16) {}
+
+    /// Called once per CPU cycle, before the cycle's bus access.
+    fn clock_cpu(&mut self) {}
+
+    /// Write all registers, counters and RAM.
+    fn save_state(&self, state: &mut StateWriter);
+
+    /// Restore what `save_state` wrote, in the same order.
+    fn load_state(&mut self, state: &mut StateReader) -> Result<()>;
+
+    /// PRG RAM that a battery keeps alive on boards that have one.
+    fn prg_ram(&self) -> Option<&[u8]> {
+        None
+    }
+
+    fn prg_ram_mut(&mut self) -> Option<&mut [u8]> {
+        None
+    }
 }
 
 pub struct Cartridge {
+    pub header: RomHeader,
     pub prg_rom: Vec<u8>,
     pub chr_rom: Vec<u8>,
-    pub mapper: u8,
-    pub _mirroring: Mirroring,
-    pub _battery_backed: bool,
-    pub prg_ram: Vec<u8>,
-    
-    // MMC1 state (Mapper 1)
-    mmc1_shift_register: u8,
-    mmc1_shift_count: u8,
-    mmc1_control: u8,
-    mmc1_chr_bank_0: u8,
-    mmc1_chr_bank_1: u8,
-    mmc1_prg_bank: u8,
-    
-    // Mapper 65 state (Irem H3001)
-    m65_prg_banks: [u8; 3],
-    m65_chr_banks: [u8; 8],
+    pub mapper: u16,
+    pub battery_backed: bool,
+    board: Box<dyn Mapper>,
+    // Four-screen boards carry 2KB of VRAM for the nametables at $2800-$2FFF
+    four_screen_vram: Vec<u8>,
+}
+
+/// Whether a discrete-logic board ANDs the ROM's byte into register writes. NES 2.0
+/// submappers 1 and 2 say so explicitly for mappers 2, 3 and 7; otherwise use the common board.
+fn bus_conflicts(header: &RomHeader, default: bool) -> bool {
+    match header.submapper {
+        1 => false,
+        2 => true,
+        _ => default,
+    }
 }
 
 impl Cartridge {
@@ -42,347 +243,210 @@ impl Cartridge {
     }
 
     pub fn load_from_bytes(data: &[u8]) -> Result<Self> {
-        if data.len() < 16 {
-            return Err(Error::new(ErrorKind::InvalidData, "ROM file too small"));
-        }
+        let header = RomHeader::parse(data)?;
+        let mirroring = header.mirroring;
+        let prg_ram_size = header.total_prg_ram_size();
 
-        if &data[0..4] != b"NES\x1A" {
-            return Err(Error::new(ErrorKind::InvalidData, "Invalid NES header"));
-        }
+        let trainer_size = if header.trainer { 512 } else { 0 };
+        let prg_rom_start = RomHeader::SIZE + trainer_size;
+        // Exponent-multiplier sizes can be absurdly large in a corrupt NES 2.0 header
+        let chr_rom_start = prg_rom_start.saturating_add(header.prg_rom_size);
+        let chr_rom_end = chr_rom_start.saturating_add(header.chr_rom_size);
 
-        let prg_rom_size = data[4] as usize * 0x4000;
-        let chr_rom_size = data[5] as usize * 0x2000;
-        
-        let flags_6 = data[6];
-        let flags_7 = data[7];
-        
-        let mirroring = if (flags_6 & 0x08) != 0 {
-            Mirroring::FourScreen
-        } else if (flags_6 & 0x01) != 0 {
-            Mirroring::Vertical
-        } else {
-            Mirroring::Horizontal
-        };
-        
-        let battery_backed = (flags_6 & 0x02) != 0;
-        let trainer_present = (flags_6 & 0x04) != 0;
-        
-        let mapper = (flags_7 & 0xF0) | ((flags_6 & 0xF0) >> 4);
-        
-        let prg_ram_size = if data[8] == 0 { 0x2000 } else { data[8] as usize * 0x2000 };
-        
-        let header_size = 16;
-        let trainer_size = if trainer_present { 512 } else { 0 };
-        let prg_rom_start = header_size + trainer_size;
-        let chr_rom_start = prg_rom_start + prg_rom_size;
-        
-        if data.len() < chr_rom_start + chr_rom_size {
+        if data.len() < chr_rom_end {
             return Err(Error::new(ErrorKind::InvalidData, "ROM file truncated"));
         }
-        
-        let prg_rom = data[prg_rom_start..prg_rom_start + prg_rom_size].to_vec();
-        let chr_rom = if chr_rom_size > 0 {
-            data[chr_rom_start..chr_rom_start + chr_rom_size].to_vec()
-        } else {
-            vec![0; 0x2000]
+        if header.prg_rom_size == 0 {
+            return Err(Error::new(ErrorKind::InvalidData, "ROM has no PRG ROM"));
+        }
+
+        let prg_rom = data[prg_rom_start..chr_rom_start].to_vec();
+        let chr_rom = data[chr_rom_start..chr_rom_end].to_vec();
+        let chr = ChrMemory::new(chr_rom.clone(), header.total_chr_ram_size());
+
+        let board: Box<dyn Mapper> = match header.mapper {
+            0 => Box::new(Mapper0::new(prg_rom.clone(), chr, prg_ram_size, mirroring)),
+            1 => Box::new(Mapper1::new(prg_rom.clone(), chr, prg_ram_size, mirroring)),
+            2 => Box::new(Mapper2::new(prg_rom.clone(), chr, mirroring, bus_conflicts(&header, true))),
+            3 => Box::new(Mapper3::new(prg_rom.clone(), chr, mirroring, bus_conflicts(&header, true))),
+            4 => Box::new(Mapper4::new(header.submapper, prg_rom.clone(), chr)),
+            5 => Box::new(Mapper5::new(prg_rom.clone(), chr)),
+            // AOROM, the most common AxROM board, has no bus conflicts
+            7 => Box::new(Mapper7::new(prg_rom.clone(), chr, bus_conflicts(&header, false))),
+            9 | 10 => Box::new(Mapper9::new(prg_rom.clone(), chr, prg_ram_size, header.mapper == 10)),
+            11 => Box::new(Mapper11::new(prg_rom.clone(), chr, mirroring)),
+            16 | 153 | 159 => Box::new(Mapper16::new(
+                header.mapper,
+                header.submapper,
+                prg_rom.clone(),
+                chr,
+                prg_ram_size,
+            )),
+            19 => Box::new(Mapper19::new(prg_rom.clone(), chr, prg_ram_size)),
+            21 | 22 | 23 | 25 => Box::new(Mapper21::new(
+                header.mapper,
+                header.submapper,
+                prg_rom.clone(),
+                chr,
+                prg_ram_size,
+            )),
+            24 | 26 => Box::new(Mapper24::new(prg_rom.clone(), chr, prg_ram_size, header.mapper == 26)),
+            34 => {
+                // Submapper 1 is NINA-001, 2 is BNROM; otherwise only NINA-001 has banked CHR ROM
+                let nina001 = match header.submapper {
+                    1 => true,
+                    2 => false,
+                    _ => header.chr_rom_size > 0x2000,
+                };
+                Box::new(Mapper34::new(prg_rom.clone(), chr, prg_ram_size, mirroring, nina001))
+            }
+            65 => Box::new(Mapper65::new(prg_rom.clone(), chr, prg_ram_size, mirroring)),
+            66 => Box::new(Mapper66::new(prg_rom.clone(), chr, mirroring)),
+            69 => Box::new(Mapper69::new(prg_rom.clone(), chr, prg_ram_size)),
+            71 => Box::new(Mapper71::new(prg_rom.clone(), chr, mirroring)),
+            75 => Box::new(Mapper75::new(prg_rom.clone(), chr)),
+            85 => Box::new(Mapper85::new(header.submapper, prg_rom.clone(), chr, prg_ram_size)),
+            _ => {
+                return Err(Error::new(
+                    ErrorKind::Unsupported,
+                    format!("Unsupported mapper: {}", header.mapper),
+                ));
+            }
+        };
+        let four_screen_vram = match header.mirroring {
+            Mirroring::FourScreen => vec![0; 0x800],
+            _ => Vec::new(),
         };
         
         Ok(Cartridge {
+            header,
             prg_rom,
             chr_rom,
-            mapper,
-            _mirroring: mirroring,
-            _battery_backed: battery_backed,
-            prg_ram: vec![0; prg_ram_size],
-            
-            // Initialize MMC1 state
-            mmc1_shift_register: 0,
-            mmc1_shift_count: 0,
-            mmc1_control: 0x0C, // Default: 16KB PRG mode, fixed high bank
-            mmc1_chr_bank_0: 0,
-            mmc1_chr_bank_1: 0,
-            mmc1_prg_bank: 0,
-            
-            // Initialize Mapper 65 state
-            m65_prg_banks: [0, 1, 2], // Default banks
-            m65_chr_banks: [0, 1, 2, 3, 4, 5, 6, 7],
+            mapper: header.mapper,
+            // Bandai's serial EEPROMs keep their contents without a battery
+            battery_backed: header.battery || header.mapper == 159 || (header.mapper == 16 && header.submapper != 4),
+            board,
+            four_screen_vram,
         })
     }
 
-    pub fn read_prg(&self, addr: u16) -> u8 {
-        match self.mapper {
-            0 => {
-                // Mapper 0: 16KB or 32KB PRG ROM
-                if self.prg_rom.len() == 0x4000 {
-                    // 16KB: Mirror at 0x8000-0xBFFF and 0xC000-0xFFFF
-                    self.prg_rom[(addr & 0x3FFF) as usize]
-                } else {
-                    // 32KB: Direct mapping
-                    if (addr as usize) < self.prg_rom.len() {
-                        self.prg_rom[addr as usize]
-                    } else {
-                        0xFF
-                    }
-                }
-            }
-            1 => {
-                // MMC1 Mapper
-                let prg_mode = (self.mmc1_control >> 2) & 0x03;
-                let prg_banks = self.prg_rom.len() / 0x4000;
-                
-                match prg_mode {
-                    0 | 1 => {
-                        // 32KB mode: ignore low bit of bank number
-                        let bank = (self.mmc1_prg_bank & 0xFE) as usize;
-                        let offset = bank * 0x4000 + (addr as usize);
-                        if offset < self.prg_rom.len() {
-                            self.prg_rom[offset]
-                        } else {
-                            0
-                        }
-                    }
-                    2 => {
-                        // Fix first bank at $8000, switch 16KB bank at $C000
-                        if addr < 0x4000 {
-                            // First bank fixed
-                            self.prg_rom[addr as usize]
-                        } else {
-                            // Switchable bank
-                            let bank = self.mmc1_prg_bank as usize;
-                            let offset = bank * 0x4000 + ((addr - 0x4000) as usize);
-                            if offset < self.prg_rom.len() {
-                                self.prg_rom[offset]
-                            } else {
-                                0
-                            }
-                        }
-                    }
-                    3 => {
-                        // Switch 16KB bank at $8000, fix last bank at $C000
-                        if addr < 0x4000 {
-                            // Switchable bank
-                            let bank = self.mmc1_prg_bank as usize;
-                            let offset = bank * 0x4000 + (addr as usize);
-                            if offset < self.prg_rom.len() {
-                                self.prg_rom[offset]
-                            } else {
-                                0
-                            }
-                        } else {
-                            // Last bank fixed
-                            let last_bank = prg_banks - 1;
-                            let offset = last_bank * 0x4000 + ((addr - 0x4000) as usize);
-                            if offset < self.prg_rom.len() {
-                                self.prg_rom[offset]
-                            } else {
-                                0
-                            }
-                        }
-                    }
-                    _ => 0
-                }
-            }
-            65 => {
-                // Mapper 65 (Irem H3001)
-                match addr {
-                    0x0000..=0x1FFF => {
-                        // Bank 0: switchable
-                        let bank = self.m65_prg_banks[0] as usize;
-                        let offset = bank * 0x2000 + (addr as usize);
-                        if offset < self.prg_rom.len() {
-                            self.prg_rom[offset]
-                        } else {
-                            0
-                        }
-                    }
-                    0x2000..=0x3FFF => {
-                        // Bank 1: switchable
-                        let bank = self.m65_prg_banks[1] as usize;
-                        let offset = bank * 0x2000 + ((addr - 0x2000) as usize);
-                        if offset < self.prg_rom.len() {
-                            self.prg_rom[offset]
-                        } else {
-                            0
-                        }
-                    }
-                    0x4000..=0x5FFF => {
-                        // Bank 2: switchable
-                        let bank = self.m65_prg_banks[2] as usize;
-                        let offset = bank * 0x2000 + ((addr - 0x4000) as usize);
-                        if offset < self.prg_rom.len() {
-                            self.prg_rom[offset]
-                        } else {
-                            0
-                        }
-                    }
-                    0x6000..=0x7FFF => {
-                        // Last bank: fixed to last 8KB
-                        let last_bank = (self.prg_rom.len() / 0x2000) - 1;
-                        let offset = last_bank * 0x2000 + ((addr - 0x6000) as usize);
-                        if offset < self.prg_rom.len() {
-                            self.prg_rom[offset]
-                        } else {
-                            0
-                        }
-                    }
-                    _ => 0
-                }
-            }
-            _ => {
-                // Basic fallback for unsupported mappers
-                // Treat as 32KB ROM with simple mirroring for smaller ROMs
-                if self.prg_rom.is_empty() {
-                    return 0;
-                }
-                
-                let rom_size = self.prg_rom.len();
-                if rom_size <= 0x4000 {
-                    // 16KB or smaller: mirror across the entire range
-                    self.prg_rom[(addr as usize) % rom_size]
-                } else if rom_size <= 0x8000 {
-                    // 32KB: direct mapping with bounds checking
-                    if (addr as usize) < rom_size {
-                        self.prg_rom[addr as usize]
-                    } else {
-                        // Mirror the last bank
-                        self.prg_rom[((addr as usize) % 0x4000) + rom_size - 0x4000]
-                    }
-                } else {
-                    // Larger ROMs: map the last 32KB to 0x8000-0xFFFF range
-                    // This gives the best chance of hitting the reset vector
-                    let bank_offset = rom_size - 0x8000;
-                    let mapped_addr = (addr as usize) + bank_offset;
-                    if mapped_addr < rom_size {
-                        self.prg_rom[mapped_addr]
-                    } else {
-                        0
-                    }
-                }
-            }
-        }
+    pub fn read_prg(&mut self, addr: u16) -> u8 {
+        self.board.read_prg(addr)
     }
 
     pub fn write_prg(&mut self, addr: u16, value: u8) {
-        match self.mapper {
-            0 => {
-                log::warn!("Attempting to write to ROM at {:04X}", addr);
-            }
-            1 => {
-                // MMC1 Register writes
-                if value & 0x80 != 0 {
-                    // Reset sequence
-                    self.mmc1_shift_register = 0;
-                    self.mmc1_shift_count = 0;
-                    self.mmc1_control |= 0x0C; // Set to mode 3
-                } else {
-                    // Normal write
-                    self.mmc1_shift_register = (self.mmc1_shift_register >> 1) | ((value & 1) << 4);
-                    self.mmc1_shift_count += 1;
-                    
-                    if self.mmc1_shift_count == 5 {
-                        // Complete write
-                        match addr & 0x6000 {
-                            0x0000 => {
-                                // Control register ($8000-$9FFF)
-                                self.mmc1_control = self.mmc1_shift_register;
-                            }
-                            0x2000 => {
-                                // CHR bank 0 ($A000-$BFFF)
-                                self.mmc1_chr_bank_0 = self.mmc1_shift_register;
-                            }
-                            0x4000 => {
-                                // CHR bank 1 ($C000-$DFFF)
-                                self.mmc1_chr_bank_1 = self.mmc1_shift_register;
-                            }
-                            0x6000 => {
-                                // PRG bank ($E000-$FFFF)
-                                self.mmc1_prg_bank = self.mmc1_shift_register & 0x0F;
-                            }
-                            _ => {}
-                        }
-                        self.mmc1_shift_register = 0;
-                        self.mmc1_shift_count = 0;
-                    }
-                }
-            }
-            65 => {
-                // Mapper 65 Register writes
-                match addr {
-                    0x0000 => self.m65_prg_banks[0] = value,
-                    0x2000 => self.m65_prg_banks[1] = value,
-                    0x4000 => self.m65_prg_banks[2] = value,
-                    0x1000 => self.m65_chr_banks[0] = value,
-                    0x1001 => self.m65_chr_banks[1] = value,
-                    0x1002 => self.m65_chr_banks[2] = value,
-                    0x1003 => self.m65_chr_banks[3] = value,
-                    0x1004 => self.m65_chr_banks[4] = value,
-                    0x1005 => self.m65_chr_banks[5] = value,
-                    0x1006 => self.m65_chr_banks[6] = value,
-                    0x1007 => self.m65_chr_banks[7] = value,
-                    _ => {}
-                }
-            }
-            _ => {
-                log::warn!("Unsupported mapper: {}", self.mapper);
-            }
+        self.board.write_prg(addr, value);
+    }
+
+    pub fn read_chr(&mut self, addr: u16) -> u8 {
+        self.board.notify_ppu_addr(addr);
+        self.board.read_chr(addr)
+    }
+
+    /// Pattern read the board doesn't see, for fetches outside the PPU's real sequence.
+    pub fn peek_chr(&mut self, addr: u16) -> u8 {
+        self.board.peek_chr(addr)
+    }
+
+    pub fn write_chr(&mut self, addr: u16, value: u8) {
+        self.board.notify_ppu_addr(addr);
+        self.board.write_chr(addr, value);
+    }
+
+    pub fn read_nametable(&mut self, addr: u16, ciram: &[u8; 0x800]) -> u8 {
+        self.board.notify_ppu_addr(addr);
+        if self.four_screen_vram.is_empty() {
+            return self.board.read_nametable(addr, ciram);
+        }
+        // Four-screen boards wire the first pair of nametables straight to CIRAM and the second
+        // to their own VRAM, whatever mirroring the mapper asks for
+        let offset = addr as usize & 0x7FF;
+        if addr & 0x0800 == 0 {
+            ciram[offset]
+        } else {
+            self.four_screen_vram[offset]
         }
     }
 
-    pub fn _read_chr(&self, addr: u16) -> u8 {
-        if self.chr_rom.is_empty() {
-            return 0;
+    pub fn write_nametable(&mut self, addr: u16, value: u8, ciram: &mut [u8; 0x800]) {
+        self.board.notify_ppu_addr(addr);
+        if self.four_screen_vram.is_empty() {
+            return self.board.write_nametable(addr, value, ciram);
         }
-        
-        match self.mapper {
-            0 => {
-                self.chr_rom[(addr & 0x1FFF) as usize]
-            }
-            _ => {
-                log::warn!("Unsupported mapper: {}", self.mapper);
-                0
-            }
+        let offset = addr as usize & 0x7FF;
+        if addr & 0x0800 == 0 {
+            ciram[offset] = value;
+        } else {
+            self.four_screen_vram[offset] = value;
         }
     }
 
-    pub fn _write_chr(&mut self, addr: u16, value: u8) {
-        if self.chr_rom.is_empty() {
-            return;
+    pub fn get_mirroring(&self) -> Mirroring {
+        // Four-screen boards hardwire their own VRAM regardless of the mapper
+        match self.header.mirroring {
+            Mirroring::FourScreen => Mirroring::FourScreen,
+            _ => self.board.mirroring(),
         }
-        
-        match self.mapper {
-            0 => {
-                if self.chr_rom.len() == 0x2000 {
-                    self.chr_rom[(addr & 0x1FFF) as usize] = value;
-                }
-            }
-            _ => {
-                log::warn!("Unsupported mapper: {}", self.mapper);
-            }
+    }
+
+    pub fn irq_pending(&self) -> bool {
+        self.board.irq_pending()
+    }
+
+    pub fn clock_scanline(&mut self) {
+        self.board.clock_scanline();
+    }
+
+    pub fn notify_ppu_state(&mut self, rendering: bool) {
+        self.board.notify_ppu_state(rendering);
+    }
+
+    pub fn notify_ppu_addr(&mut self, addr: u16) {
+        self.board.notify_ppu_addr(addr);
+    }
+
+    pub fn clock_cpu(&mut self) {
+        self.board.clock_cpu();
+    }
+
+    /// Contents of battery-backed RAM, or None if the cartridge has no battery.
+    pub fn battery_ram(&self) -> Option<&[u8]> {
+        if self.battery_backed {
+            self.board.prg_ram()
+        } else {
+            None
         }
     }
 
-    pub fn _mirror_vram_addr(&self, addr: u16) -> u16 {
-        let mirrored_addr = addr & 0x2FFF;
-        let table_index = (mirrored_addr - 0x2000) / 0x0400;
-        
-        match self._mirroring {
-            Mirroring::Vertical => {
-                match table_index {
-                    0 | 2 => 0x2000 + (mirrored_addr & 0x03FF),
-                    1 | 3 => 0x2400 + (mirrored_addr & 0x03FF),
-                    _ => mirrored_addr,
-                }
-            }
-            Mirroring::Horizontal => {
-                match table_index {
-                    0 | 1 => 0x2000 + (mirrored_addr & 0x03FF),
-                    2 | 3 => 0x2400 + (mirrored_addr & 0x03FF),
-                    _ => mirrored_addr,
-                }
+    /// Restore battery-backed RAM from a save file. A file of the wrong size is still loaded
+    /// as far as it goes, since some emulators pad or trim their saves.
+    pub fn load_battery_ram(&mut self, data: &[u8]) {
+        if !self.battery_backed {
+            return;
+        }
+        if let Some(ram) = self.board.prg_ram_mut() {
+            if data.len() != ram.len() {
+                log::warn!("Save file is {} bytes, expected {}", data.len(), ram.len());
             }
-            Mirroring::FourScreen => mirrored_addr,
-            Mirroring::_SingleScreenLower => 0x2000 + (mirrored_addr & 0x03FF),
-            Mirroring::_SingleScreenUpper => 0x2400 + (mirrored_addr & 0x03FF),
+            let len = data.len().min(ram.len());
+            ram[..len].copy_from_slice(&data[..len]);
         }
     }
-}
\ No newline at end of file
+
+    /// CRC32 of the PRG and CHR ROM, used to match save states to their game.
+    pub fn rom_crc32(&self) -> u32 {
+        let mut rom = self.prg_rom.clone();
+        rom.extend_from_slice(&self.chr_rom);
+        state::crc32(&rom)
+    }
+
+    pub fn save_state(&self, state: &mut StateWriter) {
+        self.board.save_state(state);
+        state.write_bytes(&self.four_screen_vram);
+    }
+
+    pub fn load_state(&mut self, state: &mut StateReader) -> Result<()> {
+        self.board.load_state(state)?;
+        state.read_into(&mut self.four_screen_vram)
+    }
+}
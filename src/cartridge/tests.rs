@@ -0,0 +1,734 @@
+// Mapper tests against synthetic iNES images
+
+use super::{Cartridge, Mirroring};
+
+/// Build an iNES image whose PRG banks (16KB) and CHR banks (8KB) are filled with their bank number.
+fn rom(mapper: u8, prg_banks: u8, chr_banks: u8, flags_6: u8) -> Vec<u8> {
+    let mut data = vec![0x4E, 0x45, 0x53, 0x1A, prg_banks, chr_banks, flags_6 | (mapper << 4), mapper & 0xF0];
+    data.resize(16, 0);
+    for bank in 0..prg_banks {
+        data.extend(std::iter::repeat(bank).take(0x4000));
+    }
+    for bank in 0..chr_banks {
+        data.extend(std::iter::repeat(bank).take(0x2000));
+    }
+    data
+}
+
+#[test]
+fn battery_ram_round_trips_through_mmc3() {
+    let mut cart = Cartridge::load_from_bytes(&rom(4, 2, 1, 0x02)).unwrap();
+    // Enable PRG RAM and store a byte
+    cart.write_prg(0xA001, 0x80);
+    cart.write_prg(0x6123, 0x5A);
+    let saved = cart.battery_ram().unwrap().to_vec();
+    assert_eq!(saved.len(), 0x2000);
+    assert_eq!(saved[0x123], 0x5A);
+
+    let mut reloaded = Cartridge::load_from_bytes(&rom(4, 2, 1, 0x02)).unwrap();
+    reloaded.load_battery_ram(&saved);
+    reloaded.write_prg(0xA001, 0x80);
+    assert_eq!(reloaded.read_prg(0x6123), 0x5A);
+}
+
+#[test]
+fn cartridges_without_a_battery_have_no_save() {
+    let cart = Cartridge::load_from_bytes(&rom(0, 1, 1, 0)).unwrap();
+    assert!(cart.battery_ram().is_none());
+}
+
+/// NES 2.0 image with 8KB of CHR ROM and a PRG ROM size in exponent-multiplier form.
+fn rom_with_prg_size(mapper: u8, prg_size: u8, prg_len: usize) -> Vec<u8> {
+    let mut data = vec![0x4E, 0x45, 0x53, 0x1A, prg_size, 1, (mapper & 0x0F) << 4, (mapper & 0xF0) | 0x08, 0, 0x0F];
+    data.resize(16, 0);
+    data.extend((0..prg_len).map(|i| i as u8));
+    data.extend(vec![0; 0x2000]);
+    data
+}
+
+#[test]
+fn prg_rom_smaller_than_the_banking_windows_loads_and_reads() {
+    assert!(Cartridge::load_from_bytes(&rom(0, 0, 1, 0)).is_err());
+
+    // 3KB, 8KB and 16KB: 2^10 * 3, 2^13 and 2^14 bytes
+    for (prg_size, prg_len) in [(0x29, 0xC00), (0x34, 0x2000), (0x38, 0x4000)] {
+        for mapper in [0, 2, 3, 4, 7, 9, 10, 11, 16, 19, 21, 22, 23, 24, 25, 26, 34, 65, 66, 69, 71, 75, 85] {
+            let mut cart = Cartridge::load_from_bytes(&rom_with_prg_size(mapper, prg_size, prg_len)).unwrap();
+            // Every window, fixed or switched to an out-of-range bank, reads from the image
+            cart.read_prg(0xFFFC);
+            for addr in (0x8000..=0xFFFFu32).step_by(0x1000) {
+                cart.write_prg(addr as u16, 0xFF);
+            }
+            for addr in (0x8000..=0xFFFFu32).step_by(0x100) {
+                cart.read_prg(addr as u16);
+            }
+        }
+    }
+}
+
+/// Load an MMC1 register through its five-write serial port, leaving a cycle between writes.
+fn mmc1_write(cart: &mut Cartridge, addr: u16, value: u8) {
+    for bit in 0..5 {
+        cart.clock_cpu();
+        cart.clock_cpu();
+        cart.write_prg(addr, (value >> bit) & 1);
+    }
+}
+
+#[test]
+fn chr_ram_size_comes_from_the_nes2_header() {
+    // NES 2.0 MMC1 board with no CHR ROM and 32KB of CHR RAM (64 << 9)
+    let mut data = rom(1, 2, 0, 0);
+    data[7] |= 0x08;
+    data[11] = 0x09;
+    let mut cart = Cartridge::load_from_bytes(&data).unwrap();
+
+    // 4KB CHR mode, then fill the last 4KB bank through $0000
+    mmc1_write(&mut cart, 0x8000, 0x1C);
+    mmc1_write(&mut cart, 0xA000, 7);
+    cart.write_chr(0x0010, 0xA7);
+    mmc1_write(&mut cart, 0xA000, 0);
+    cart.write_chr(0x0010, 0xA0);
+    assert_eq!(cart.read_chr(0x0010), 0xA0);
+
+    // The same bank is visible through $1000 once selected there
+    mmc1_write(&mut cart, 0xC000, 7);
+    assert_eq!(cart.read_chr(0x1010), 0xA7);
+
+    let mut state = crate::state::StateWriter::new();
+    cart.save_state(&mut state);
+    let state = state.into_bytes();
+    let mut reloaded = Cartridge::load_from_bytes(&data).unwrap();
+    reloaded.load_state(&mut crate::state::StateReader::new(&state)).unwrap();
+    assert_eq!(reloaded.read_chr(0x0010), 0xA0);
+    assert_eq!(reloaded.read_chr(0x1010), 0xA7);
+}
+
+#[test]
+fn chr_rom_ignores_writes() {
+    let mut cart = Cartridge::load_from_bytes(&rom(0, 1, 1, 0)).unwrap();
+    cart.write_chr(0x0123, 0x55);
+    assert_eq!(cart.read_chr(0x0123), 0);
+}
+
+#[test]
+fn mmc1_controls_mirroring() {
+    let mut cart = Cartridge::load_from_bytes(&rom(1, 2, 1, 0x01)).unwrap();
+    assert_eq!(cart.get_mirroring(), Mirroring::Vertical);
+    for (control, expected) in [
+        (0x0C, Mirroring::SingleScreenLower),
+        (0x0D, Mirroring::SingleScreenUpper),
+        (0x0E, Mirroring::Vertical),
+        (0x0F, Mirroring::Horizontal),
+    ] {
+        mmc1_write(&mut cart, 0x8000, control);
+        assert_eq!(cart.get_mirroring(), expected);
+    }
+}
+
+#[test]
+fn mmc1_ignores_writes_on_consecutive_cycles() {
+    let mut cart = Cartridge::load_from_bytes(&rom(1, 4, 1, 0)).unwrap();
+    // Like INC $E000 writing twice: the second write lands on the next cycle and is dropped
+    for value in [1, 0, 0, 0, 0] {
+        cart.clock_cpu();
+        cart.clock_cpu();
+        cart.write_prg(0xE000, value);
+        cart.clock_cpu();
+        cart.write_prg(0xE000, 0);
+    }
+    assert_eq!(cart.read_prg(0x8000), 1);
+}
+
+#[test]
+fn mmc1_prg_ram_can_be_disabled() {
+    let mut cart = Cartridge::load_from_bytes(&rom(1, 2, 1, 0)).unwrap();
+    cart.write_prg(0x6000, 0x42);
+    assert_eq!(cart.read_prg(0x6000), 0x42);
+    mmc1_write(&mut cart, 0xE000, 0x10);
+    cart.write_prg(0x6000, 0x24);
+    assert_eq!(cart.read_prg(0x6000), 0);
+    mmc1_write(&mut cart, 0xE000, 0x00);
+    assert_eq!(cart.read_prg(0x6000), 0x42);
+}
+
+#[test]
+fn surom_selects_the_outer_prg_bank_with_chr_bank_bit_4() {
+    let mut cart = Cartridge::load_from_bytes(&rom(1, 32, 0, 0)).unwrap();
+    mmc1_write(&mut cart, 0xE000, 3);
+    assert_eq!(cart.read_prg(0x8000), 3);
+    assert_eq!(cart.read_prg(0xC000), 15);
+    mmc1_write(&mut cart, 0xA000, 0x10);
+    assert_eq!(cart.read_prg(0x8000), 19);
+    assert_eq!(cart.read_prg(0xC000), 31);
+}
+
+#[test]
+fn mmc1_routes_nametables_to_one_ciram_page() {
+    let mut cart = Cartridge::load_from_bytes(&rom(1, 2, 1, 0)).unwrap();
+    let mut ciram = [0; 0x800];
+    mmc1_write(&mut cart, 0x8000, 0x0D);
+    cart.write_nametable(0x2005, 0x11, &mut ciram);
+    assert_eq!(ciram[0x405], 0x11);
+    assert_eq!(cart.read_nametable(0x2C05, &ciram), 0x11);
+    mmc1_write(&mut cart, 0x8000, 0x0C);
+    assert_eq!(cart.read_nametable(0x2C05, &ciram), 0);
+}
+
+#[test]
+fn four_screen_boards_supply_the_upper_nametables() {
+    let mut cart = Cartridge::load_from_bytes(&rom(4, 2, 1, 0x08)).unwrap();
+    let mut ciram = [0; 0x800];
+    // MMC3 horizontal mirroring must not fold $2400 onto $2000
+    cart.write_prg(0xA000, 1);
+    let tables = [0x2000, 0x2400, 0x2800, 0x2C00];
+    for (value, addr) in (1..).zip(tables) {
+        cart.write_nametable(addr, value, &mut ciram);
+    }
+    for (value, addr) in (1..).zip(tables) {
+        assert_eq!(cart.read_nametable(addr, &ciram), value);
+    }
+    assert_eq!((ciram[0], ciram[0x400]), (1, 2));
+}
+
+#[test]
+fn mmc5_supplies_exram_and_fill_mode_nametables() {
+    let mut cart = Cartridge::load_from_bytes(&rom(5, 2, 1, 0)).unwrap();
+    let mut ciram = [0; 0x800];
+    // $2000: CIRAM page 1, $2400: ExRAM, $2800/$2C00: fill mode
+    cart.write_prg(0x5104, 0x00);
+    cart.write_prg(0x5105, 0xF9);
+    cart.write_prg(0x5106, 0x42);
+    cart.write_prg(0x5107, 0x02);
+
+    cart.write_nametable(0x2010, 0x33, &mut ciram);
+    assert_eq!(ciram[0x410], 0x33);
+    cart.write_nametable(0x2410, 0x44, &mut ciram);
+    assert_eq!(cart.read_nametable(0x2410, &ciram), 0x44);
+    assert_eq!(cart.read_nametable(0x2810, &ciram), 0x42);
+    assert_eq!(cart.read_nametable(0x2FC0, &ciram), 0xAA);
+}
+
+/// Mark an image as NES 2.0 with the given submapper.
+fn with_submapper(mut data: Vec<u8>, submapper: u8) -> Vec<u8> {
+    data[7] |= 0x08;
+    data[8] = submapper << 4;
+    data
+}
+
+#[test]
+fn uxrom_switches_the_low_bank_with_bus_conflicts() {
+    let mut cart = Cartridge::load_from_bytes(&rom(2, 8, 0, 0)).unwrap();
+    // $C000 holds 7 (the fixed last bank), so 5 survives the AND
+    cart.write_prg(0xC000, 5);
+    assert_eq!((cart.read_prg(0x8000), cart.read_prg(0xC000)), (5, 7));
+    // $8000 now holds 5: writing 2 ends up selecting bank 0
+    cart.write_prg(0x8000, 2);
+    assert_eq!(cart.read_prg(0x8000), 0);
+
+    let mut cart = Cartridge::load_from_bytes(&with_submapper(rom(2, 8, 0, 0), 1)).unwrap();
+    cart.write_prg(0x8000, 2);
+    assert_eq!(cart.read_prg(0x8000), 2);
+}
+
+#[test]
+fn cnrom_switches_chr_with_bus_conflicts() {
+    // $C000 holds 1, so writing 3 selects CHR bank 1
+    let mut cart = Cartridge::load_from_bytes(&rom(3, 2, 4, 0)).unwrap();
+    cart.write_prg(0xC000, 3);
+    assert_eq!(cart.read_chr(0x0000), 1);
+
+    let mut cart = Cartridge::load_from_bytes(&with_submapper(rom(3, 2, 4, 0), 1)).unwrap();
+    cart.write_prg(0xC000, 3);
+    assert_eq!(cart.read_chr(0x1FFF), 3);
+}
+
+#[test]
+fn axrom_switches_32kb_and_selects_a_single_screen() {
+    let mut cart = Cartridge::load_from_bytes(&rom(7, 8, 0, 0)).unwrap();
+    assert_eq!(cart.get_mirroring(), Mirroring::SingleScreenLower);
+    cart.write_prg(0x8000, 0x12);
+    assert_eq!((cart.read_prg(0x8000), cart.read_prg(0xC000)), (4, 5));
+    assert_eq!(cart.get_mirroring(), Mirroring::SingleScreenUpper);
+}
+
+#[test]
+fn color_dreams_switches_prg_and_chr_with_bus_conflicts() {
+    let mut data = rom(11, 4, 4, 0);
+    data[16 + 0x100] = 0xFF;
+    let mut cart = Cartridge::load_from_bytes(&data).unwrap();
+    cart.write_prg(0x8100, 0x31);
+    assert_eq!((cart.read_prg(0x8000), cart.read_chr(0x0000)), (2, 3));
+    // $8000 now holds 2, masking the write down to bank 0
+    cart.write_prg(0x8000, 0x31);
+    assert_eq!((cart.read_prg(0x8000), cart.read_chr(0x0000)), (0, 0));
+}
+
+#[test]
+fn gxrom_switches_prg_and_chr() {
+    let mut data = rom(66, 4, 4, 0);
+    data[16 + 0x100] = 0xFF;
+    let mut cart = Cartridge::load_from_bytes(&data).unwrap();
+    cart.write_prg(0x8100, 0x13);
+    assert_eq!((cart.read_prg(0x8000), cart.read_chr(0x0000)), (2, 3));
+}
+
+#[test]
+fn mapper_34_supports_bnrom_and_nina001() {
+    let mut data = rom(34, 4, 0, 0);
+    data[16 + 0x100] = 0xFF;
+    let mut bnrom = Cartridge::load_from_bytes(&data).unwrap();
+    bnrom.write_prg(0x8100, 1);
+    assert_eq!(bnrom.read_prg(0x8000), 2);
+
+    let mut nina = Cartridge::load_from_bytes(&rom(34, 4, 2, 0)).unwrap();
+    nina.write_prg(0x7FFD, 1);
+    nina.write_prg(0x7FFE, 3);
+    nina.write_prg(0x7FFF, 0);
+    assert_eq!(nina.read_prg(0x8000), 2);
+    assert_eq!((nina.read_chr(0x0000), nina.read_chr(0x1000)), (1, 0));
+    assert_eq!(nina.read_prg(0x7FFD), 1);
+}
+
+#[test]
+fn camerica_switches_prg_and_fire_hawk_mirroring() {
+    let mut cart = Cartridge::load_from_bytes(&rom(71, 8, 0, 0)).unwrap();
+    cart.write_prg(0xC000, 3);
+    assert_eq!((cart.read_prg(0x8000), cart.read_prg(0xC000)), (3, 7));
+    cart.write_prg(0x9000, 0x10);
+    assert_eq!(cart.get_mirroring(), Mirroring::SingleScreenUpper);
+}
+
+#[test]
+fn vrc1_switches_prg_chr_and_mirroring() {
+    let mut cart = Cartridge::load_from_bytes(&rom(75, 4, 4, 0)).unwrap();
+    cart.write_prg(0x8000, 3);
+    cart.write_prg(0xE000, 5);
+    cart.write_prg(0x9000, 0x05);
+    // 8KB PRG bank 3 lies in 16KB bank 1 and 4KB CHR bank 5 in 8KB bank 2;
+    // $9000 bit 2 turns CHR bank 1 into bank 17, which wraps around to bank 1
+    assert_eq!(cart.read_prg(0x8000), 1);
+    assert_eq!(cart.read_chr(0x0000), 2);
+    assert_eq!(cart.read_chr(0x1000), 0);
+    assert_eq!(cart.get_mirroring(), Mirroring::Horizontal);
+}
+
+#[test]
+fn vrc4_decodes_registers_and_swaps_prg() {
+    // Mapper 21 without a submapper decodes VRC4a (A1/A2) and VRC4c (A6/A7) lines together
+    let mut cart = Cartridge::load_from_bytes(&rom(21, 8, 8, 0)).unwrap();
+    cart.write_prg(0x8000, 5);
+    assert_eq!(cart.read_prg(0x8000), 2);
+    cart.write_prg(0x9004, 0x02);
+    assert_eq!((cart.read_prg(0x8000), cart.read_prg(0xC000)), (7, 2));
+
+    // CHR bank 0 = $14: low nibble through $B000, high bits through $B002 (VRC4a) or $B040 (VRC4c)
+    cart.write_prg(0xB000, 0x04);
+    cart.write_prg(0xB002, 0x01);
+    assert_eq!(cart.read_chr(0x0000), 2);
+    cart.write_prg(0xB040, 0x00);
+    assert_eq!(cart.read_chr(0x0000), 0);
+
+    cart.write_prg(0x9000, 0x03);
+    assert_eq!(cart.get_mirroring(), Mirroring::SingleScreenUpper);
+}
+
+#[test]
+fn vrc2a_ignores_the_low_chr_bank_bit() {
+    let mut cart = Cartridge::load_from_bytes(&rom(22, 8, 8, 0)).unwrap();
+    // $B002 is the high half of CHR bank 0 on VRC2a's swapped select lines
+    cart.write_prg(0xB002, 0x01);
+    assert_eq!(cart.read_chr(0x0000), 1);
+}
+
+#[test]
+fn vrc_irq_counts_cpu_cycles() {
+    let mut cart = Cartridge::load_from_bytes(&rom(21, 8, 8, 0)).unwrap();
+    // Latch $FE, enabled in cycle mode
+    cart.write_prg(0xF000, 0x0E);
+    cart.write_prg(0xF002, 0x0F);
+    cart.write_prg(0xF004, 0x06);
+    cart.clock_cpu();
+    assert!(!cart.irq_pending());
+    cart.clock_cpu();
+    assert!(cart.irq_pending());
+    cart.write_prg(0xF006, 0);
+    assert!(!cart.irq_pending());
+}
+
+#[test]
+fn vrc_irq_scanline_mode_uses_the_prescaler() {
+    let mut cart = Cartridge::load_from_bytes(&rom(24, 8, 8, 0)).unwrap();
+    cart.write_prg(0xF000, 0xFF);
+    cart.write_prg(0xF001, 0x02);
+    // 341 dots at 3 per CPU cycle
+    for _ in 0..113 {
+        cart.clock_cpu();
+    }
+    assert!(!cart.irq_pending());
+    cart.clock_cpu();
+    assert!(cart.irq_pending());
+}
+
+#[test]
+fn vrc6_switches_banks_and_enables_prg_ram() {
+    let mut cart = Cartridge::load_from_bytes(&rom(24, 8, 8, 0)).unwrap();
+    cart.write_prg(0x8000, 2);
+    cart.write_prg(0xC000, 7);
+    cart.write_prg(0xD000, 9);
+    assert_eq!((cart.read_prg(0x8000), cart.read_prg(0xC000), cart.read_prg(0xE000)), (2, 3, 7));
+    assert_eq!(cart.read_chr(0x0000), 1);
+
+    cart.write_prg(0xB003, 0xA4);
+    assert_eq!(cart.get_mirroring(), Mirroring::Horizontal);
+    cart.write_prg(0x6000, 0x5A);
+    assert_eq!(cart.read_prg(0x6000), 0x5A);
+
+    // Mapper 26 swaps A0 and A1, so $D001 selects the third CHR bank
+    let mut cart = Cartridge::load_from_bytes(&rom(26, 8, 8, 0)).unwrap();
+    cart.write_prg(0xD001, 9);
+    assert_eq!((cart.read_chr(0x0400), cart.read_chr(0x0800)), (0, 1));
+}
+
+#[test]
+fn vrc7_switches_banks_and_raises_irqs() {
+    let mut cart = Cartridge::load_from_bytes(&rom(85, 8, 8, 0)).unwrap();
+    cart.write_prg(0x8000, 3);
+    cart.write_prg(0x8010, 5);
+    cart.write_prg(0x9000, 6);
+    assert_eq!((cart.read_prg(0x8000), cart.read_prg(0xA000), cart.read_prg(0xC000)), (1, 2, 3));
+    cart.write_prg(0xA008, 9);
+    assert_eq!(cart.read_chr(0x0400), 1);
+    cart.write_prg(0xE000, 0x81);
+    assert_eq!(cart.get_mirroring(), Mirroring::Horizontal);
+
+    cart.write_prg(0xE010, 0xFF);
+    cart.write_prg(0xF000, 0x06);
+    cart.clock_cpu();
+    assert!(cart.irq_pending());
+    cart.write_prg(0xF010, 0);
+    assert!(!cart.irq_pending());
+}
+
+#[test]
+fn irem_h3001_switches_mirroring_and_counts_down_cpu_cycles() {
+    let mut cart = Cartridge::load_from_bytes(&rom(65, 8, 8, 0)).unwrap();
+    cart.write_prg(0x9001, 0x80);
+    assert_eq!(cart.get_mirroring(), Mirroring::Horizontal);
+    cart.write_prg(0xB003, 9);
+    assert_eq!(cart.read_chr(0x0C00), 1);
+
+    // Reload value $0102, loaded into the counter by $9004
+    cart.write_prg(0x9005, 0x01);
+    cart.write_prg(0x9006, 0x02);
+    cart.write_prg(0x9004, 0);
+    cart.write_prg(0x9003, 0x80);
+    for _ in 0..0x101 {
+        cart.clock_cpu();
+    }
+    assert!(!cart.irq_pending());
+    cart.clock_cpu();
+    assert!(cart.irq_pending());
+    // Stopped at zero
+    cart.write_prg(0x9003, 0x80);
+    for _ in 0..0x200 {
+        cart.clock_cpu();
+    }
+    assert!(!cart.irq_pending());
+}
+
+#[test]
+fn irem_h3001_prg_layout_swaps_the_8000_and_c000_windows() {
+    // 8KB bank n holds n / 2, the 16KB bank number the rom() helper fills in
+    let mut cart = Cartridge::load_from_bytes(&rom(65, 8, 8, 0)).unwrap();
+    // $C000 starts at the second-to-last bank
+    assert_eq!((cart.read_prg(0x8000), cart.read_prg(0xC000), cart.read_prg(0xE000)), (0, 7, 7));
+
+    let mirroring = cart.get_mirroring();
+    cart.write_prg(0x8000, 4);
+    cart.write_prg(0xC000, 8);
+    assert_eq!((cart.read_prg(0x8000), cart.read_prg(0xC000)), (2, 4));
+    cart.write_prg(0x9000, 0x80);
+    assert_eq!((cart.read_prg(0x8000), cart.read_prg(0xC000)), (4, 2));
+    // Bit 7 of $9000 is not a mirroring bit
+    assert_eq!(cart.get_mirroring(), mirroring);
+
+    cart.write_prg(0x9000, 0x00);
+    assert_eq!((cart.read_prg(0x8000), cart.read_prg(0xC000)), (2, 4));
+}
+
+#[test]
+fn mmc2_latches_switch_chr_after_fetching_tiles_fd_and_fe() {
+    let mut cart = Cartridge::load_from_bytes(&rom(9, 8, 4, 0)).unwrap();
+    cart.write_prg(0xA000, 3);
+    assert_eq!((cart.read_prg(0x8000), cart.read_prg(0xA000)), (1, 6));
+    // Lower half: bank 2 for $FD, bank 4 for $FE; upper half: bank 6 for $FD, bank 0 for $FE
+    cart.write_prg(0xB000, 2);
+    cart.write_prg(0xC000, 4);
+    cart.write_prg(0xD000, 6);
+    cart.write_prg(0xE000, 0);
+
+    // Latches start at $FE
+    assert_eq!((cart.read_chr(0x0000), cart.read_chr(0x1000)), (2, 0));
+    // The triggering fetch still sees the old bank
+    assert_eq!(cart.read_chr(0x0FD8), 2);
+    assert_eq!(cart.read_chr(0x0000), 1);
+    // MMC2 only reacts to the exact address in the lower half, but to any row in the upper one
+    cart.read_chr(0x0FE9);
+    assert_eq!(cart.read_chr(0x0000), 1);
+    cart.read_chr(0x1FDD);
+    assert_eq!(cart.read_chr(0x1000), 3);
+}
+
+#[test]
+fn mmc4_switches_16kb_prg_and_latches_on_any_row() {
+    let mut cart = Cartridge::load_from_bytes(&rom(10, 8, 4, 0)).unwrap();
+    cart.write_prg(0xA000, 3);
+    assert_eq!((cart.read_prg(0x8000), cart.read_prg(0xC000)), (3, 7));
+    cart.write_prg(0xB000, 2);
+    cart.write_prg(0xC000, 4);
+    cart.read_chr(0x0FDB);
+    assert_eq!(cart.read_chr(0x0000), 1);
+    cart.write_prg(0x6000, 0x77);
+    assert_eq!(cart.read_prg(0x6000), 0x77);
+}
+
+/// One scanline's worth of A12 activity with sprites at $1000: background fetches keep A12
+/// low for the whole line, then the first sprite fetch raises it.
+fn mmc3_scanline(cart: &mut Cartridge) {
+    cart.read_chr(0x0000);
+    for _ in 0..113 {
+        cart.clock_cpu();
+    }
+    cart.read_chr(0x1000);
+}
+
+#[test]
+fn mmc3_counts_filtered_a12_rises() {
+    let mut cart = Cartridge::load_from_bytes(&rom(4, 2, 1, 0)).unwrap();
+    cart.write_prg(0xC000, 2);
+    cart.write_prg(0xC001, 0);
+    cart.write_prg(0xE001, 0);
+
+    // Reload on the first rise, then count down on the next two
+    mmc3_scanline(&mut cart);
+    mmc3_scanline(&mut cart);
+    assert!(!cart.irq_pending());
+    // Short dips between sprite fetches are filtered out
+    for _ in 0..8 {
+        cart.read_chr(0x0FF0);
+        cart.clock_cpu();
+        cart.read_chr(0x1FF0);
+    }
+    assert!(!cart.irq_pending());
+    mmc3_scanline(&mut cart);
+    assert!(cart.irq_pending());
+    cart.write_prg(0xE000, 0);
+    assert!(!cart.irq_pending());
+}
+
+#[test]
+fn mmc3a_only_fires_on_an_explicit_reload_to_zero() {
+    for (submapper, repeats) in [(0, true), (4, false)] {
+        let mut cart = Cartridge::load_from_bytes(&with_submapper(rom(4, 2, 1, 0), submapper)).unwrap();
+        cart.write_prg(0xC000, 0);
+        cart.write_prg(0xC001, 0);
+        cart.write_prg(0xE001, 0);
+        mmc3_scanline(&mut cart);
+        assert!(cart.irq_pending());
+        cart.write_prg(0xE000, 0);
+        cart.write_prg(0xE001, 0);
+        mmc3_scanline(&mut cart);
+        assert_eq!(cart.irq_pending(), repeats);
+    }
+}
+
+#[test]
+fn mmc6_protects_each_half_of_its_internal_ram() {
+    let mut cart = Cartridge::load_from_bytes(&with_submapper(rom(4, 2, 1, 0x02), 1)).unwrap();
+    // $A001 is ignored until $8000 bit 5 enables the RAM
+    cart.write_prg(0xA001, 0xF0);
+    cart.write_prg(0x8000, 0x20);
+    cart.write_prg(0x7000, 0x11);
+    assert_eq!(cart.read_prg(0x7000), 0);
+
+    cart.write_prg(0xA001, 0xF0);
+    cart.write_prg(0x7000, 0x11);
+    cart.write_prg(0x7E00, 0x22);
+    assert_eq!((cart.read_prg(0x7400), cart.read_prg(0x7200)), (0x11, 0x22));
+    // Upper half read-only, lower half disabled
+    cart.write_prg(0xA001, 0x80);
+    cart.write_prg(0x7200, 0x33);
+    assert_eq!((cart.read_prg(0x7000), cart.read_prg(0x7200)), (0, 0x22));
+    assert_eq!(cart.battery_ram().unwrap().len(), 0x400);
+}
+
+#[test]
+fn fme7_switches_banks_maps_ram_and_counts_down_cpu_cycles() {
+    let mut cart = Cartridge::load_from_bytes(&rom(69, 8, 4, 0)).unwrap();
+    cart.write_prg(0x8000, 0x09);
+    cart.write_prg(0xA000, 5);
+    assert_eq!((cart.read_prg(0x8000), cart.read_prg(0xE000)), (2, 7));
+    // $6000 maps ROM, then enabled RAM
+    cart.write_prg(0x8000, 0x08);
+    cart.write_prg(0xA000, 0x03);
+    assert_eq!(cart.read_prg(0x6000), 1);
+    cart.write_prg(0xA000, 0xC0);
+    cart.write_prg(0x6000, 0x42);
+    assert_eq!(cart.read_prg(0x6000), 0x42);
+    cart.write_prg(0x8000, 0x0C);
+    cart.write_prg(0xA000, 0x01);
+    assert_eq!(cart.get_mirroring(), Mirroring::Horizontal);
+
+    cart.write_prg(0x8000, 0x0E);
+    cart.write_prg(0xA000, 2);
+    cart.write_prg(0x8000, 0x0F);
+    cart.write_prg(0xA000, 0);
+    cart.write_prg(0x8000, 0x0D);
+    cart.write_prg(0xA000, 0x81);
+    // 2 -> 1 -> 0 -> $FFFF
+    for _ in 0..2 {
+        cart.clock_cpu();
+    }
+    assert!(!cart.irq_pending());
+    cart.clock_cpu();
+    assert!(cart.irq_pending());
+    cart.write_prg(0xA000, 0x81);
+    assert!(!cart.irq_pending());
+}
+
+#[test]
+fn namco163_maps_chr_rom_into_nametables_and_has_internal_ram() {
+    let mut cart = Cartridge::load_from_bytes(&rom(19, 8, 4, 0)).unwrap();
+    let mut ciram = [0; 0x800];
+    // CIRAM page 1, then 1KB CHR ROM bank 20 (in the 8KB bank holding 2s)
+    cart.write_prg(0xC000, 0xE1);
+    cart.write_prg(0xC800, 20);
+    cart.write_nametable(0x2005, 0x33, &mut ciram);
+    assert_eq!(ciram[0x405], 0x33);
+    assert_eq!(cart.read_nametable(0x2405, &ciram), 2);
+
+    // Auto-incrementing address port
+    cart.write_prg(0xF800, 0x80 | 0x7F);
+    cart.write_prg(0x4800, 0x11);
+    cart.write_prg(0x4800, 0x22);
+    cart.write_prg(0xF800, 0x7F);
+    assert_eq!(cart.read_prg(0x4800), 0x11);
+    cart.write_prg(0xF800, 0x00);
+    assert_eq!(cart.read_prg(0x4800), 0x22);
+
+    // PRG RAM writes need $4x in $F800 with the block's protect bit clear
+    cart.write_prg(0x6000, 0x55);
+    assert_eq!(cart.read_prg(0x6000), 0);
+    cart.write_prg(0xF800, 0x40);
+    cart.write_prg(0x6000, 0x55);
+    assert_eq!(cart.read_prg(0x6000), 0x55);
+}
+
+#[test]
+fn namco163_irq_counts_up_to_7fff() {
+    let mut cart = Cartridge::load_from_bytes(&rom(19, 8, 4, 0)).unwrap();
+    cart.write_prg(0x5000, 0xFD);
+    cart.write_prg(0x5800, 0xFF);
+    cart.clock_cpu();
+    assert!(!cart.irq_pending());
+    cart.clock_cpu();
+    assert!(cart.irq_pending());
+    cart.clock_cpu();
+    assert_eq!((cart.read_prg(0x5000), cart.read_prg(0x5800)), (0xFF, 0xFF));
+    cart.write_prg(0x5000, 0);
+    assert!(!cart.irq_pending());
+}
+
+#[test]
+fn bandai_fcg_switches_banks_and_counts_down_from_the_latch() {
+    let mut cart = Cartridge::load_from_bytes(&with_submapper(rom(16, 8, 4, 0), 5)).unwrap();
+    cart.write_prg(0x8008, 3);
+    cart.write_prg(0x8009, 1);
+    assert_eq!((cart.read_prg(0x8000), cart.read_prg(0xC000)), (3, 7));
+    assert_eq!(cart.get_mirroring(), Mirroring::Horizontal);
+    // Submapper 5 ignores the FCG register range
+    cart.write_prg(0x6008, 1);
+    assert_eq!(cart.read_prg(0x8000), 3);
+
+    cart.write_prg(0x800B, 2);
+    cart.write_prg(0x800C, 0);
+    cart.write_prg(0x800A, 1);
+    cart.clock_cpu();
+    assert!(!cart.irq_pending());
+    cart.clock_cpu();
+    assert!(cart.irq_pending());
+    cart.write_prg(0x800A, 0);
+    assert!(!cart.irq_pending());
+}
+
+/// Drive the EEPROM lines through $800D: bit 5 is SCL, bit 6 SDA.
+fn i2c(cart: &mut Cartridge, scl: bool, sda: bool) {
+    cart.write_prg(0x800D, (scl as u8) << 5 | (sda as u8) << 6);
+}
+
+fn i2c_start(cart: &mut Cartridge) {
+    i2c(cart, false, true);
+    i2c(cart, true, true);
+    i2c(cart, true, false);
+    i2c(cart, false, false);
+}
+
+fn i2c_stop(cart: &mut Cartridge) {
+    i2c(cart, false, false);
+    i2c(cart, true, false);
+    i2c(cart, true, true);
+}
+
+/// Clock out a byte most significant bit first and return whether it was acknowledged.
+fn i2c_send(cart: &mut Cartridge, byte: u8) -> bool {
+    for bit in (0..8).rev() {
+        let sda = byte >> bit & 0x01 != 0;
+        i2c(cart, false, sda);
+        i2c(cart, true, sda);
+        i2c(cart, false, sda);
+    }
+    i2c(cart, false, true);
+    i2c(cart, true, true);
+    let ack = cart.read_prg(0x6000) & 0x10 == 0;
+    i2c(cart, false, true);
+    ack
+}
+
+fn i2c_receive(cart: &mut Cartridge) -> u8 {
+    let mut byte = 0;
+    for _ in 0..8 {
+        i2c(cart, false, true);
+        i2c(cart, true, true);
+        byte = byte << 1 | (cart.read_prg(0x6000) >> 4 & 0x01);
+        i2c(cart, false, true);
+    }
+    // No acknowledge: end of the read
+    i2c(cart, true, true);
+    i2c(cart, false, true);
+    byte
+}
+
+#[test]
+fn bandai_24c02_eeprom_is_saved_like_battery_ram() {
+    let mut cart = Cartridge::load_from_bytes(&rom(16, 8, 4, 0)).unwrap();
+    i2c_start(&mut cart);
+    assert!(i2c_send(&mut cart, 0xA0));
+    assert!(i2c_send(&mut cart, 0x10));
+    assert!(i2c_send(&mut cart, 0x5A));
+    assert!(i2c_send(&mut cart, 0xC3));
+    i2c_stop(&mut cart);
+    let saved = cart.battery_ram().unwrap().to_vec();
+    assert_eq!((saved.len(), saved[0x10], saved[0x11]), (0x100, 0x5A, 0xC3));
+
+    let mut reloaded = Cartridge::load_from_bytes(&rom(16, 8, 4, 0)).unwrap();
+    reloaded.load_battery_ram(&saved);
+    // Random read: set the address, then restart in read mode
+    i2c_start(&mut reloaded);
+    assert!(i2c_send(&mut reloaded, 0xA0));
+    assert!(i2c_send(&mut reloaded, 0x11));
+    i2c_start(&mut reloaded);
+    assert!(i2c_send(&mut reloaded, 0xA1));
+    assert_eq!(i2c_receive(&mut reloaded), 0xC3);
+    i2c_stop(&mut reloaded);
+}
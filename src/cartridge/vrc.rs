@@ -0,0 +1,124 @@
+// Pieces shared by the Konami VRC mappers
+// VRC4, VRC6 and VRC7 have the same IRQ counter: an 8-bit up-counter that raises an IRQ when
+// it overflows, clocked either every CPU cycle or once per scanline by a prescaler that
+// approximates 341 PPU dots by counting down 3 per CPU cycle.
+
+use super::Mirroring;
+use crate::state::{StateReader, StateWriter};
+use std::io::Result;
+
+const PRESCALER_PERIOD: i16 = 341;
+
+/// The two-bit mirroring field of VRC4, VRC6 and VRC7.
+pub fn mirroring(value: u8) -> Mirroring {
+    match value & 0x03 {
+        0 => Mirroring::Vertical,
+        1 => Mirroring::Horizontal,
+        2 => Mirroring::SingleScreenLower,
+        _ => Mirroring::SingleScreenUpper,
+    }
+}
+
+pub struct VrcIrq {
+    latch: u8,
+    counter: u8,
+    prescaler: i16,
+    enabled: bool,
+    // Copied into `enabled` when the IRQ is acknowledged
+    enable_after_ack: bool,
+    cycle_mode: bool,
+    pending: bool,
+}
+
+impl VrcIrq {
+    pub fn new() -> Self {
+        VrcIrq {
+            latch: 0,
+            counter: 0,
+            prescaler: PRESCALER_PERIOD,
+            enabled: false,
+            enable_after_ack: false,
+            cycle_mode: false,
+            pending: false,
+        }
+    }
+
+    pub fn write_latch(&mut self, value: u8) {
+        self.latch = value;
+    }
+
+    /// VRC4 loads the latch a nibble at a time.
+    pub fn write_latch_low(&mut self, value: u8) {
+        self.latch = (self.latch & 0xF0) | (value & 0x0F);
+    }
+
+    pub fn write_latch_high(&mut self, value: u8) {
+        self.latch = (self.latch & 0x0F) | (value & 0x0F) << 4;
+    }
+
+    pub fn write_control(&mut self, value: u8) {
+        self.pending = false;
+        self.enable_after_ack = value & 0x01 != 0;
+        self.enabled = value & 0x02 != 0;
+        self.cycle_mode = value & 0x04 != 0;
+        if self.enabled {
+            self.counter = self.latch;
+            self.prescaler = PRESCALER_PERIOD;
+        }
+    }
+
+    pub fn acknowledge(&mut self) {
+        self.pending = false;
+        self.enabled = self.enable_after_ack;
+    }
+
+    pub fn pending(&self) -> bool {
+        self.pending
+    }
+
+    /// Advance by one CPU cycle.
+    pub fn clock(&mut self) {
+        if !self.enabled {
+            return;
+        }
+        if self.cycle_mode {
+            self.clock_counter();
+        } else {
+            self.prescaler -= 3;
+            if self.prescaler <= 0 {
+                self.prescaler += PRESCALER_PERIOD;
+                self.clock_counter();
+            }
+        }
+    }
+
+    fn clock_counter(&mut self) {
+        if self.counter == 0xFF {
+            self.counter = self.latch;
+            self.pending = true;
+        } else {
+            self.counter += 1;
+        }
+    }
+
+    pub fn save_state(&self, state: &mut StateWriter) {
+        state.write_u8(self.latch);
+        state.write_u8(self.counter);
+        state.write_u16(self.prescaler as u16);
+        state.write_bool(self.enabled);
+        state.write_bool(self.enable_after_ack);
+        state.write_bool(self.cycle_mode);
+        state.write_bool(self.pending);
+    }
+
+    pub fn load_state(&mut self, state: &mut StateReader) -> Result<()> {
+        self.latch = state.read_u8()?;
+        self.counter = state.read_u8()?;
+        self.prescaler = state.read_u16()? as i16;
+        self.enabled = state.read_bool()?;
+        self.enable_after_ack = state.read_bool()?;
+        self.cycle_mode = state.read_bool()?;
+        self.pending = state.read_bool()?;
+        Ok(())
+    }
+}
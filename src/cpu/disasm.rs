@@ -0,0 +1,169 @@
+// 6502 disassembler
+// Decodes instructions through any read function, so it sees whatever the mapper currently has banked in.
+
+use std::collections::BTreeMap;
+use std::fmt;
+
+use super::{AddressingMode, Mnemonic, OPCODES};
+use super::AddressingMode::*;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Instruction {
+    pub address: u16,
+    pub opcode: u8,
+    pub mnemonic: Mnemonic,
+    pub mode: AddressingMode,
+    /// Operand bytes as a little-endian value, zero when there are none.
+    pub operand: u16,
+    /// Length in bytes, opcode included.
+    pub size: u16,
+    pub official: bool,
+}
+
+impl Instruction {
+    /// The address the instruction refers to when it is known without register values:
+    /// zero page and absolute operands, and branch targets.
+    pub fn effective_address(&self) -> Option<u16> {
+        match self.mode {
+            ZeroPage | Absolute => Some(self.operand),
+            Relative => Some(
+                self.address
+                    .wrapping_add(2)
+                    .wrapping_add(self.operand as u8 as i8 as u16),
+            ),
+            _ => None,
+        }
+    }
+
+    /// Operand in assembler syntax, e.g. `#$10`, `$0200,X` or `($80),Y`.
+    pub fn operand_text(&self) -> String {
+        match self.mode {
+            Implied => String::new(),
+            Accumulator => "A".to_string(),
+            Immediate => format!("#${:02X}", self.operand),
+            ZeroPage => format!("${:02X}", self.operand),
+            ZeroPageX => format!("${:02X},X", self.operand),
+            ZeroPageY => format!("${:02X},Y", self.operand),
+            Absolute => format!("${:04X}", self.operand),
+            AbsoluteX => format!("${:04X},X", self.operand),
+            AbsoluteY => format!("${:04X},Y", self.operand),
+            Indirect => format!("(${:04X})", self.operand),
+            IndirectX => format!("(${:02X},X)", self.operand),
+            IndirectY => format!("(${:02X}),Y", self.operand),
+            Relative => format!("${:04X}", self.effective_address().unwrap_or_default()),
+        }
+    }
+
+    /// True if execution never falls through to the next instruction.
+    pub fn ends_flow(&self) -> bool {
+        matches!(
+            self.mnemonic,
+            Mnemonic::Jmp | Mnemonic::Rts | Mnemonic::Rti | Mnemonic::Brk | Mnemonic::Jam
+        )
+    }
+
+    /// Statically known address control can transfer to: JMP/JSR absolute and branch targets.
+    pub fn jump_target(&self) -> Option<u16> {
+        match (self.mnemonic, self.mode) {
+            (Mnemonic::Jmp | Mnemonic::Jsr, Absolute) | (_, Relative) => self.effective_address(),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for Instruction {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let operand = self.operand_text();
+        if operand.is_empty() {
+            write!(f, "{}", self.mnemonic.name())
+        } else {
+            write!(f, "{} {}", self.mnemonic.name(), operand)
+        }
+    }
+}
+
+/// Decode the instruction at `address`.
+pub fn decode(address: u16, mut read: impl FnMut(u16) -> u8) -> Instruction {
+    let opcode = read(address);
+    let entry = OPCODES[opcode as usize];
+    let operand = match entry.mode.operand_len() {
+        0 => 0,
+        1 => read(address.wrapping_add(1)) as u16,
+        _ => read(address.wrapping_add(1)) as u16 | (read(address.wrapping_add(2)) as u16) << 8,
+    };
+
+    Instruction {
+        address,
+        opcode,
+        mnemonic: entry.mnemonic,
+        mode: entry.mode,
+        operand,
+        size: entry.size(),
+        official: entry.official,
+    }
+}
+
+/// Follow every statically reachable path from `entry_points` through PRG space ($8000-$FFFF).
+/// Bytes that never show up as an instruction are data, or code only reached through jump tables.
+pub fn trace_code(entry_points: &[u16], mut read: impl FnMut(u16) -> u8) -> BTreeMap<u16, Instruction> {
+    let mut code = BTreeMap::new();
+    let mut pending: Vec<u16> = entry_points.to_vec();
+
+    while let Some(mut address) = pending.pop() {
+        while address >= 0x8000 && !code.contains_key(&address) {
+            let instruction = decode(address, &mut read);
+            code.insert(address, instruction);
+
+            if let Some(target) = instruction.jump_target() {
+                pending.push(target);
+            }
+            if instruction.ends_flow() {
+                break;
+            }
+            address = match address.checked_add(instruction.size) {
+                Some(next) => next,
+                None => break,
+            };
+        }
+    }
+
+    code
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{decode, trace_code};
+
+    fn rom(program: &[u8]) -> impl Fn(u16) -> u8 {
+        let mut memory = vec![0u8; 0x10000];
+        memory[0x8000..0x8000 + program.len()].copy_from_slice(program);
+        move |addr| memory[addr as usize]
+    }
+
+    #[test]
+    fn decodes_operands_and_targets() {
+        let read = rom(&[0xBD, 0x00, 0x03, 0xD0, 0xFB, 0xB1, 0x80, 0x6C, 0xFF, 0x02]);
+
+        let lda = decode(0x8000, &read);
+        assert_eq!(lda.to_string(), "LDA $0300,X");
+        assert_eq!(lda.size, 3);
+        assert_eq!(lda.effective_address(), None);
+
+        let bne = decode(0x8003, &read);
+        assert_eq!(bne.to_string(), "BNE $8000");
+        assert_eq!(bne.jump_target(), Some(0x8000));
+
+        assert_eq!(decode(0x8005, &read).to_string(), "LDA ($80),Y");
+        assert_eq!(decode(0x8007, &read).to_string(), "JMP ($02FF)");
+    }
+
+    #[test]
+    fn tracing_skips_data_after_unconditional_jumps() {
+        // $8000: JSR $8008; JMP $8000; .byte $FF, $FF; $8008: BEQ $800B; RTS; $800B: RTS
+        let read = rom(&[0x20, 0x08, 0x80, 0x4C, 0x00, 0x80, 0xFF, 0xFF, 0xF0, 0x01, 0x60, 0x60]);
+        let code = trace_code(&[0x8000], &read);
+
+        let addresses: Vec<u16> = code.keys().copied().collect();
+        assert_eq!(addresses, vec![0x8000, 0x8003, 0x8008, 0x800A, 0x800B]);
+    }
+}
@@ -0,0 +1,799 @@
+// 6502 CPU core (Ricoh 2A03, no decimal mode)
+// The CPU only sees memory through a Bus, so it can run against the full system or a flat RAM image.
+// Every bus access is one cycle and ticks the rest of the machine first, dummy accesses included.
+
+use std::io::Result;
+use std::marker::PhantomData;
+
+use crate::state::{StateReader, StateWriter};
+
+pub mod disasm;
+mod opcodes;
+pub mod trace;
+
+pub use opcodes::{Mnemonic, Opcode, OPCODES};
+
+#[cfg(test)]
+mod tests;
+
+/// Everything the CPU is wired to: the address space, the clock and the interrupt lines.
+pub trait Bus {
+    fn read(&mut self, addr: u16) -> u8;
+    fn write(&mut self, addr: u16, value: u8);
+
+    /// Advance the rest of the machine by one CPU cycle. Called before every access.
+    fn tick(&mut self) {}
+
+    /// Return true (and acknowledge it) if an edge was latched on /NMI.
+    fn poll_nmi(&mut self) -> bool {
+        false
+    }
+
+    /// Level of the /IRQ line, true while any device asserts it.
+    fn irq(&self) -> bool {
+        false
+    }
+
+    /// Return (and reset) the cycles the CPU spent halted by DMA during the last access.
+    fn take_stall_cycles(&mut self) -> u64 {
+        0
+    }
+}
+
+// Status register bits
+const CARRY: u8 = 0x01;
+const ZERO: u8 = 0x02;
+const INTERRUPT_DISABLE: u8 = 0x04;
+const DECIMAL: u8 = 0x08;
+const BREAK: u8 = 0x10;
+const UNUSED: u8 = 0x20;
+const OVERFLOW: u8 = 0x40;
+const NEGATIVE: u8 = 0x80;
+
+// Analog noise ORed into A by XAA and LXA. $EE matches most 2A03s.
+const UNSTABLE_MAGIC: u8 = 0xEE;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AddressingMode {
+    Implied,
+    Accumulator,
+    Immediate,
+    ZeroPage,
+    ZeroPageX,
+    ZeroPageY,
+    Absolute,
+    AbsoluteX,
+    AbsoluteY,
+    Indirect,
+    IndirectX,
+    IndirectY,
+    Relative,
+}
+
+use AddressingMode::*;
+
+// Stores and read-modify-write instructions always spend the extra indexing cycle,
+// reads only spend it when the index carries into the high byte
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Access {
+    Read,
+    Write,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Interrupt {
+    Nmi,
+    Irq,
+    Brk,
+}
+
+pub struct Cpu<B: Bus> {
+    pub a: u8,
+    pub x: u8,
+    pub y: u8,
+    pub sp: u8,
+    pub pc: u16,
+    pub status: u8,
+    /// CPU cycles since power on.
+    pub cycles: u64,
+    // Interrupt lines as sampled at the end of this cycle and the one before. The CPU acts on the
+    // previous sample, so an interrupt raised during an instruction's last cycle waits one instruction.
+    nmi_pending: bool,
+    nmi_previous: bool,
+    irq_pending: bool,
+    irq_previous: bool,
+    _bus: PhantomData<B>,
+}
+
+impl<B: Bus> Default for Cpu<B> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<B: Bus> Cpu<B> {
+    pub fn new() -> Self {
+        Cpu {
+            a: 0,
+            x: 0,
+            y: 0,
+            sp: 0xFD,
+            pc: 0,
+            status: 0x24,
+            cycles: 0,
+            nmi_pending: false,
+            nmi_previous: false,
+            irq_pending: false,
+            irq_previous: false,
+            _bus: PhantomData,
+        }
+    }
+
+    /// Run the 7 cycle reset sequence. It is an interrupt whose stack writes are turned into reads.
+    pub fn reset(&mut self, bus: &mut B) {
+        self.a = 0;
+        self.x = 0;
+        self.y = 0;
+        self.sp = 0x00;
+        self.status = 0x24;
+
+        self.read(bus, self.pc);
+        self.read(bus, self.pc);
+        for _ in 0..3 {
+            self.read(bus, 0x0100 | self.sp as u16);
+            self.sp = self.sp.wrapping_sub(1);
+        }
+        self.pc = self.read_word(bus, 0xFFFC);
+
+        self.nmi_pending = false;
+        self.nmi_previous = false;
+        self.irq_pending = false;
+        self.irq_previous = false;
+    }
+
+    pub fn save_state(&self, state: &mut StateWriter) {
+        state.write_u8(self.a);
+        state.write_u8(self.x);
+        state.write_u8(self.y);
+        state.write_u8(self.sp);
+        state.write_u16(self.pc);
+        state.write_u8(self.status);
+        state.write_u64(self.cycles);
+        state.write_bool(self.nmi_pending);
+        state.write_bool(self.nmi_previous);
+        state.write_bool(self.irq_pending);
+        state.write_bool(self.irq_previous);
+    }
+
+    pub fn load_state(&mut self, state: &mut StateReader) -> Result<()> {
+        self.a = state.read_u8()?;
+        self.x = state.read_u8()?;
+        self.y = state.read_u8()?;
+        self.sp = state.read_u8()?;
+        self.pc = state.read_u16()?;
+        self.status = state.read_u8()?;
+        self.cycles = state.read_u64()?;
+        self.nmi_pending = state.read_bool()?;
+        self.nmi_previous = state.read_bool()?;
+        self.irq_pending = state.read_bool()?;
+        self.irq_previous = state.read_bool()?;
+        Ok(())
+    }
+
+    /// Run one instruction (or interrupt sequence) and return the cycles it took,
+    /// including any the CPU spent halted by DMA.
+    pub fn step(&mut self, bus: &mut B) -> u64 {
+        let start = self.cycles;
+
+        // NMI takes priority over IRQ
+        if self.nmi_previous {
+            self.nmi_pending = false;
+            self.interrupt(bus, Interrupt::Nmi);
+        } else if self.irq_previous {
+            self.interrupt(bus, Interrupt::Irq);
+        } else {
+            let opcode = self.fetch(bus);
+            self.execute(bus, opcode);
+        }
+
+        self.cycles - start
+    }
+
+    /// True if the next step runs an interrupt sequence instead of the instruction at PC.
+    pub fn interrupt_pending(&self) -> bool {
+        self.nmi_previous || self.irq_previous
+    }
+
+    fn execute(&mut self, bus: &mut B, opcode: u8) {
+        use Mnemonic::*;
+
+        let Opcode { mnemonic, mode, .. } = OPCODES[opcode as usize];
+        match mnemonic {
+            // Loads and stores
+            Lda => self.lda(bus, mode),
+            Ldx => self.ldx(bus, mode),
+            Ldy => self.ldy(bus, mode),
+            Sta => self.store(bus, mode, self.a),
+            Stx => self.store(bus, mode, self.x),
+            Sty => self.store(bus, mode, self.y),
+
+            // Logic and arithmetic
+            And => self.and(bus, mode),
+            Ora => self.ora(bus, mode),
+            Eor => self.eor(bus, mode),
+            Adc => self.adc(bus, mode),
+            Sbc => self.sbc(bus, mode),
+            Cmp => self.cmp(bus, mode),
+            Cpx => self.cpx(bus, mode),
+            Cpy => self.cpy(bus, mode),
+            Bit => self.bit(bus, mode),
+
+            // Shifts, rotates, increments and decrements
+            Asl => { self.modify(bus, mode, Self::shift_left); }
+            Lsr => { self.modify(bus, mode, Self::shift_right); }
+            Rol => { self.modify(bus, mode, Self::rotate_left); }
+            Ror => { self.modify(bus, mode, Self::rotate_right); }
+            Inc => { self.modify(bus, mode, Self::increment); }
+            Dec => { self.modify(bus, mode, Self::decrement); }
+
+            // Register instructions
+            Inx => { self.implied(bus); self.x = self.increment(self.x); }
+            Iny => { self.implied(bus); self.y = self.increment(self.y); }
+            Dex => { self.implied(bus); self.x = self.decrement(self.x); }
+            Dey => { self.implied(bus); self.y = self.decrement(self.y); }
+            Tax => { self.implied(bus); self.x = self.a; self.update_nz(self.x); }
+            Tay => { self.implied(bus); self.y = self.a; self.update_nz(self.y); }
+            Txa => { self.implied(bus); self.a = self.x; self.update_nz(self.a); }
+            Tya => { self.implied(bus); self.a = self.y; self.update_nz(self.a); }
+            Tsx => { self.implied(bus); self.x = self.sp; self.update_nz(self.x); }
+            Txs => { self.implied(bus); self.sp = self.x; }
+
+            // Flags. I changes after the interrupt poll, so CLI/SEI/PLP act one instruction late
+            Clc => { self.implied(bus); self.status &= !CARRY; }
+            Sec => { self.implied(bus); self.status |= CARRY; }
+            Cli => { self.implied(bus); self.status &= !INTERRUPT_DISABLE; }
+            Sei => { self.implied(bus); self.status |= INTERRUPT_DISABLE; }
+            Clv => { self.implied(bus); self.status &= !OVERFLOW; }
+            Cld => { self.implied(bus); self.status &= !DECIMAL; }
+            Sed => { self.implied(bus); self.status |= DECIMAL; }
+
+            // Stack
+            Pha => { self.implied(bus); self.push(bus, self.a); }
+            Php => { self.implied(bus); self.push(bus, self.status | BREAK | UNUSED); }
+            Pla => {
+                self.implied(bus);
+                self.read(bus, 0x0100 | self.sp as u16);
+                self.a = self.pop(bus);
+                self.update_nz(self.a);
+            }
+            Plp => {
+                self.implied(bus);
+                self.read(bus, 0x0100 | self.sp as u16);
+                self.status = (self.pop(bus) & !BREAK) | UNUSED;
+            }
+
+            // Jumps and subroutines
+            Jmp if mode == Indirect => self.jmp_indirect(bus),
+            Jmp => self.pc = self.fetch_word(bus),
+            Jsr => self.jsr(bus),
+            Rts => self.rts(bus),
+            Rti => self.rti(bus),
+            Brk => {
+                // The padding byte is read and skipped so RTI returns past it
+                self.fetch(bus);
+                self.interrupt(bus, Interrupt::Brk);
+            }
+
+            // Branches
+            Bpl => self.branch(bus, self.status & NEGATIVE == 0),
+            Bmi => self.branch(bus, self.status & NEGATIVE != 0),
+            Bvc => self.branch(bus, self.status & OVERFLOW == 0),
+            Bvs => self.branch(bus, self.status & OVERFLOW != 0),
+            Bcc => self.branch(bus, self.status & CARRY == 0),
+            Bcs => self.branch(bus, self.status & CARRY != 0),
+            Bne => self.branch(bus, self.status & ZERO == 0),
+            Beq => self.branch(bus, self.status & ZERO != 0),
+
+            // The unofficial NOPs still read their operand
+            Nop if mode == Implied => self.implied(bus),
+            Nop => { self.read_operand(bus, mode); }
+
+            // Unofficial combined operations
+            Lax => self.lax(bus, mode),
+            Sax => self.store(bus, mode, self.a & self.x),
+            Dcp => self.dcp(bus, mode),
+            Isc => self.isc(bus, mode),
+            Slo => self.slo(bus, mode),
+            Rla => self.rla(bus, mode),
+            Sre => self.sre(bus, mode),
+            Rra => self.rra(bus, mode),
+            Anc => self.anc(bus),
+            Alr => self.alr(bus),
+            Arr => self.arr(bus),
+            Axs => self.axs(bus),
+
+            // Unstable opcodes
+            Xaa => {
+                let value = self.read_operand(bus, mode);
+                self.a = (self.a | UNSTABLE_MAGIC) & self.x & value;
+                self.update_nz(self.a);
+            }
+            Lxa => {
+                let value = (self.a | UNSTABLE_MAGIC) & self.read_operand(bus, mode);
+                self.a = value;
+                self.x = value;
+                self.update_nz(value);
+            }
+            Las => {
+                let value = self.read_operand(bus, mode) & self.sp;
+                self.a = value;
+                self.x = value;
+                self.sp = value;
+                self.update_nz(value);
+            }
+            Sha => self.store_high_and(bus, mode, self.a & self.x),
+            Shx => self.store_high_and(bus, mode, self.x),
+            Shy => self.store_high_and(bus, mode, self.y),
+            Tas => {
+                self.sp = self.a & self.x;
+                self.store_high_and(bus, mode, self.sp);
+            }
+
+            // KIL/JAM - Halt CPU by executing the same opcode forever
+            Jam => {
+                self.implied(bus);
+                self.pc = self.pc.wrapping_sub(1);
+            }
+        }
+    }
+
+    // Bus cycles
+
+    fn read(&mut self, bus: &mut B, addr: u16) -> u8 {
+        bus.tick();
+        let value = bus.read(addr);
+        self.end_cycle(bus);
+        value
+    }
+
+    fn write(&mut self, bus: &mut B, addr: u16, value: u8) {
+        bus.tick();
+        bus.write(addr, value);
+        self.end_cycle(bus);
+    }
+
+    fn end_cycle(&mut self, bus: &mut B) {
+        self.cycles += 1 + bus.take_stall_cycles();
+
+        self.nmi_previous = self.nmi_pending;
+        if bus.poll_nmi() {
+            self.nmi_pending = true;
+        }
+
+        self.irq_previous = self.irq_pending;
+        self.irq_pending = bus.irq() && self.status & INTERRUPT_DISABLE == 0;
+    }
+
+    fn fetch(&mut self, bus: &mut B) -> u8 {
+        let value = self.read(bus, self.pc);
+        self.pc = self.pc.wrapping_add(1);
+        value
+    }
+
+    fn fetch_word(&mut self, bus: &mut B) -> u16 {
+        let lo = self.fetch(bus) as u16;
+        let hi = self.fetch(bus) as u16;
+        (hi << 8) | lo
+    }
+
+    fn read_word(&mut self, bus: &mut B, addr: u16) -> u16 {
+        let lo = self.read(bus, addr) as u16;
+        let hi = self.read(bus, addr.wrapping_add(1)) as u16;
+        (hi << 8) | lo
+    }
+
+    /// Single byte instructions still read the byte after the opcode.
+    fn implied(&mut self, bus: &mut B) {
+        self.read(bus, self.pc);
+    }
+
+    /// Resolve the effective address of a memory operand, performing the dummy reads real hardware does.
+    fn operand_address(&mut self, bus: &mut B, mode: AddressingMode, access: Access) -> u16 {
+        match mode {
+            Immediate => {
+                let addr = self.pc;
+                self.pc = self.pc.wrapping_add(1);
+                addr
+            }
+            ZeroPage => self.fetch(bus) as u16,
+            ZeroPageX | ZeroPageY => {
+                let base = self.fetch(bus);
+                // The unindexed address is read while the index is added
+                self.read(bus, base as u16);
+                let index = if mode == ZeroPageX { self.x } else { self.y };
+                base.wrapping_add(index) as u16
+            }
+            Absolute => self.fetch_word(bus),
+            AbsoluteX | AbsoluteY | IndirectY => {
+                let (base, index) = self.indexed_base(bus, mode);
+                self.index_address(bus, base, index, access)
+            }
+            IndirectX => {
+                let ptr = self.fetch(bus);
+                self.read(bus, ptr as u16);
+                let ptr = ptr.wrapping_add(self.x);
+                let lo = self.read(bus, ptr as u16) as u16;
+                let hi = self.read(bus, ptr.wrapping_add(1) as u16) as u16;
+                (hi << 8) | lo
+            }
+            Implied | Accumulator | Indirect | Relative => {
+                unreachable!("{:?} has no memory operand", mode)
+            }
+        }
+    }
+
+    /// Fetch the unindexed base address of an absolute indexed or (indirect),Y operand.
+    fn indexed_base(&mut self, bus: &mut B, mode: AddressingMode) -> (u16, u8) {
+        match mode {
+            AbsoluteX => (self.fetch_word(bus), self.x),
+            AbsoluteY => (self.fetch_word(bus), self.y),
+            IndirectY => {
+                let ptr = self.fetch(bus);
+                let lo = self.read(bus, ptr as u16) as u16;
+                let hi = self.read(bus, ptr.wrapping_add(1) as u16) as u16;
+                ((hi << 8) | lo, self.y)
+            }
+            _ => unreachable!("{:?} is not an indexed mode", mode),
+        }
+    }
+
+    fn index_address(&mut self, bus: &mut B, base: u16, index: u8, access: Access) -> u16 {
+        let addr = base.wrapping_add(index as u16);
+        // The first read uses the low byte sum before the carry reaches the high byte
+        if access == Access::Write || Self::page_crossed(base, addr) {
+            self.read(bus, (base & 0xFF00) | (addr & 0x00FF));
+        }
+        addr
+    }
+
+    fn read_operand(&mut self, bus: &mut B, mode: AddressingMode) -> u8 {
+        let addr = self.operand_address(bus, mode, Access::Read);
+        self.read(bus, addr)
+    }
+
+    fn store(&mut self, bus: &mut B, mode: AddressingMode, value: u8) {
+        let addr = self.operand_address(bus, mode, Access::Write);
+        self.write(bus, addr, value);
+    }
+
+    /// Read-modify-write: the unmodified value is written back before the result.
+    fn modify(&mut self, bus: &mut B, mode: AddressingMode, op: fn(&mut Self, u8) -> u8) -> u8 {
+        if mode == Accumulator {
+            self.implied(bus);
+            self.a = op(self, self.a);
+            return self.a;
+        }
+
+        let addr = self.operand_address(bus, mode, Access::Write);
+        let value = self.read(bus, addr);
+        self.write(bus, addr, value);
+        let result = op(self, value);
+        self.write(bus, addr, result);
+        result
+    }
+
+    // Instructions
+
+    fn lda(&mut self, bus: &mut B, mode: AddressingMode) {
+        self.a = self.read_operand(bus, mode);
+        self.update_nz(self.a);
+    }
+
+    fn ldx(&mut self, bus: &mut B, mode: AddressingMode) {
+        self.x = self.read_operand(bus, mode);
+        self.update_nz(self.x);
+    }
+
+    fn ldy(&mut self, bus: &mut B, mode: AddressingMode) {
+        self.y = self.read_operand(bus, mode);
+        self.update_nz(self.y);
+    }
+
+    fn and(&mut self, bus: &mut B, mode: AddressingMode) {
+        self.a &= self.read_operand(bus, mode);
+        self.update_nz(self.a);
+    }
+
+    fn ora(&mut self, bus: &mut B, mode: AddressingMode) {
+        self.a |= self.read_operand(bus, mode);
+        self.update_nz(self.a);
+    }
+
+    fn eor(&mut self, bus: &mut B, mode: AddressingMode) {
+        self.a ^= self.read_operand(bus, mode);
+        self.update_nz(self.a);
+    }
+
+    fn adc(&mut self, bus: &mut B, mode: AddressingMode) {
+        let value = self.read_operand(bus, mode);
+        self.add_with_carry(value);
+    }
+
+    fn sbc(&mut self, bus: &mut B, mode: AddressingMode) {
+        let value = self.read_operand(bus, mode);
+        self.add_with_carry(!value);
+    }
+
+    fn cmp(&mut self, bus: &mut B, mode: AddressingMode) {
+        let value = self.read_operand(bus, mode);
+        self.compare(self.a, value);
+    }
+
+    fn cpx(&mut self, bus: &mut B, mode: AddressingMode) {
+        let value = self.read_operand(bus, mode);
+        self.compare(self.x, value);
+    }
+
+    fn cpy(&mut self, bus: &mut B, mode: AddressingMode) {
+        let value = self.read_operand(bus, mode);
+        self.compare(self.y, value);
+    }
+
+    fn bit(&mut self, bus: &mut B, mode: AddressingMode) {
+        let value = self.read_operand(bus, mode);
+        self.status = (self.status & !(ZERO | OVERFLOW | NEGATIVE))
+            | if self.a & value == 0 { ZERO } else { 0 }
+            | (value & (OVERFLOW | NEGATIVE));
+    }
+
+    fn jmp_indirect(&mut self, bus: &mut B) {
+        let ptr = self.fetch_word(bus);
+        // 6502 bug: the pointer's high byte is fetched without carrying into the page
+        let lo = self.read(bus, ptr) as u16;
+        let hi = self.read(bus, (ptr & 0xFF00) | (ptr.wrapping_add(1) & 0x00FF)) as u16;
+        self.pc = (hi << 8) | lo;
+    }
+
+    fn jsr(&mut self, bus: &mut B) {
+        let lo = self.fetch(bus) as u16;
+        self.read(bus, 0x0100 | self.sp as u16);
+        // The pushed return address points at the last byte of the JSR
+        self.push_word(bus, self.pc);
+        let hi = self.read(bus, self.pc) as u16;
+        self.pc = (hi << 8) | lo;
+    }
+
+    fn rts(&mut self, bus: &mut B) {
+        self.implied(bus);
+        self.read(bus, 0x0100 | self.sp as u16);
+        self.pc = self.pop_word(bus);
+        self.fetch(bus);
+    }
+
+    fn rti(&mut self, bus: &mut B) {
+        self.implied(bus);
+        self.read(bus, 0x0100 | self.sp as u16);
+        self.status = (self.pop(bus) & !BREAK) | UNUSED;
+        self.pc = self.pop_word(bus);
+    }
+
+    fn branch(&mut self, bus: &mut B, condition: bool) {
+        let offset = self.fetch(bus) as i8;
+        if !condition {
+            return;
+        }
+
+        self.read(bus, self.pc);
+        let target = self.pc.wrapping_add(offset as u16);
+        if Self::page_crossed(self.pc, target) {
+            self.read(bus, (self.pc & 0xFF00) | (target & 0x00FF));
+        }
+        self.pc = target;
+    }
+
+    fn lax(&mut self, bus: &mut B, mode: AddressingMode) {
+        let value = self.read_operand(bus, mode);
+        self.a = value;
+        self.x = value;
+        self.update_nz(value);
+    }
+
+    fn dcp(&mut self, bus: &mut B, mode: AddressingMode) {
+        let value = self.modify(bus, mode, Self::decrement);
+        self.compare(self.a, value);
+    }
+
+    fn isc(&mut self, bus: &mut B, mode: AddressingMode) {
+        let value = self.modify(bus, mode, Self::increment);
+        self.add_with_carry(!value);
+    }
+
+    fn slo(&mut self, bus: &mut B, mode: AddressingMode) {
+        let value = self.modify(bus, mode, Self::shift_left);
+        self.a |= value;
+        self.update_nz(self.a);
+    }
+
+    fn rla(&mut self, bus: &mut B, mode: AddressingMode) {
+        let value = self.modify(bus, mode, Self::rotate_left);
+        self.a &= value;
+        self.update_nz(self.a);
+    }
+
+    fn sre(&mut self, bus: &mut B, mode: AddressingMode) {
+        let value = self.modify(bus, mode, Self::shift_right);
+        self.a ^= value;
+        self.update_nz(self.a);
+    }
+
+    fn rra(&mut self, bus: &mut B, mode: AddressingMode) {
+        let value = self.modify(bus, mode, Self::rotate_right);
+        self.add_with_carry(value);
+    }
+
+    fn anc(&mut self, bus: &mut B) {
+        self.a &= self.read_operand(bus, Immediate);
+        self.update_nz(self.a);
+        self.set_flag(CARRY, self.a & 0x80 != 0);
+    }
+
+    fn alr(&mut self, bus: &mut B) {
+        self.a &= self.read_operand(bus, Immediate);
+        self.a = self.shift_right(self.a);
+    }
+
+    fn arr(&mut self, bus: &mut B) {
+        self.a &= self.read_operand(bus, Immediate);
+        self.a = (self.a >> 1) | ((self.status & CARRY) << 7);
+        self.update_nz(self.a);
+        self.set_flag(CARRY, self.a & 0x40 != 0);
+        self.set_flag(OVERFLOW, ((self.a >> 6) ^ (self.a >> 5)) & 1 != 0);
+    }
+
+    fn axs(&mut self, bus: &mut B) {
+        let value = self.read_operand(bus, Immediate);
+        let masked = self.a & self.x;
+        self.set_flag(CARRY, masked >= value);
+        self.x = masked.wrapping_sub(value);
+        self.update_nz(self.x);
+    }
+
+    /// SHA/SHX/SHY/TAS store the value ANDed with the high byte of the base address plus one.
+    /// When the index crosses a page, that value also replaces the high byte of the target address.
+    fn store_high_and(&mut self, bus: &mut B, mode: AddressingMode, value: u8) {
+        let (base, index) = self.indexed_base(bus, mode);
+        let addr = base.wrapping_add(index as u16);
+        self.read(bus, (base & 0xFF00) | (addr & 0x00FF));
+
+        let value = value & ((base >> 8) as u8).wrapping_add(1);
+        let addr = if Self::page_crossed(base, addr) {
+            ((value as u16) << 8) | (addr & 0x00FF)
+        } else {
+            addr
+        };
+        self.write(bus, addr, value);
+    }
+
+    fn interrupt(&mut self, bus: &mut B, kind: Interrupt) {
+        if kind != Interrupt::Brk {
+            // The opcode fetch is discarded and PC is not incremented
+            self.read(bus, self.pc);
+            self.read(bus, self.pc);
+        }
+
+        self.push_word(bus, self.pc);
+        // B is only set in the pushed copy, it doesn't exist in the status register
+        let b_flag = if kind == Interrupt::Brk { BREAK } else { 0 };
+        self.push(bus, self.status | UNUSED | b_flag);
+        self.status |= INTERRUPT_DISABLE;
+
+        // An NMI raised before the vector fetch hijacks BRK and IRQ, keeping the pushed B flag
+        let hijacked = kind != Interrupt::Nmi && std::mem::take(&mut self.nmi_pending);
+        let vector = if kind == Interrupt::Nmi || hijacked { 0xFFFA } else { 0xFFFE };
+        self.pc = self.read_word(bus, vector);
+
+        // The first instruction of the handler always runs before another interrupt is taken
+        self.nmi_previous = false;
+        self.irq_previous = false;
+    }
+
+    // ALU helpers
+
+    fn update_nz(&mut self, value: u8) {
+        self.status = (self.status & !(ZERO | NEGATIVE))
+            | if value == 0 { ZERO } else { 0 }
+            | (value & NEGATIVE);
+    }
+
+    fn set_flag(&mut self, flag: u8, on: bool) {
+        if on {
+            self.status |= flag;
+        } else {
+            self.status &= !flag;
+        }
+    }
+
+    fn page_crossed(addr1: u16, addr2: u16) -> bool {
+        (addr1 & 0xFF00) != (addr2 & 0xFF00)
+    }
+
+    /// ADC, and SBC with the operand inverted. The 2A03 has no decimal mode.
+    fn add_with_carry(&mut self, value: u8) {
+        let sum = self.a as u16 + value as u16 + (self.status & CARRY) as u16;
+        let result = sum as u8;
+
+        self.set_flag(CARRY, sum > 0xFF);
+        self.set_flag(OVERFLOW, (self.a ^ result) & (value ^ result) & 0x80 != 0);
+
+        self.a = result;
+        self.update_nz(self.a);
+    }
+
+    fn compare(&mut self, reg: u8, value: u8) {
+        self.set_flag(CARRY, reg >= value);
+        self.update_nz(reg.wrapping_sub(value));
+    }
+
+    fn shift_left(&mut self, value: u8) -> u8 {
+        self.set_flag(CARRY, value & 0x80 != 0);
+        let result = value << 1;
+        self.update_nz(result);
+        result
+    }
+
+    fn shift_right(&mut self, value: u8) -> u8 {
+        self.set_flag(CARRY, value & 0x01 != 0);
+        let result = value >> 1;
+        self.update_nz(result);
+        result
+    }
+
+    fn rotate_left(&mut self, value: u8) -> u8 {
+        let result = (value << 1) | (self.status & CARRY);
+        self.set_flag(CARRY, value & 0x80 != 0);
+        self.update_nz(result);
+        result
+    }
+
+    fn rotate_right(&mut self, value: u8) -> u8 {
+        let result = (value >> 1) | ((self.status & CARRY) << 7);
+        self.set_flag(CARRY, value & 0x01 != 0);
+        self.update_nz(result);
+        result
+    }
+
+    fn increment(&mut self, value: u8) -> u8 {
+        let result = value.wrapping_add(1);
+        self.update_nz(result);
+        result
+    }
+
+    fn decrement(&mut self, value: u8) -> u8 {
+        let result = value.wrapping_sub(1);
+        self.update_nz(result);
+        result
+    }
+
+    // Stack
+
+    fn push(&mut self, bus: &mut B, value: u8) {
+        self.write(bus, 0x0100 | self.sp as u16, value);
+        self.sp = self.sp.wrapping_sub(1);
+    }
+
+    fn pop(&mut self, bus: &mut B) -> u8 {
+        self.sp = self.sp.wrapping_add(1);
+        self.read(bus, 0x0100 | self.sp as u16)
+    }
+
+    fn push_word(&mut self, bus: &mut B, value: u16) {
+        self.push(bus, (value >> 8) as u8);
+        self.push(bus, value as u8);
+    }
+
+    fn pop_word(&mut self, bus: &mut B) -> u16 {
+        let lo = self.pop(bus) as u16;
+        let hi = self.pop(bus) as u16;
+        (hi << 8) | lo
+    }
+}
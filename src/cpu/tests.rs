@@ -0,0 +1,455 @@
+// Instruction tests against a flat 64KB RAM bus
+
+use super::{AddressingMode, Bus, Cpu, Mnemonic, OPCODES};
+
+struct RamBus {
+    ram: Vec<u8>,
+    cycles: u64,
+    nmi: bool,
+    irq: bool,
+    reads: Vec<u16>,
+    writes: Vec<(u16, u8)>,
+}
+
+impl RamBus {
+    /// Load a program at $8000 and point the reset, NMI and IRQ vectors at fixed handlers.
+    fn with_program(program: &[u8]) -> Self {
+        let mut ram = vec![0; 0x10000];
+        ram[0x8000..0x8000 + program.len()].copy_from_slice(program);
+        ram[0xFFFA] = 0x00; // NMI -> $9000
+        ram[0xFFFB] = 0x90;
+        ram[0xFFFC] = 0x00; // Reset -> $8000
+        ram[0xFFFD] = 0x80;
+        ram[0xFFFE] = 0x00; // IRQ/BRK -> $A000
+        ram[0xFFFF] = 0xA0;
+        RamBus { ram, cycles: 0, nmi: false, irq: false, reads: Vec::new(), writes: Vec::new() }
+    }
+}
+
+impl Bus for RamBus {
+    fn read(&mut self, addr: u16) -> u8 {
+        self.reads.push(addr);
+        self.ram[addr as usize]
+    }
+
+    fn write(&mut self, addr: u16, value: u8) {
+        self.writes.push((addr, value));
+        self.ram[addr as usize] = value;
+    }
+
+    fn tick(&mut self) {
+        self.cycles += 1;
+    }
+
+    fn poll_nmi(&mut self) -> bool {
+        std::mem::take(&mut self.nmi)
+    }
+
+    fn irq(&self) -> bool {
+        self.irq
+    }
+}
+
+fn boot(program: &[u8]) -> (Cpu<RamBus>, RamBus) {
+    let mut bus = RamBus::with_program(program);
+    let mut cpu = Cpu::new();
+    cpu.reset(&mut bus);
+    bus.reads.clear();
+    (cpu, bus)
+}
+
+#[test]
+fn reset_takes_seven_cycles() {
+    let (cpu, bus) = boot(&[]);
+    assert_eq!(bus.cycles, 7);
+    assert_eq!(cpu.cycles, 7);
+    assert_eq!(cpu.pc, 0x8000);
+    assert_eq!(cpu.sp, 0xFD);
+}
+
+#[test]
+fn lda_immediate_sets_zero_and_negative() {
+    let (mut cpu, mut bus) = boot(&[0xA9, 0x00, 0xA9, 0x80]);
+    let start = bus.cycles;
+
+    assert_eq!(cpu.step(&mut bus), 2);
+    assert_eq!(cpu.a, 0x00);
+    assert_eq!(cpu.status & 0x82, 0x02);
+
+    cpu.step(&mut bus);
+    assert_eq!(cpu.a, 0x80);
+    assert_eq!(cpu.status & 0x82, 0x80);
+    assert_eq!(bus.cycles - start, 4);
+}
+
+#[test]
+fn adc_sets_carry_and_overflow() {
+    // CLC; LDA #$7F; ADC #$01; ADC #$80
+    let (mut cpu, mut bus) = boot(&[0x18, 0xA9, 0x7F, 0x69, 0x01, 0x69, 0x80]);
+    for _ in 0..3 {
+        cpu.step(&mut bus);
+    }
+    assert_eq!(cpu.a, 0x80);
+    assert_eq!(cpu.status & 0x41, 0x40);
+
+    cpu.step(&mut bus);
+    assert_eq!(cpu.a, 0x00);
+    assert_eq!(cpu.status & 0x43, 0x43);
+}
+
+#[test]
+fn store_and_indexed_load() {
+    // LDX #$05; LDA #$42; STA $0200,X; LDY $0205
+    let (mut cpu, mut bus) = boot(&[0xA2, 0x05, 0xA9, 0x42, 0x9D, 0x00, 0x02, 0xAC, 0x05, 0x02]);
+    for _ in 0..4 {
+        cpu.step(&mut bus);
+    }
+    assert_eq!(bus.ram[0x0205], 0x42);
+    assert_eq!(cpu.y, 0x42);
+}
+
+#[test]
+fn branch_cycles_depend_on_taken() {
+    // SEC; BCC +$02 (not taken); BCS +$02 (taken)
+    let (mut cpu, mut bus) = boot(&[0x38, 0x90, 0x02, 0xB0, 0x02]);
+
+    cpu.step(&mut bus);
+    assert_eq!(cpu.step(&mut bus), 2);
+    assert_eq!(cpu.pc, 0x8003);
+    assert_eq!(cpu.step(&mut bus), 3);
+    assert_eq!(cpu.pc, 0x8007);
+}
+
+#[test]
+fn jsr_and_rts_round_trip() {
+    let mut program = vec![0xEA; 0x20];
+    // $8000: JSR $8010
+    program[0x00] = 0x20;
+    program[0x01] = 0x10;
+    program[0x02] = 0x80;
+    // $8010: RTS
+    program[0x10] = 0x60;
+    let (mut cpu, mut bus) = boot(&program);
+
+    assert_eq!(cpu.step(&mut bus), 6);
+    assert_eq!(cpu.pc, 0x8010);
+    assert_eq!(cpu.sp, 0xFB);
+    assert_eq!(cpu.step(&mut bus), 6);
+    assert_eq!(cpu.pc, 0x8003);
+    assert_eq!(cpu.sp, 0xFD);
+}
+
+#[test]
+fn brk_pushes_b_flag_and_skips_padding_byte() {
+    let (mut cpu, mut bus) = boot(&[0x00, 0xFF]);
+
+    assert_eq!(cpu.step(&mut bus), 7);
+    assert_eq!(cpu.pc, 0xA000);
+    assert_eq!(bus.ram[0x01FD], 0x80);
+    assert_eq!(bus.ram[0x01FC], 0x02);
+    assert_eq!(bus.ram[0x01FB] & 0x30, 0x30);
+    // B only exists on the stack
+    assert_eq!(cpu.status & 0x10, 0);
+    assert_ne!(cpu.status & 0x04, 0);
+}
+
+#[test]
+fn irq_waits_for_one_instruction_after_cli() {
+    // CLI; NOP; NOP
+    let (mut cpu, mut bus) = boot(&[0x58, 0xEA, 0xEA]);
+    bus.irq = true;
+
+    cpu.step(&mut bus);
+    // The poll during CLI still saw I set, so the next instruction runs first
+    cpu.step(&mut bus);
+    assert_eq!(cpu.pc, 0x8002);
+
+    assert_eq!(cpu.step(&mut bus), 7);
+    assert_eq!(cpu.pc, 0xA000);
+    // IRQ pushes status with B clear
+    assert_eq!(bus.ram[0x01FB] & 0x30, 0x20);
+}
+
+#[test]
+fn branch_page_cross_costs_extra_cycle() {
+    let mut program = vec![0xEA; 0x100];
+    // $80F0: SEC; BCS +$20 -> $8113
+    program[0xF0] = 0x38;
+    program[0xF1] = 0xB0;
+    program[0xF2] = 0x20;
+    let (mut cpu, mut bus) = boot(&program);
+    cpu.pc = 0x80F0;
+
+    cpu.step(&mut bus);
+    assert_eq!(cpu.step(&mut bus), 4);
+    assert_eq!(cpu.pc, 0x8113);
+}
+
+#[test]
+fn indexed_store_reads_before_the_carry() {
+    // LDX #$20; STA $20F0,X
+    let (mut cpu, mut bus) = boot(&[0xA2, 0x20, 0x9D, 0xF0, 0x20]);
+    cpu.step(&mut bus);
+    bus.reads.clear();
+
+    assert_eq!(cpu.step(&mut bus), 5);
+    assert_eq!(bus.reads, vec![0x8002, 0x8003, 0x8004, 0x2010]);
+    assert_eq!(bus.writes, vec![(0x2110, 0x00)]);
+}
+
+#[test]
+fn indexed_load_only_reads_twice_on_page_cross() {
+    // LDX #$01; LDA $2000,X; LDA $20FF,X
+    let (mut cpu, mut bus) = boot(&[0xA2, 0x01, 0xBD, 0x00, 0x20, 0xBD, 0xFF, 0x20]);
+    cpu.step(&mut bus);
+
+    assert_eq!(cpu.step(&mut bus), 4);
+    bus.reads.clear();
+    assert_eq!(cpu.step(&mut bus), 5);
+    assert_eq!(&bus.reads[3..], &[0x2000, 0x2100]);
+}
+
+#[test]
+fn read_modify_write_writes_the_old_value_first() {
+    // INC $10
+    let (mut cpu, mut bus) = boot(&[0xE6, 0x10]);
+    bus.ram[0x10] = 0x41;
+
+    assert_eq!(cpu.step(&mut bus), 5);
+    assert_eq!(bus.writes, vec![(0x0010, 0x41), (0x0010, 0x42)]);
+}
+
+#[test]
+fn irq_is_still_taken_after_sei() {
+    // SEI; NOP
+    let (mut cpu, mut bus) = boot(&[0x78, 0xEA]);
+    cpu.status &= !0x04;
+    bus.irq = true;
+
+    // The polls during SEI saw I clear, so the interrupt is taken with I set on the stack
+    cpu.step(&mut bus);
+    assert_eq!(cpu.step(&mut bus), 7);
+    assert_eq!(cpu.pc, 0xA000);
+    assert_eq!(bus.ram[0x01FB] & 0x04, 0x04);
+}
+
+#[test]
+fn nmi_takes_priority_over_irq() {
+    let (mut cpu, mut bus) = boot(&[0xEA, 0xEA]);
+    cpu.status &= !0x04;
+    bus.nmi = true;
+    bus.irq = true;
+
+    cpu.step(&mut bus);
+    cpu.step(&mut bus);
+    assert_eq!(cpu.pc, 0x9000);
+}
+
+#[test]
+fn nmi_during_brk_hijacks_the_vector() {
+    let (mut cpu, mut bus) = boot(&[0x00, 0xFF]);
+    bus.nmi = true;
+
+    assert_eq!(cpu.step(&mut bus), 7);
+    assert_eq!(cpu.pc, 0x9000);
+    // The pushed status still has B set
+    assert_eq!(bus.ram[0x01FB] & 0x30, 0x30);
+
+    // The NMI was consumed by the hijack
+    cpu.step(&mut bus);
+    assert_ne!(cpu.pc, 0x9000);
+}
+
+#[test]
+fn nmi_is_taken_after_the_instruction_that_saw_it() {
+    let (mut cpu, mut bus) = boot(&[0xEA, 0xEA, 0xEA]);
+    cpu.step(&mut bus);
+    bus.nmi = true;
+
+    // Latched on the first cycle of the next NOP, which is also its penultimate cycle
+    cpu.step(&mut bus);
+    assert_eq!(cpu.pc, 0x8002);
+    cpu.step(&mut bus);
+    assert_eq!(cpu.pc, 0x9000);
+}
+
+// Reference opcode matrix, written out independently of OPCODES. JAM halts the CPU and is listed
+// at the 2 cycles the core spends before refetching it.
+#[rustfmt::skip]
+const REFERENCE_MNEMONICS: [&str; 256] = [
+    "BRK", "ORA", "JAM", "SLO", "NOP", "ORA", "ASL", "SLO", "PHP", "ORA", "ASL", "ANC", "NOP", "ORA", "ASL", "SLO",
+    "BPL", "ORA", "JAM", "SLO", "NOP", "ORA", "ASL", "SLO", "CLC", "ORA", "NOP", "SLO", "NOP", "ORA", "ASL", "SLO",
+    "JSR", "AND", "JAM", "RLA", "BIT", "AND", "ROL", "RLA", "PLP", "AND", "ROL", "ANC", "BIT", "AND", "ROL", "RLA",
+    "BMI", "AND", "JAM", "RLA", "NOP", "AND", "ROL", "RLA", "SEC", "AND", "NOP", "RLA", "NOP", "AND", "ROL", "RLA",
+    "RTI", "EOR", "JAM", "SRE", "NOP", "EOR", "LSR", "SRE", "PHA", "EOR", "LSR", "ALR", "JMP", "EOR", "LSR", "SRE",
+    "BVC", "EOR", "JAM", "SRE", "NOP", "EOR", "LSR", "SRE", "CLI", "EOR", "NOP", "SRE", "NOP", "EOR", "LSR", "SRE",
+    "RTS", "ADC", "JAM", "RRA", "NOP", "ADC", "ROR", "RRA", "PLA", "ADC", "ROR", "ARR", "JMP", "ADC", "ROR", "RRA",
+    "BVS", "ADC", "JAM", "RRA", "NOP", "ADC", "ROR", "RRA", "SEI", "ADC", "NOP", "RRA", "NOP", "ADC", "ROR", "RRA",
+    "NOP", "STA", "NOP", "SAX", "STY", "STA", "STX", "SAX", "DEY", "NOP", "TXA", "XAA", "STY", "STA", "STX", "SAX",
+    "BCC", "STA", "JAM", "SHA", "STY", "STA", "STX", "SAX", "TYA", "STA", "TXS", "TAS", "SHY", "STA", "SHX", "SHA",
+    "LDY", "LDA", "LDX", "LAX", "LDY", "LDA", "LDX", "LAX", "TAY", "LDA", "TAX", "LXA", "LDY", "LDA", "LDX", "LAX",
+    "BCS", "LDA", "JAM", "LAX", "LDY", "LDA", "LDX", "LAX", "CLV", "LDA", "TSX", "LAS", "LDY", "LDA", "LDX", "LAX",
+    "CPY", "CMP", "NOP", "DCP", "CPY", "CMP", "DEC", "DCP", "INY", "CMP", "DEX", "AXS", "CPY", "CMP", "DEC", "DCP",
+    "BNE", "CMP", "JAM", "DCP", "NOP", "CMP", "DEC", "DCP", "CLD", "CMP", "NOP", "DCP", "NOP", "CMP", "DEC", "DCP",
+    "CPX", "SBC", "NOP", "ISC", "CPX", "SBC", "INC", "ISC", "INX", "SBC", "NOP", "SBC", "CPX", "SBC", "INC", "ISC",
+    "BEQ", "SBC", "JAM", "ISC", "NOP", "SBC", "INC", "ISC", "SED", "SBC", "NOP", "ISC", "NOP", "SBC", "INC", "ISC",
+];
+
+#[rustfmt::skip]
+const REFERENCE_MODES: [&str; 256] = [
+    "imp", "izx", "imp", "izx", "zp",  "zp",  "zp",  "zp",  "imp", "imm", "acc", "imm", "abs", "abs", "abs", "abs",
+    "rel", "izy", "imp", "izy", "zpx", "zpx", "zpx", "zpx", "imp", "aby", "imp", "aby", "abx", "abx", "abx", "abx",
+    "abs", "izx", "imp", "izx", "zp",  "zp",  "zp",  "zp",  "imp", "imm", "acc", "imm", "abs", "abs", "abs", "abs",
+    "rel", "izy", "imp", "izy", "zpx", "zpx", "zpx", "zpx", "imp", "aby", "imp", "aby", "abx", "abx", "abx", "abx",
+    "imp", "izx", "imp", "izx", "zp",  "zp",  "zp",  "zp",  "imp", "imm", "acc", "imm", "abs", "abs", "abs", "abs",
+    "rel", "izy", "imp", "izy", "zpx", "zpx", "zpx", "zpx", "imp", "aby", "imp", "aby", "abx", "abx", "abx", "abx",
+    "imp", "izx", "imp", "izx", "zp",  "zp",  "zp",  "zp",  "imp", "imm", "acc", "imm", "ind", "abs", "abs", "abs",
+    "rel", "izy", "imp", "izy", "zpx", "zpx", "zpx", "zpx", "imp", "aby", "imp", "aby", "abx", "abx", "abx", "abx",
+    "imm", "izx", "imm", "izx", "zp",  "zp",  "zp",  "zp",  "imp", "imm", "imp", "imm", "abs", "abs", "abs", "abs",
+    "rel", "izy", "imp", "izy", "zpx", "zpx", "zpy", "zpy", "imp", "aby", "imp", "aby", "abx", "abx", "aby", "aby",
+    "imm", "izx", "imm", "izx", "zp",  "zp",  "zp",  "zp",  "imp", "imm", "imp", "imm", "abs", "abs", "abs", "abs",
+    "rel", "izy", "imp", "izy", "zpx", "zpx", "zpy", "zpy", "imp", "aby", "imp", "aby", "abx", "abx", "aby", "aby",
+    "imm", "izx", "imm", "izx", "zp",  "zp",  "zp",  "zp",  "imp", "imm", "imp", "imm", "abs", "abs", "abs", "abs",
+    "rel", "izy", "imp", "izy", "zpx", "zpx", "zpx", "zpx", "imp", "aby", "imp", "aby", "abx", "abx", "abx", "abx",
+    "imm", "izx", "imm", "izx", "zp",  "zp",  "zp",  "zp",  "imp", "imm", "imp", "imm", "abs", "abs", "abs", "abs",
+    "rel", "izy", "imp", "izy", "zpx", "zpx", "zpx", "zpx", "imp", "aby", "imp", "aby", "abx", "abx", "abx", "abx",
+];
+
+// Base cycles from blargg's instr_timing, without branch or page crossing penalties
+#[rustfmt::skip]
+const REFERENCE_CYCLES: [u8; 256] = [
+    7, 6, 2, 8, 3, 3, 5, 5, 3, 2, 2, 2, 4, 4, 6, 6,
+    2, 5, 2, 8, 4, 4, 6, 6, 2, 4, 2, 7, 4, 4, 7, 7,
+    6, 6, 2, 8, 3, 3, 5, 5, 4, 2, 2, 2, 4, 4, 6, 6,
+    2, 5, 2, 8, 4, 4, 6, 6, 2, 4, 2, 7, 4, 4, 7, 7,
+    6, 6, 2, 8, 3, 3, 5, 5, 3, 2, 2, 2, 3, 4, 6, 6,
+    2, 5, 2, 8, 4, 4, 6, 6, 2, 4, 2, 7, 4, 4, 7, 7,
+    6, 6, 2, 8, 3, 3, 5, 5, 4, 2, 2, 2, 5, 4, 6, 6,
+    2, 5, 2, 8, 4, 4, 6, 6, 2, 4, 2, 7, 4, 4, 7, 7,
+    2, 6, 2, 6, 3, 3, 3, 3, 2, 2, 2, 2, 4, 4, 4, 4,
+    2, 6, 2, 6, 4, 4, 4, 4, 2, 5, 2, 5, 5, 5, 5, 5,
+    2, 6, 2, 6, 3, 3, 3, 3, 2, 2, 2, 2, 4, 4, 4, 4,
+    2, 5, 2, 5, 4, 4, 4, 4, 2, 4, 2, 4, 4, 4, 4, 4,
+    2, 6, 2, 8, 3, 3, 5, 5, 2, 2, 2, 2, 4, 4, 6, 6,
+    2, 5, 2, 8, 4, 4, 6, 6, 2, 4, 2, 7, 4, 4, 7, 7,
+    2, 6, 2, 8, 3, 3, 5, 5, 2, 2, 2, 2, 4, 4, 6, 6,
+    2, 5, 2, 8, 4, 4, 6, 6, 2, 4, 2, 7, 4, 4, 7, 7,
+];
+
+// Opcodes whose indexed read costs one more cycle when it crosses a page
+const PAGE_CROSS_PENALTY: [u8; 32] = [
+    0x11, 0x19, 0x1C, 0x1D, 0x31, 0x39, 0x3C, 0x3D, 0x51, 0x59, 0x5C, 0x5D, 0x71, 0x79, 0x7C, 0x7D,
+    0xB1, 0xB3, 0xB9, 0xBB, 0xBC, 0xBD, 0xBE, 0xBF, 0xD1, 0xD9, 0xDC, 0xDD, 0xF1, 0xF9, 0xFC, 0xFD,
+];
+
+fn mode_abbreviation(mode: AddressingMode) -> &'static str {
+    match mode {
+        AddressingMode::Implied => "imp",
+        AddressingMode::Accumulator => "acc",
+        AddressingMode::Immediate => "imm",
+        AddressingMode::ZeroPage => "zp",
+        AddressingMode::ZeroPageX => "zpx",
+        AddressingMode::ZeroPageY => "zpy",
+        AddressingMode::Absolute => "abs",
+        AddressingMode::AbsoluteX => "abx",
+        AddressingMode::AbsoluteY => "aby",
+        AddressingMode::Indirect => "ind",
+        AddressingMode::IndirectX => "izx",
+        AddressingMode::IndirectY => "izy",
+        AddressingMode::Relative => "rel",
+    }
+}
+
+/// Run a single opcode with operand $0301 and return the cycles it took.
+/// Every pointer in the zero page also resolves to $0301, and branches are set up not to be taken.
+fn time_opcode(opcode: u8, index: u8) -> u64 {
+    let (mut cpu, mut bus) = boot(&[opcode, 0x01, 0x03]);
+    bus.ram[0x00] = 0x01;
+    bus.ram[0x01] = 0x01;
+    bus.ram[0x02] = 0x03;
+    cpu.x = index;
+    cpu.y = index;
+
+    if opcode & 0x1F == 0x10 {
+        // Branches test N, V, C or Z against bit 5 of the opcode
+        let flag = [0x80, 0x40, 0x01, 0x02][(opcode >> 6) as usize];
+        if opcode & 0x20 == 0 {
+            cpu.status |= flag;
+        } else {
+            cpu.status &= !flag;
+        }
+    }
+
+    cpu.step(&mut bus)
+}
+
+#[test]
+fn opcode_table_matches_reference() {
+    for (opcode, entry) in OPCODES.iter().enumerate() {
+        assert_eq!(entry.mnemonic.name(), REFERENCE_MNEMONICS[opcode], "opcode ${:02X}", opcode);
+        assert_eq!(mode_abbreviation(entry.mode), REFERENCE_MODES[opcode], "opcode ${:02X}", opcode);
+        assert_eq!(entry.cycles, REFERENCE_CYCLES[opcode], "opcode ${:02X}", opcode);
+    }
+    assert_eq!(OPCODES.iter().filter(|entry| entry.official).count(), 151);
+}
+
+#[test]
+fn every_opcode_takes_its_table_cycles() {
+    for opcode in 0..=255u8 {
+        if OPCODES[opcode as usize].mnemonic == Mnemonic::Jam {
+            continue;
+        }
+        assert_eq!(time_opcode(opcode, 0), OPCODES[opcode as usize].cycles as u64, "opcode ${:02X}", opcode);
+    }
+}
+
+#[test]
+fn indexed_reads_pay_for_page_crossing() {
+    for opcode in 0..=255u8 {
+        if OPCODES[opcode as usize].mnemonic == Mnemonic::Jam {
+            continue;
+        }
+        let penalty = PAGE_CROSS_PENALTY.contains(&opcode) as u64;
+        assert_eq!(
+            time_opcode(opcode, 0xFF),
+            OPCODES[opcode as usize].cycles as u64 + penalty,
+            "opcode ${:02X}",
+            opcode
+        );
+    }
+}
+
+#[test]
+fn jam_halts_on_the_same_opcode() {
+    let (mut cpu, mut bus) = boot(&[0x02, 0xEA]);
+    cpu.step(&mut bus);
+    cpu.step(&mut bus);
+    assert_eq!(cpu.pc, 0x8000);
+}
+
+#[test]
+fn shx_corrupts_the_high_byte_on_page_cross() {
+    // LDX #$03; LDY #$01; SHX $04FF,Y; LDX #$FF; SHX $0400,Y
+    let (mut cpu, mut bus) =
+        boot(&[0xA2, 0x03, 0xA0, 0x01, 0x9E, 0xFF, 0x04, 0xA2, 0xFF, 0x9E, 0x00, 0x04]);
+    for _ in 0..5 {
+        cpu.step(&mut bus);
+    }
+    // X & ($04 + 1) = $01, which also becomes the high byte of $0500
+    assert_eq!(bus.writes, vec![(0x0100, 0x01), (0x0401, 0x05)]);
+}
+
+#[test]
+fn xaa_and_lxa_mix_in_the_magic_constant() {
+    // LDX #$0F; LDA #$01; XAA #$FF; LDA #$10; LXA #$F3
+    let (mut cpu, mut bus) = boot(&[0xA2, 0x0F, 0xA9, 0x01, 0x8B, 0xFF, 0xA9, 0x10, 0xAB, 0xF3]);
+    for _ in 0..3 {
+        cpu.step(&mut bus);
+    }
+    assert_eq!(cpu.a, 0x0F);
+
+    cpu.step(&mut bus);
+    cpu.step(&mut bus);
+    assert_eq!(cpu.a, 0xF2);
+    assert_eq!(cpu.x, 0xF2);
+}
@@ -0,0 +1,151 @@
+// nestest.log compatible instruction trace
+// Lines are formatted before the instruction executes, so a run can be diffed against a known-good log.
+
+use super::disasm;
+use super::{Bus, Cpu, Mnemonic};
+use super::AddressingMode::*;
+
+/// Format the instruction at the CPU's PC as one nestest.log line.
+/// `peek` must read memory without side effects, and `scanline`/`dot` give the PPU position.
+/// CYC is the CPU's cycle count, which includes cycles it spent halted by DMA.
+pub fn trace_line<B: Bus>(cpu: &Cpu<B>, peek: impl Fn(u16) -> u8, scanline: u16, dot: u16) -> String {
+    let instruction = disasm::decode(cpu.pc, &peek);
+
+    let bytes: Vec<String> = (0..instruction.size)
+        .map(|i| format!("{:02X}", peek(cpu.pc.wrapping_add(i))))
+        .collect();
+
+    let read_word = |addr: u16| {
+        // Pointers never carry into the high byte, both in the zero page and for JMP ($xxFF)
+        let next = (addr & 0xFF00) | (addr.wrapping_add(1) & 0x00FF);
+        (peek(next) as u16) << 8 | peek(addr) as u16
+    };
+
+    // nestest follows the operand with the memory it resolves to
+    let operand = instruction.operand;
+    let annotation = match instruction.mode {
+        ZeroPage | Absolute if !matches!(instruction.mnemonic, Mnemonic::Jmp | Mnemonic::Jsr) => {
+            format!(" = {:02X}", peek(operand))
+        }
+        ZeroPageX | ZeroPageY => {
+            let index = if instruction.mode == ZeroPageX { cpu.x } else { cpu.y };
+            let addr = (operand as u8).wrapping_add(index) as u16;
+            format!(" @ {:02X} = {:02X}", addr, peek(addr))
+        }
+        AbsoluteX | AbsoluteY => {
+            let index = if instruction.mode == AbsoluteX { cpu.x } else { cpu.y };
+            let addr = operand.wrapping_add(index as u16);
+            format!(" @ {:04X} = {:02X}", addr, peek(addr))
+        }
+        Indirect => format!(" = {:04X}", read_word(operand)),
+        IndirectX => {
+            let ptr = (operand as u8).wrapping_add(cpu.x);
+            let addr = read_word(ptr as u16);
+            format!(" @ {:02X} = {:04X} = {:02X}", ptr, addr, peek(addr))
+        }
+        IndirectY => {
+            let base = read_word(operand);
+            let addr = base.wrapping_add(cpu.y as u16);
+            format!(" = {:04X} @ {:04X} = {:02X}", base, addr, peek(addr))
+        }
+        _ => String::new(),
+    };
+
+    // nestest spells ISC as ISB and marks unofficial opcodes with a star
+    let name = match instruction.mnemonic {
+        Mnemonic::Isc => "ISB",
+        mnemonic => mnemonic.name(),
+    };
+    let disassembly = format!("{} {}{}", name, instruction.operand_text(), annotation);
+
+    format!(
+        "{:04X}  {:<8} {}{:<32}A:{:02X} X:{:02X} Y:{:02X} P:{:02X} SP:{:02X} PPU:{:>3},{:>3} CYC:{}",
+        cpu.pc,
+        bytes.join(" "),
+        if instruction.official { ' ' } else { '*' },
+        disassembly.trim_end(),
+        cpu.a,
+        cpu.x,
+        cpu.y,
+        cpu.status,
+        cpu.sp,
+        scanline,
+        dot,
+        cpu.cycles,
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::trace_line;
+    use crate::cpu::{Bus, Cpu};
+
+    struct NullBus;
+
+    impl Bus for NullBus {
+        fn read(&mut self, _addr: u16) -> u8 {
+            0
+        }
+
+        fn write(&mut self, _addr: u16, _value: u8) {}
+    }
+
+    fn cpu_at(pc: u16, a: u8, x: u8, y: u8, status: u8, sp: u8, cycles: u64) -> Cpu<NullBus> {
+        let mut cpu = Cpu::new();
+        cpu.pc = pc;
+        cpu.a = a;
+        cpu.x = x;
+        cpu.y = y;
+        cpu.status = status;
+        cpu.sp = sp;
+        cpu.cycles = cycles;
+        cpu
+    }
+
+    fn memory(entries: &[(u16, &[u8])]) -> impl Fn(u16) -> u8 {
+        let mut ram = vec![0u8; 0x10000];
+        for (addr, bytes) in entries {
+            ram[*addr as usize..*addr as usize + bytes.len()].copy_from_slice(bytes);
+        }
+        move |addr| ram[addr as usize]
+    }
+
+    #[test]
+    fn matches_nestest_lines() {
+        let cpu = cpu_at(0xC000, 0x00, 0x00, 0x00, 0x24, 0xFD, 7);
+        assert_eq!(
+            trace_line(&cpu, memory(&[(0xC000, &[0x4C, 0xF5, 0xC5])]), 0, 21),
+            "C000  4C F5 C5  JMP $C5F5                       A:00 X:00 Y:00 P:24 SP:FD PPU:  0, 21 CYC:7"
+        );
+
+        let cpu = cpu_at(0xC5F7, 0x00, 0x00, 0x00, 0x26, 0xFD, 11);
+        assert_eq!(
+            trace_line(&cpu, memory(&[(0xC5F7, &[0x86, 0x00])]), 0, 33),
+            "C5F7  86 00     STX $00 = 00                    A:00 X:00 Y:00 P:26 SP:FD PPU:  0, 33 CYC:11"
+        );
+
+        let cpu = cpu_at(0xD959, 0x5A, 0x00, 0x00, 0x27, 0xFB, 3156);
+        let peek = memory(&[(0xD959, &[0xA1, 0x80]), (0x0080, &[0x00, 0x02]), (0x0200, &[0x5A])]);
+        assert_eq!(
+            trace_line(&cpu, peek, 29, 259),
+            "D959  A1 80     LDA ($80,X) @ 80 = 0200 = 5A    A:5A X:00 Y:00 P:27 SP:FB PPU: 29,259 CYC:3156"
+        );
+
+        let cpu = cpu_at(0xDB7B, 0xDB, 0x07, 0x00, 0xE5, 0xFB, 3508);
+        let peek = memory(&[(0xDB7B, &[0x6C, 0x00, 0x02]), (0x0200, &[0x7E, 0xDB])]);
+        assert_eq!(
+            trace_line(&cpu, peek, 33, 23),
+            "DB7B  6C 00 02  JMP ($0200) = DB7E              A:DB X:07 Y:00 P:E5 SP:FB PPU: 33, 23 CYC:3508"
+        );
+    }
+
+    #[test]
+    fn marks_unofficial_opcodes() {
+        let cpu = cpu_at(0xE8D5, 0xAB, 0x02, 0x00, 0x6C, 0xFB, 14275);
+        let peek = memory(&[(0xE8D5, &[0xE3, 0x45]), (0x0047, &[0x47, 0x06]), (0x0647, &[0xEB])]);
+        assert_eq!(
+            trace_line(&cpu, peek, 125, 48),
+            "E8D5  E3 45    *ISB ($45,X) @ 47 = 0647 = EB    A:AB X:02 Y:00 P:6C SP:FB PPU:125, 48 CYC:14275"
+        );
+    }
+}
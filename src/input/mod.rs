@@ -1,4 +1,6 @@
 use bitflags::bitflags;
+use crate::state::{StateReader, StateWriter};
+use std::io::Result;
 
 bitflags! {
     #[derive(Debug, Clone, Copy)]
@@ -115,4 +117,17 @@ impl Controller {
     pub fn _is_pressed(&self, button: ControllerButton) -> bool {
         self.buttons.contains(button)
     }
-}
\ No newline at end of file
+
+    pub fn save_state(&self, state: &mut StateWriter) {
+        state.write_u8(self.buttons.bits());
+        state.write_bool(self.strobe);
+        state.write_u8(self.index);
+    }
+
+    pub fn load_state(&mut self, state: &mut StateReader) -> Result<()> {
+        self.buttons = ControllerButton::from_bits_truncate(state.read_u8()?);
+        self.strobe = state.read_bool()?;
+        self.index = state.read_u8()?;
+        Ok(())
+    }
+}
@@ -2,4 +2,6 @@ pub mod ppu;
 pub mod apu;
 pub mod cartridge;
 pub mod input;
-pub mod system;
\ No newline at end of file
+pub mod cpu;
+pub mod system;
+pub mod state;
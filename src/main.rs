@@ -1,9 +1,3 @@
-mod ppu;
-mod apu;
-mod cartridge;
-mod input;
-mod system;
-
 use sdl2::pixels::{PixelFormatEnum, Color};
 use sdl2::event::Event;
 use sdl2::keyboard::Keycode;
@@ -12,17 +6,21 @@ use sdl2::video::WindowContext;
 use sdl2::audio::{AudioCallback, AudioSpecDesired, AudioDevice};
 use sdl2::rect::Rect;
 use std::env;
+use std::fs::File;
+use std::io::BufWriter;
+use std::path::{Path, PathBuf};
 use std::sync::{Arc, Mutex};
 use std::collections::VecDeque;
 use std::time::{Duration, Instant};
 use anyhow::Result;
 
-use crate::cartridge::Cartridge;
-use crate::input::ControllerButton;
-use crate::system::System;
-use crate::ppu::{SCREEN_WIDTH, SCREEN_HEIGHT};
+use nes_emu::cartridge::Cartridge;
+use nes_emu::input::ControllerButton;
+use nes_emu::system::System;
+use nes_emu::ppu::{SCREEN_WIDTH, SCREEN_HEIGHT};
 
 const SCALE: u32 = 3;
+const BATTERY_FLUSH_INTERVAL: Duration = Duration::from_secs(5);
 
 struct ApuAudioCallback {
     audio_buffer: Arc<Mutex<VecDeque<f32>>>,
@@ -59,17 +57,62 @@ fn map_keycode_to_button(key: Keycode) -> Option<ControllerButton> {
     }
 }
 
+fn map_keycode_to_slot(key: Keycode) -> Option<u8> {
+    match key {
+        Keycode::Num0 => Some(0),
+        Keycode::Num1 => Some(1),
+        Keycode::Num2 => Some(2),
+        Keycode::Num3 => Some(3),
+        Keycode::Num4 => Some(4),
+        Keycode::Num5 => Some(5),
+        Keycode::Num6 => Some(6),
+        Keycode::Num7 => Some(7),
+        Keycode::Num8 => Some(8),
+        Keycode::Num9 => Some(9),
+        _ => None,
+    }
+}
+
+/// Save states live next to the ROM: game.nes -> game.ss1
+fn state_path(rom_path: &str, slot: u8) -> PathBuf {
+    Path::new(rom_path).with_extension(format!("ss{}", slot))
+}
+
+/// Write battery RAM to its save file if it changed since the last flush.
+fn flush_battery_ram(system: &System, path: &Path, last_flushed: &mut Vec<u8>) {
+    let Some(ref cart) = system.cartridge else {
+        return;
+    };
+    let cart = cart.borrow();
+    let Some(ram) = cart.battery_ram() else {
+        return;
+    };
+    if ram == last_flushed.as_slice() {
+        return;
+    }
+
+    match std::fs::write(path, ram) {
+        Ok(()) => {
+            log::info!("Saved battery RAM to {}", path.display());
+            *last_flushed = ram.to_vec();
+        }
+        Err(e) => log::error!("Failed to write {}: {}", path.display(), e),
+    }
+}
+
 fn main() -> Result<()> {
     env_logger::init();
 
     let args: Vec<String> = env::args().collect();
     if args.len() < 2 {
-        eprintln!("Usage: {} <rom_file> [--no-audio]", args[0]);
+        eprintln!("Usage: {} <rom_file> [--no-audio] [--trace <log_file>] [--no-sprite-limit]", args[0]);
         std::process::exit(1);
     }
 
     let rom_path = &args[1];
     let enable_audio = !args.contains(&"--no-audio".to_string());
+    let trace_path = args.iter().position(|arg| arg == "--trace").and_then(|i| args.get(i + 1));
+    let unlimited_sprites = args.contains(&"--no-sprite-limit".to_string());
 
     if !enable_audio {
         log::info!("Audio disabled via command-line flag");
@@ -77,9 +120,23 @@ fn main() -> Result<()> {
 
     log::info!("Loading ROM: {}", rom_path);
 
-    let cartridge = Cartridge::load_from_file(rom_path)?;
+    let mut cartridge = Cartridge::load_from_file(rom_path)?;
     log::info!("ROM loaded successfully. Mapper: {}", cartridge.mapper);
 
+    // Battery-backed RAM persists in a .sav file next to the ROM
+    let sav_path = Path::new(rom_path).with_extension("sav");
+    if cartridge.battery_backed {
+        match std::fs::read(&sav_path) {
+            Ok(data) => {
+                cartridge.load_battery_ram(&data);
+                log::info!("Loaded battery RAM from {}", sav_path.display());
+            }
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+            Err(e) => log::warn!("Failed to read {}: {}", sav_path.display(), e),
+        }
+    }
+    let mut last_flushed_battery_ram = cartridge.battery_ram().map(<[u8]>::to_vec).unwrap_or_default();
+
     let sdl_context = sdl2::init().map_err(|e| anyhow::anyhow!("SDL init failed: {}", e))?;
     let video_subsystem = sdl_context.video().map_err(|e| anyhow::anyhow!("Video subsystem failed: {}", e))?;
 
@@ -145,11 +202,18 @@ fn main() -> Result<()> {
     };
 
     let mut system = System::new();
+    if let Some(path) = trace_path {
+        log::info!("Writing CPU trace to {}", path);
+        system.set_trace_output(Some(Box::new(BufWriter::new(File::create(path)?))));
+    }
+    system.ppu.unlimited_sprites = unlimited_sprites;
     system.load_cartridge(cartridge);
 
     let frame_duration = Duration::from_nanos(16_666_667);
     let mut _last_frame = Instant::now();
     let mut osd_shown_until: Option<Instant> = None;
+    let mut save_slot: u8 = 1;
+    let mut last_battery_flush = Instant::now();
 
     log::info!("Starting emulation...");
 
@@ -170,6 +234,25 @@ fn main() -> Result<()> {
                         log::info!("Resetting NES...");
                         system.reset();
                     }
+                    // Save states
+                    if let Some(slot) = map_keycode_to_slot(keycode) {
+                        save_slot = slot;
+                        log::info!("Save slot {} selected", slot);
+                    }
+                    if keycode == Keycode::F5 {
+                        let path = state_path(rom_path, save_slot);
+                        match std::fs::write(&path, system.save_state()) {
+                            Ok(()) => log::info!("Saved state to {}", path.display()),
+                            Err(e) => log::error!("Failed to save state to {}: {}", path.display(), e),
+                        }
+                    }
+                    if keycode == Keycode::F9 {
+                        let path = state_path(rom_path, save_slot);
+                        match std::fs::read(&path).and_then(|data| system.load_state(&data)) {
+                            Ok(()) => log::info!("Loaded state from {}", path.display()),
+                            Err(e) => log::error!("Failed to load state from {}: {}", path.display(), e),
+                        }
+                    }
                     // Audio controls
                     if enable_audio {
                         if keycode == Keycode::M {
@@ -209,6 +292,11 @@ fn main() -> Result<()> {
 
         system.run_frame_with_audio(audio_buffer.as_ref());
 
+        if last_battery_flush.elapsed() >= BATTERY_FLUSH_INTERVAL {
+            flush_battery_ram(&system, &sav_path, &mut last_flushed_battery_ram);
+            last_battery_flush = Instant::now();
+        }
+
         texture
             .update(None, system.get_frame_buffer(), SCREEN_WIDTH * 3)
             .map_err(|e| anyhow::anyhow!("Texture update failed: {}", e))?;
@@ -265,6 +353,7 @@ fn main() -> Result<()> {
         _last_frame = Instant::now();
     }
 
+    flush_battery_ram(&system, &sav_path, &mut last_flushed_battery_ram);
     log::info!("Emulation stopped.");
     Ok(())
 }
@@ -1,9 +1,18 @@
 use bitflags::bitflags;
-use crate::cartridge::Mirroring;
+use crate::cartridge::{Cartridge, Mirroring};
+use crate::state::{StateReader, StateWriter};
+use std::cell::RefCell;
+use std::io::{Error, ErrorKind, Result};
+use std::rc::Rc;
 
 pub const SCREEN_WIDTH: usize = 256;
 pub const SCREEN_HEIGHT: usize = 240;
 
+// Dots between a rising edge on the /NMI output and the CPU latching it
+const NMI_DELAY: u8 = 2;
+// Frames an I/O latch bit holds its value without being driven, roughly one second
+const IO_BUS_DECAY_FRAMES: u64 = 60;
+
 bitflags! {
     #[derive(Debug, Clone, Copy)]
     pub struct PpuCtrl: u8 {
@@ -66,7 +75,12 @@ pub struct Ppu {
     pub oam_addr: u8,
     pub oam_data: [u8; 256],
     pub ppu_data_buffer: u8,
-    pub vram: [u8; 0x4000],
+    // The I/O latch: the last value on the CPU-PPU data bus, read back from write-only registers
+    // and undriven bits. Each bit fades to 0 on its own if nothing drives it.
+    io_bus: u8,
+    io_bus_refreshed: [u64; 8],
+    /// The console's 2KB of nametable RAM; the cartridge decides how it is arranged.
+    pub ciram: [u8; 0x800],
     pub palette: [u8; 32],
     
     pub scanline: u16,
@@ -75,24 +89,58 @@ pub struct Ppu {
     
     pub frame_buffer: [u8; SCREEN_WIDTH * SCREEN_HEIGHT * 3],
     pub nmi_interrupt: bool,
+    // /NMI output level (VBlank flag AND NMI_ENABLE) and the dots left before a rising edge
+    // reaches the CPU. Dropping the output in the meantime, as a $2002 read does, swallows it.
+    nmi_output: bool,
+    nmi_delay: u8,
+    // $2002 was read on the dot before VBlank starts, so the flag stays clear for this frame
+    suppress_vblank: bool,
+    odd_frame: bool,
     
     // PPU internal registers for scrolling
     v: u16,     // Current VRAM address (15 bits)
     t: u16,     // Temporary VRAM address (15 bits)
     x: u8,      // Fine X scroll (3 bits)
     w: bool,    // Write latch
+
+    // Background pipeline: the tile being fetched, and 16-bit shift registers holding the
+    // current tile in the high byte and the next one in the low byte
+    bg_next_tile: u8,
+    bg_next_attr: u8,
+    bg_next_low: u8,
+    bg_next_high: u8,
+    bg_pattern_low: u16,
+    bg_pattern_high: u16,
+    bg_attr_low: u16,
+    bg_attr_high: u16,
     
-    // Sprite evaluation data
+    /// Draw every sprite on a line instead of the first eight. The overflow flag and the
+    /// bus activity still follow the hardware's eight-sprite evaluation.
+    pub unlimited_sprites: bool,
+
+    // Sprite evaluation for the next line, stepped one dot at a time
     secondary_oam: [u8; 32],
+    // Last byte the sprite circuitry read; $2004 returns it while rendering
+    oam_latch: u8,
+    eval_index: u8,
+    // Bytes of an in-range sprite left to copy after its Y
+    eval_copy: u8,
+    eval_found: u8,
+    eval_done: bool,
+    // OAM offset after the eighth sprite found, where unlimited mode keeps searching
+    eval_extra_start: u16,
+    // The first sprite checked was in range; it takes part in sprite 0 hits
+    sprite_zero_next: bool,
+
+    // Sprites for the line being drawn, latched during the previous line's fetches
     sprite_count: u8,
-    sprite_zero_in_secondary: bool,
-    sprite_patterns: [(u8, u8); 8],
-    sprite_positions: [u8; 8],
-    sprite_priorities: [u8; 8],
-    sprite_indexes: [u8; 8],
+    sprite_zero_on_line: bool,
+    sprite_patterns: [(u8, u8); 64],
+    sprite_positions: [u8; 64],
+    sprite_attributes: [u8; 64],
     
-    // Mirroring mode
-    pub mirroring: Mirroring,
+    // Pattern tables ($0000-$1FFF) live on the cartridge, which also routes nametable accesses
+    cartridge: Option<Rc<RefCell<Cartridge>>>,
 }
 
 impl Ppu {
@@ -104,25 +152,46 @@ impl Ppu {
             oam_addr: 0,
             oam_data: [0; 256],
             ppu_data_buffer: 0,
-            vram: [0; 0x4000],
+            io_bus: 0,
+            io_bus_refreshed: [0; 8],
+            ciram: [0; 0x800],
             palette: [0; 32],
             scanline: 0,
             cycle: 0,
             frame: 0,
             frame_buffer: [0; SCREEN_WIDTH * SCREEN_HEIGHT * 3],
             nmi_interrupt: false,
+            nmi_output: false,
+            nmi_delay: 0,
+            suppress_vblank: false,
+            odd_frame: false,
             v: 0,
             t: 0,
             x: 0,
             w: false,
+            bg_next_tile: 0,
+            bg_next_attr: 0,
+            bg_next_low: 0,
+            bg_next_high: 0,
+            bg_pattern_low: 0,
+            bg_pattern_high: 0,
+            bg_attr_low: 0,
+            bg_attr_high: 0,
+            unlimited_sprites: false,
             secondary_oam: [0xFF; 32],
+            oam_latch: 0,
+            eval_index: 0,
+            eval_copy: 0,
+            eval_found: 0,
+            eval_done: false,
+            eval_extra_start: 0,
+            sprite_zero_next: false,
             sprite_count: 0,
-            sprite_zero_in_secondary: false,
-            sprite_patterns: [(0, 0); 8],
-            sprite_positions: [0; 8],
-            sprite_priorities: [0; 8],
-            sprite_indexes: [0; 8],
-            mirroring: Mirroring::Horizontal,
+            sprite_zero_on_line: false,
+            sprite_patterns: [(0, 0); 64],
+            sprite_positions: [0; 64],
+            sprite_attributes: [0; 64],
+            cartridge: None,
         };
         
         // Initialize with default NES palette values
@@ -138,6 +207,10 @@ impl Ppu {
         ppu
     }
 
+    pub fn connect_cartridge(&mut self, cartridge: Rc<RefCell<Cartridge>>) {
+        self.cartridge = Some(cartridge);
+    }
+
     pub fn reset(&mut self) {
         self.ctrl = PpuCtrl::empty();
         self.mask = PpuMask::empty();
@@ -151,18 +224,134 @@ impl Ppu {
         self.x = 0;
         self.w = false;
         self.nmi_interrupt = false;
+        self.nmi_output = false;
+        self.nmi_delay = 0;
+        self.suppress_vblank = false;
+        self.odd_frame = false;
     }
 
-    pub fn read_register(&mut self, address: u16) -> u8 {
-        match address {
-            0x2002 => self.read_status(),
-            0x2004 => self.read_oam_data(),
-            0x2007 => self.read_ppu_data(),
-            _ => 0,
+    /// Save everything except the frame buffer, which is redrawn by the next frame.
+    pub fn save_state(&self, state: &mut StateWriter) {
+        state.write_u8(self.ctrl.bits());
+        state.write_u8(self.mask.bits());
+        state.write_u8(self.status.bits());
+        state.write_u8(self.oam_addr);
+        state.write_bytes(&self.oam_data);
+        state.write_u8(self.ppu_data_buffer);
+        state.write_u8(self.io_bus);
+        for &frame in &self.io_bus_refreshed {
+            state.write_u64(frame);
         }
+        state.write_bytes(&self.ciram);
+        state.write_bytes(&self.palette);
+        state.write_u16(self.scanline);
+        state.write_u16(self.cycle);
+        state.write_u64(self.frame);
+        state.write_bool(self.nmi_interrupt);
+        state.write_bool(self.nmi_output);
+        state.write_u8(self.nmi_delay);
+        state.write_bool(self.suppress_vblank);
+        state.write_bool(self.odd_frame);
+        state.write_u16(self.v);
+        state.write_u16(self.t);
+        state.write_u8(self.x);
+        state.write_bool(self.w);
+        state.write_u8(self.bg_next_tile);
+        state.write_u8(self.bg_next_attr);
+        state.write_u8(self.bg_next_low);
+        state.write_u8(self.bg_next_high);
+        state.write_u16(self.bg_pattern_low);
+        state.write_u16(self.bg_pattern_high);
+        state.write_u16(self.bg_attr_low);
+        state.write_u16(self.bg_attr_high);
+        state.write_bytes(&self.secondary_oam);
+        state.write_u8(self.oam_latch);
+        state.write_u8(self.eval_index);
+        state.write_u8(self.eval_copy);
+        state.write_u8(self.eval_found);
+        state.write_bool(self.eval_done);
+        state.write_u16(self.eval_extra_start);
+        state.write_bool(self.sprite_zero_next);
+        state.write_u8(self.sprite_count);
+        state.write_bool(self.sprite_zero_on_line);
+        for &(low, high) in &self.sprite_patterns {
+            state.write_u8(low);
+            state.write_u8(high);
+        }
+        state.write_bytes(&self.sprite_positions);
+        state.write_bytes(&self.sprite_attributes);
+    }
+
+    pub fn load_state(&mut self, state: &mut StateReader) -> Result<()> {
+        self.ctrl = PpuCtrl::from_bits_truncate(state.read_u8()?);
+        self.mask = PpuMask::from_bits_truncate(state.read_u8()?);
+        self.status = PpuStatus::from_bits_truncate(state.read_u8()?);
+        self.oam_addr = state.read_u8()?;
+        state.read_into(&mut self.oam_data)?;
+        self.ppu_data_buffer = state.read_u8()?;
+        self.io_bus = state.read_u8()?;
+        for frame in &mut self.io_bus_refreshed {
+            *frame = state.read_u64()?;
+        }
+        state.read_into(&mut self.ciram)?;
+        state.read_into(&mut self.palette)?;
+        self.scanline = state.read_u16()?;
+        self.cycle = state.read_u16()?;
+        self.frame = state.read_u64()?;
+        self.nmi_interrupt = state.read_bool()?;
+        self.nmi_output = state.read_bool()?;
+        self.nmi_delay = state.read_u8()?;
+        self.suppress_vblank = state.read_bool()?;
+        self.odd_frame = state.read_bool()?;
+        self.v = state.read_u16()?;
+        self.t = state.read_u16()?;
+        self.x = state.read_u8()?;
+        self.w = state.read_bool()?;
+        self.bg_next_tile = state.read_u8()?;
+        self.bg_next_attr = state.read_u8()?;
+        self.bg_next_low = state.read_u8()?;
+        self.bg_next_high = state.read_u8()?;
+        self.bg_pattern_low = state.read_u16()?;
+        self.bg_pattern_high = state.read_u16()?;
+        self.bg_attr_low = state.read_u16()?;
+        self.bg_attr_high = state.read_u16()?;
+        state.read_into(&mut self.secondary_oam)?;
+        self.oam_latch = state.read_u8()?;
+        self.eval_index = state.read_u8()?;
+        self.eval_copy = state.read_u8()?;
+        self.eval_found = state.read_u8()?;
+        self.eval_done = state.read_bool()?;
+        self.eval_extra_start = state.read_u16()?;
+        self.sprite_zero_next = state.read_bool()?;
+        self.sprite_count = state.read_u8()?;
+        self.sprite_zero_on_line = state.read_bool()?;
+        for pattern in &mut self.sprite_patterns {
+            *pattern = (state.read_u8()?, state.read_u8()?);
+        }
+        state.read_into(&mut self.sprite_positions)?;
+        state.read_into(&mut self.sprite_attributes)?;
+        // Sprite fetches index the 64 slots and OAM with these
+        if self.sprite_count > 64 || self.eval_found > 8 || self.eval_extra_start > 256 {
+            return Err(Error::new(ErrorKind::InvalidData, "Invalid sprite evaluation in save state"));
+        }
+        Ok(())
+    }
+
+    pub fn read_register(&mut self, address: u16) -> u8 {
+        // Bits a register doesn't drive read back from the I/O latch
+        let (value, driven) = match address {
+            0x2002 => (self.read_status(), 0xE0),
+            0x2004 => (self.read_oam_data(), 0xFF),
+            0x2007 if self.v & 0x3FFF >= 0x3F00 => (self.read_ppu_data(), 0x3F),
+            0x2007 => (self.read_ppu_data(), 0xFF),
+            _ => (0, 0x00),
+        };
+        self.drive_io_bus(value, driven);
+        self.io_bus
     }
 
     pub fn write_register(&mut self, address: u16, value: u8) {
+        self.drive_io_bus(value, 0xFF);
         match address {
             0x2000 => self.write_ctrl(value),
             0x2001 => self.write_mask(value),
@@ -175,14 +364,42 @@ impl Ppu {
         }
     }
 
+    fn drive_io_bus(&mut self, value: u8, driven: u8) {
+        self.io_bus = (self.io_bus & !driven) | (value & driven);
+        for (bit, refreshed) in self.io_bus_refreshed.iter_mut().enumerate() {
+            if driven & (1 << bit) != 0 {
+                *refreshed = self.frame;
+            }
+        }
+    }
+
+    fn decay_io_bus(&mut self) {
+        for (bit, &refreshed) in self.io_bus_refreshed.iter().enumerate() {
+            if self.frame.saturating_sub(refreshed) >= IO_BUS_DECAY_FRAMES {
+                self.io_bus &= !(1 << bit);
+            }
+        }
+    }
+
     fn read_status(&mut self) -> u8 {
         let result = self.status.bits();
         self.status.remove(PpuStatus::VBLANK_STARTED);
         self.w = false;  // Clear write latch
+        // Reading on the dot before VBlank starts returns it clear and keeps it from being set.
+        // Reading on the dot it is set, or the next, clears it before the NMI goes out.
+        if self.scanline == 241 && self.cycle == 0 {
+            self.suppress_vblank = true;
+        }
+        self.update_nmi_output();
         result
     }
 
     fn read_oam_data(&self) -> u8 {
+        // While sprites are being evaluated and fetched, $2004 shows what that circuitry reads
+        let rendering = self.mask.intersects(PpuMask::SHOW_BG | PpuMask::SHOW_SPRITES);
+        if rendering && self.scanline < 240 && (1..=320).contains(&self.cycle) {
+            return self.oam_latch;
+        }
         self.oam_data[self.oam_addr as usize]
     }
 
@@ -194,25 +411,18 @@ impl Ppu {
             buffered
         } else {
             self.ppu_data_buffer = self.read_vram(addr - 0x1000);
-            self.read_vram(addr)
+            let color = self.read_vram(addr);
+            self.greyscale(color)
         };
         
-        if self.ctrl.contains(PpuCtrl::VRAM_INCREMENT) {
-            self.v = (self.v + 32) & 0x7FFF;
-        } else {
-            self.v = (self.v + 1) & 0x7FFF;
-        }
+        self.increment_vram_addr();
         result
     }
 
     fn write_ctrl(&mut self, value: u8) {
-        let prev_nmi = self.ctrl.contains(PpuCtrl::NMI_ENABLE);
         self.ctrl = PpuCtrl::from_bits_truncate(value);
-        
-        if !prev_nmi && self.ctrl.contains(PpuCtrl::NMI_ENABLE) 
-            && self.status.contains(PpuStatus::VBLANK_STARTED) {
-            self.nmi_interrupt = true;
-        }
+        // Enabling NMI during VBlank raises it right away; toggling it can raise it again
+        self.update_nmi_output();
         
         // Set nametable bits in temporary address
         self.t = (self.t & !0x0C00) | ((value as u16 & 0x03) << 10);
@@ -253,6 +463,7 @@ impl Ppu {
             // Second write (low byte)
             self.t = (self.t & 0xFF00) | value as u16;
             self.v = self.t;  // Copy t to v
+            self.set_bus_address(self.v & 0x3FFF);
         }
         self.w = !self.w;
     }
@@ -260,25 +471,49 @@ impl Ppu {
     fn write_ppu_data(&mut self, value: u8) {
         let addr = self.v & 0x3FFF;
         self.write_vram(addr, value);
-        
+        self.increment_vram_addr();
+    }
+
+    fn increment_vram_addr(&mut self) {
         if self.ctrl.contains(PpuCtrl::VRAM_INCREMENT) {
             self.v = (self.v + 32) & 0x7FFF;
         } else {
             self.v = (self.v + 1) & 0x7FFF;
         }
+        self.set_bus_address(self.v & 0x3FFF);
+    }
+
+    /// Outside of rendering the PPU leaves v on its address bus, where boards watching
+    /// A12 can see it change.
+    fn set_bus_address(&self, addr: u16) {
+        if let Some(ref cart) = self.cartridge {
+            cart.borrow_mut().notify_ppu_addr(addr);
+        }
     }
 
     pub fn _oam_dma(&mut self, data: &[u8; 256]) {
         self.oam_data.copy_from_slice(data);
     }
 
+    /// Pattern read hidden from the cartridge, so fetches the real PPU doesn't make can't
+    /// disturb the board.
+    fn peek_chr(&self, addr: u16) -> u8 {
+        match self.cartridge {
+            Some(ref cart) => cart.borrow_mut().peek_chr(addr),
+            None => 0,
+        }
+    }
+
     fn read_vram(&self, addr: u16) -> u8 {
         match addr {
-            0x0000..=0x1FFF => self.vram[addr as usize],
-            0x2000..=0x2FFF => {
-                let mirrored_addr = self.mirror_nametable_addr(addr);
-                self.vram[mirrored_addr as usize]
-            }
+            0x0000..=0x1FFF => match self.cartridge {
+                Some(ref cart) => cart.borrow_mut().read_chr(addr),
+                None => 0,
+            },
+            0x2000..=0x2FFF => match self.cartridge {
+                Some(ref cart) => cart.borrow_mut().read_nametable(addr, &self.ciram),
+                None => self.ciram[Mirroring::Horizontal.ciram_offset(addr)],
+            },
             0x3000..=0x3EFF => {
                 // Mirror of 0x2000-0x2EFF
                 self.read_vram(addr - 0x1000)
@@ -298,11 +533,15 @@ impl Ppu {
 
     fn write_vram(&mut self, addr: u16, value: u8) {
         match addr {
-            0x0000..=0x1FFF => self.vram[addr as usize] = value,
-            0x2000..=0x2FFF => {
-                let mirrored_addr = self.mirror_nametable_addr(addr);
-                self.vram[mirrored_addr as usize] = value;
+            0x0000..=0x1FFF => {
+                if let Some(ref cart) = self.cartridge {
+                    cart.borrow_mut().write_chr(addr, value);
+                }
             }
+            0x2000..=0x2FFF => match self.cartridge {
+                Some(ref cart) => cart.borrow_mut().write_nametable(addr, value, &mut self.ciram),
+                None => self.ciram[Mirroring::Horizontal.ciram_offset(addr)] = value,
+            },
             0x3000..=0x3EFF => {
                 // Mirror of 0x2000-0x2EFF
                 self.write_vram(addr - 0x1000, value);
@@ -320,101 +559,50 @@ impl Ppu {
         }
     }
     
-    fn mirror_nametable_addr(&self, addr: u16) -> u16 {
-        let table = (addr - 0x2000) / 0x400;
-        let offset = (addr - 0x2000) % 0x400;
-        
-        let mirrored_table = match self.mirroring {
-            Mirroring::Horizontal => {
-                // 0,1 -> 0; 2,3 -> 1
-                match table {
-                    0 | 1 => 0,
-                    2 | 3 => 1,
-                    _ => table & 0x03,
-                }
-            }
-            Mirroring::Vertical => {
-                // 0,2 -> 0; 1,3 -> 1
-                match table {
-                    0 | 2 => 0,
-                    1 | 3 => 1,
-                    _ => table & 0x03,
-                }
-            }
-            Mirroring::FourScreen => {
-                // Each table is separate (no mirroring)
-                table & 0x03
-            }
-            Mirroring::_SingleScreenLower => {
-                // All tables map to table 0
-                0
-            }
-            Mirroring::_SingleScreenUpper => {
-                // All tables map to table 1
-                1
-            }
-        };
-        
-        0x2000 + mirrored_table * 0x400 + offset
-    }
-
     pub fn step(&mut self) {
         self.cycle += 1;
+        self.clock_nmi_delay();
         
         let rendering_enabled = self.mask.contains(PpuMask::SHOW_BG) || self.mask.contains(PpuMask::SHOW_SPRITES);
 
         if self.scanline < 240 {
             // Visible scanlines (0-239)
-            if self.cycle == 1 {
-                // Clear secondary OAM for sprite evaluation
-                for i in 0..32 {
-                    self.secondary_oam[i] = 0xFF;
-                }
-                self.sprite_count = 0;
-                self.sprite_zero_in_secondary = false;
-            }
-            
-            // Sprite evaluation happens during cycles 65-256
-            if self.cycle == 65 && self.mask.contains(PpuMask::SHOW_SPRITES) {
-                self.evaluate_sprites();
-            }
-            
-            // Sprite fetching happens during cycles 257-320
-            if self.cycle == 257 && self.mask.contains(PpuMask::SHOW_SPRITES) {
-                self.fetch_sprites();
+            if rendering_enabled {
+                self.background_fetches();
+                self.sprite_evaluation();
+                self.sprite_fetches();
             }
-            
+
             // Render when background or sprites are enabled
             if rendering_enabled && self.cycle >= 1 && self.cycle <= 256 {
                 self.render_pixel();
             }
-            
-            // Scrolling updates for rendering - simplified for now
-            if rendering_enabled {
-                // Basic scrolling support - will improve later
-                if self.cycle == 257 {
-                    self.copy_x();  // Copy horizontal bits from t to v
-                }
-            }
         } else if self.scanline == 241 && self.cycle == 1 {
-            self.status.insert(PpuStatus::VBLANK_STARTED);
-            if self.ctrl.contains(PpuCtrl::NMI_ENABLE) {
-                self.nmi_interrupt = true;
+            if !std::mem::take(&mut self.suppress_vblank) {
+                self.status.insert(PpuStatus::VBLANK_STARTED);
             }
+            self.update_nmi_output();
         } else if self.scanline == 261 {
             if self.cycle == 1 {
                 self.status.remove(PpuStatus::VBLANK_STARTED);
                 self.status.remove(PpuStatus::SPRITE_ZERO_HIT);
                 self.status.remove(PpuStatus::SPRITE_OVERFLOW);
+                self.update_nmi_output();
+                // Nothing is evaluated here, so line 0 never has sprites
+                self.eval_found = 0;
+                self.sprite_zero_next = false;
             }
             
-            // Pre-render scanline updates - simplified for now
+            // The pre-render line fetches like a visible one and reloads the vertical scroll
             if rendering_enabled {
+                self.background_fetches();
+                self.sprite_fetches();
                 if self.cycle >= 280 && self.cycle <= 304 {
                     self.copy_y();  // Copy vertical bits from t to v
                 }
-                if self.cycle == 257 {
-                    self.copy_x();
+                // Odd frames skip the pre-render line's last dot while rendering
+                if self.odd_frame && self.cycle == 339 {
+                    self.cycle = 340;
                 }
             }
         }
@@ -426,10 +614,31 @@ impl Ppu {
             if self.scanline > 261 {
                 self.scanline = 0;
                 self.frame += 1;
+                self.odd_frame = !self.odd_frame;
+                self.decay_io_bus();
             }
         }
     }
 
+    /// Follow the /NMI output after the VBlank flag or NMI_ENABLE changes. A rising edge
+    /// reaches the CPU NMI_DELAY dots later, unless the output drops again first.
+    fn update_nmi_output(&mut self) {
+        let output = self.status.contains(PpuStatus::VBLANK_STARTED) && self.ctrl.contains(PpuCtrl::NMI_ENABLE);
+        if !output {
+            self.nmi_delay = 0;
+        } else if !self.nmi_output {
+            self.nmi_delay = NMI_DELAY;
+        }
+        self.nmi_output = output;
+    }
+
+    fn clock_nmi_delay(&mut self) {
+        if self.nmi_delay > 0 {
+            self.nmi_delay -= 1;
+            self.nmi_interrupt |= self.nmi_delay == 0;
+        }
+    }
+
     fn render_pixel(&mut self) {
         let x = (self.cycle - 1) as usize;
         let y = self.scanline as usize;
@@ -445,7 +654,7 @@ impl Ppu {
             // Get background pixel if enabled
             if self.mask.contains(PpuMask::SHOW_BG) {
                 if x >= 8 || self.mask.contains(PpuMask::SHOW_BG_LEFT) {
-                    let bg_data = self.get_background_pixel(x as u16, y as u16);
+                    let bg_data = self.get_background_pixel();
                     bg_pixel = bg_data & 0x03;
                     bg_palette = bg_data >> 2;
                 }
@@ -539,207 +748,312 @@ impl Ppu {
         self.v = (self.v & !0x7BE0) | (self.t & 0x7BE0);
     }
     
-    fn get_background_pixel(&self, screen_x: u16, screen_y: u16) -> u8 {
-        // Use PPU scroll registers (v and x) to determine actual position in nametable(s)
-        // The v register contains the current VRAM address during rendering:
-        // Bits 0-4:   Coarse X scroll (0-31)
-        // Bits 5-9:   Coarse Y scroll (0-29)
-        // Bits 10-11: Nametable select (0-3)
-        // Bits 12-14: Fine Y scroll (0-7)
-        //
-        // The x register (3 bits) contains the fine X scroll (0-7)
-
-        // Extract scroll position from v register
-        let coarse_x = (self.v & 0x001F) as u16;  // Bits 0-4
-        let coarse_y = ((self.v >> 5) & 0x001F) as u16;  // Bits 5-9
-        let nametable_select = ((self.v >> 10) & 0x0003) as u16;  // Bits 10-11
-        let fine_y_scroll = ((self.v >> 12) & 0x0007) as u16;  // Bits 12-14
-        let fine_x_scroll = self.x as u16;  // Fine X from x register
-
-        // Calculate scrolled position
-        // Add screen position to scroll position
-        let scrolled_x = screen_x + (coarse_x * 8) + fine_x_scroll;
-        let scrolled_y = screen_y + (coarse_y * 8) + fine_y_scroll;
-
-        // Determine which tile we're in
-        let tile_x = (scrolled_x / 8) % 32;  // Wrap at 32 tiles (256 pixels)
-        let tile_y = (scrolled_y / 8) % 30;  // Wrap at 30 tiles (240 pixels)
-        let fine_x = scrolled_x % 8;
-        let fine_y = scrolled_y % 8;
-
-        // Handle nametable wrapping and selection
-        // Each nametable is 32x30 tiles (256x240 pixels)
-        let nt_x_offset = if scrolled_x >= 256 { 1 } else { 0 };
-        let nt_y_offset = if scrolled_y >= 240 { 2 } else { 0 };
-        let effective_nametable = (nametable_select + nt_x_offset + nt_y_offset) % 4;
-
-        // Calculate nametable base address (0x2000, 0x2400, 0x2800, or 0x2C00)
-        let nametable_base = 0x2000 + (effective_nametable * 0x400);
-        let nametable_addr = nametable_base + tile_y * 32 + tile_x;
-        let tile_id = self.read_vram(nametable_addr) as u16;
-
-        // Get pattern from CHR ROM area
-        let pattern_base = if self.ctrl.contains(PpuCtrl::BG_PATTERN) { 0x1000 } else { 0x0000 };
-        let pattern_addr = pattern_base + tile_id * 16 + fine_y;
-
-        let low_byte = self.vram[(pattern_addr & 0x1FFF) as usize];
-        let high_byte = self.vram[((pattern_addr + 8) & 0x1FFF) as usize];
-
-        let bit = 7 - fine_x;
-        let pixel = ((high_byte >> bit) & 1) << 1 | ((low_byte >> bit) & 1);
-
+    /// Current background pixel from the shift registers, selected by fine X:
+    /// palette in bits 2-3, color in bits 0-1.
+    fn get_background_pixel(&self) -> u8 {
+        let mux = 0x8000 >> self.x;
+        let pixel = ((self.bg_pattern_high & mux != 0) as u8) << 1 | (self.bg_pattern_low & mux != 0) as u8;
         if pixel == 0 {
             return 0; // Universal background
         }
+        let palette = ((self.bg_attr_high & mux != 0) as u8) << 1 | (self.bg_attr_low & mux != 0) as u8;
+        palette << 2 | pixel
+    }
+
+    /// Background fetch cycle: every 8 dots the nametable byte, attribute byte and both pattern
+    /// planes of the next tile, loaded into the shift registers as the previous tile runs out.
+    /// Dots 321-336 prefetch the first two tiles of the next line.
+    fn background_fetches(&mut self) {
+        let cycle = self.cycle;
+        if (2..=257).contains(&cycle) || (322..=337).contains(&cycle) {
+            self.shift_background();
+        }
+
+        if (1..=256).contains(&cycle) || (321..=336).contains(&cycle) {
+            match (cycle - 1) % 8 {
+                0 => {
+                    self.load_background_shifters();
+                    self.bg_next_tile = self.read_vram(0x2000 | (self.v & 0x0FFF));
+                }
+                2 => {
+                    let attr_addr = 0x23C0 | (self.v & 0x0C00) | ((self.v >> 4) & 0x38) | ((self.v >> 2) & 0x07);
+                    let mut attr = self.read_vram(attr_addr);
+                    // Each attribute byte covers 4x4 tiles, two bits per 2x2 quadrant
+                    if self.v & 0x0040 != 0 {
+                        attr >>= 4;
+                    }
+                    if self.v & 0x0002 != 0 {
+                        attr >>= 2;
+                    }
+                    self.bg_next_attr = attr & 0x03;
+                }
+                4 => self.bg_next_low = self.read_vram(self.background_pattern_addr()),
+                6 => self.bg_next_high = self.read_vram(self.background_pattern_addr() + 8),
+                7 => self.increment_x(),
+                _ => {}
+            }
+        }
 
-        // Get attribute for palette selection
-        let attr_table_x = tile_x / 4;
-        let attr_table_y = tile_y / 4;
-        let attr_base = nametable_base + 0x3C0;  // Attribute table offset
-        let attr_addr = attr_base + attr_table_y * 8 + attr_table_x;
-        let attr_byte = self.read_vram(attr_addr);
+        match cycle {
+            256 => self.increment_y(),
+            257 => {
+                self.load_background_shifters();
+                self.copy_x();
+            }
+            // Two unused nametable fetches end the line
+            337 | 339 => {
+                self.bg_next_tile = self.read_vram(0x2000 | (self.v & 0x0FFF));
+            }
+            _ => {}
+        }
+    }
 
-        let palette_shift = ((tile_y % 4) / 2) * 4 + ((tile_x % 4) / 2) * 2;
-        let palette_index = ((attr_byte >> palette_shift) & 0x03) << 2;
+    fn background_pattern_addr(&self) -> u16 {
+        let base = if self.ctrl.contains(PpuCtrl::BG_PATTERN) { 0x1000 } else { 0x0000 };
+        let fine_y = (self.v >> 12) & 0x07;
+        base + self.bg_next_tile as u16 * 16 + fine_y
+    }
 
-        palette_index | pixel
+    /// Move the fetched tile into the low byte of the shift registers. Attribute bits are
+    /// expanded to a full byte so they shift out alongside the pattern.
+    fn load_background_shifters(&mut self) {
+        self.bg_pattern_low = (self.bg_pattern_low & 0xFF00) | self.bg_next_low as u16;
+        self.bg_pattern_high = (self.bg_pattern_high & 0xFF00) | self.bg_next_high as u16;
+        let attr_low = if self.bg_next_attr & 0x01 != 0 { 0xFF } else { 0x00 };
+        let attr_high = if self.bg_next_attr & 0x02 != 0 { 0xFF } else { 0x00 };
+        self.bg_attr_low = (self.bg_attr_low & 0xFF00) | attr_low;
+        self.bg_attr_high = (self.bg_attr_high & 0xFF00) | attr_high;
+    }
+
+    fn shift_background(&mut self) {
+        self.bg_pattern_low <<= 1;
+        self.bg_pattern_high <<= 1;
+        self.bg_attr_low <<= 1;
+        self.bg_attr_high <<= 1;
+    }
+
+    /// Sprite pattern fetches for the next line on dots 257-320, loading the latches the
+    /// renderer draws from. Boards like MMC3 count scanlines by watching these addresses.
+    fn sprite_fetches(&mut self) {
+        if let 257..=320 = self.cycle {
+            // OAMADDR is held at 0 throughout the fetches
+            self.oam_addr = 0;
+            if self.cycle == 257 {
+                self.sprite_count = self.eval_found;
+                self.sprite_zero_on_line = self.sprite_zero_next;
+            }
+            let slot = ((self.cycle - 257) / 8) as usize;
+            match (self.cycle - 257) % 8 {
+                // Two garbage nametable fetches before each sprite's pattern bytes
+                0 | 2 => {
+                    self.read_vram(0x2000 | (self.v & 0x0FFF));
+                }
+                4 => self.fetch_sprite(slot, false),
+                6 => self.fetch_sprite(slot, true),
+                _ => {}
+            }
+            if self.cycle == 320 && self.unlimited_sprites {
+                self.fetch_extra_sprites();
+            }
+        }
     }
 
+    /// Output color for a palette RAM index, with PPUMASK greyscale and emphasis applied.
     fn get_color_from_palette(&self, index: u8) -> (u8, u8, u8) {
-        let palette_entry = self.palette[(index & 0x1F) as usize] & 0x3F;
-        // Return a default gray if palette entry is 0 and it's the background
-        if palette_entry == 0 && index == 0 {
-            return (0x75, 0x75, 0x75); // Default gray background
+        let color = self.greyscale(self.palette[(index & 0x1F) as usize] & 0x3F);
+        let emphasis = (self.mask.bits() >> 5) as usize;
+        EMPHASIS_PALETTE[emphasis << 6 | color as usize]
+    }
+
+    /// Greyscale keeps only the brightness column of a color, for output and palette reads.
+    fn greyscale(&self, color: u8) -> u8 {
+        if self.mask.contains(PpuMask::GRAYSCALE) {
+            color & 0x30
+        } else {
+            color
         }
-        NES_PALETTE[palette_entry as usize]
     }
 
     pub fn get_frame_buffer(&self) -> &[u8] {
         &self.frame_buffer
     }
     
-    fn evaluate_sprites(&mut self) {
-        let mut secondary_index = 0;
-        self.sprite_count = 0;
-        self.sprite_zero_in_secondary = false;
-        
-        let sprite_height = if self.ctrl.contains(PpuCtrl::SPRITE_SIZE) { 16 } else { 8 };
-        // Sprites are evaluated for the NEXT scanline
-        let y = (self.scanline + 1) as i16;
-        
-        // Evaluate all 64 sprites
-        for sprite_index in 0..64 {
-            if secondary_index >= 32 {
-                // Secondary OAM is full, set overflow flag
-                self.status.insert(PpuStatus::SPRITE_OVERFLOW);
-                break;
+    fn sprite_height(&self) -> u16 {
+        if self.ctrl.contains(PpuCtrl::SPRITE_SIZE) { 16 } else { 8 }
+    }
+
+    /// Sprite evaluation for the next line, one step per dot: dots 1-64 clear secondary OAM,
+    /// then dots 65-256 alternate between reading OAM at OAMADDR and acting on the byte read.
+    fn sprite_evaluation(&mut self) {
+        match self.cycle {
+            1..=64 => {
+                if self.cycle == 1 {
+                    self.eval_index = 0;
+                    self.eval_copy = 0;
+                    self.eval_found = 0;
+                    self.eval_done = false;
+                    self.sprite_zero_next = false;
+                }
+                self.oam_latch = 0xFF;
+                if self.cycle & 1 == 0 {
+                    self.secondary_oam[(self.cycle / 2 - 1) as usize] = 0xFF;
+                }
             }
-            
-            let oam_offset = sprite_index * 4;
-            let sprite_y = self.oam_data[oam_offset] as i16 + 1; // Sprites are delayed by one scanline
-            
-            // Check if sprite is on the next scanline
-            if y >= sprite_y && y < sprite_y + sprite_height {
-                // Copy sprite to secondary OAM
-                if secondary_index < 32 {
-                    for i in 0..4 {
-                        self.secondary_oam[secondary_index + i] = self.oam_data[oam_offset + i];
-                    }
-                    
-                    if sprite_index == 0 {
-                        self.sprite_zero_in_secondary = true;
-                    }
-                    
-                    if self.sprite_count < 8 {
-                        self.sprite_indexes[self.sprite_count as usize] = sprite_index as u8;
+            65..=256 if self.cycle & 1 == 1 => self.oam_latch = self.oam_data[self.oam_addr as usize],
+            65..=256 => self.evaluation_step(),
+            _ => {}
+        }
+    }
+
+    /// Act on the OAM byte read on the previous dot. The search starts wherever OAMADDR points
+    /// and copies up to eight in-range sprites. While looking for a ninth to set the overflow
+    /// flag, the hardware advances both the sprite index and the byte within the sprite, so it
+    /// compares tile numbers and attributes as Y coordinates: the overflow flag's false positives
+    /// and negatives.
+    fn evaluation_step(&mut self) {
+        let addr = self.oam_addr;
+        let latch = self.oam_latch;
+
+        if self.eval_done {
+            // Every sprite has been checked; keep stepping without copying anything
+            self.oam_addr = addr.wrapping_add(4);
+            return;
+        }
+
+        if self.eval_copy > 0 {
+            // The rest of an in-range sprite
+            self.secondary_oam[self.eval_index as usize] = latch;
+            self.eval_index += 1;
+            self.eval_copy -= 1;
+            self.oam_addr = addr.wrapping_add(1);
+        } else {
+            let in_range = self.scanline.wrapping_sub(latch as u16) < self.sprite_height();
+            if self.eval_found < 8 {
+                // Y is copied whether or not the sprite is in range
+                self.secondary_oam[self.eval_index as usize] = latch;
+                if self.cycle == 66 {
+                    self.sprite_zero_next = in_range;
+                }
+                if in_range {
+                    self.eval_index += 1;
+                    self.eval_copy = 3;
+                    self.eval_found += 1;
+                    if self.eval_found == 8 {
+                        self.eval_extra_start = (addr & 0xFC) as u16 + 4;
                     }
-                    self.sprite_count += 1;
-                    secondary_index += 4;
+                    self.oam_addr = addr.wrapping_add(1);
+                } else {
+                    self.oam_addr = addr.wrapping_add(4);
                 }
+            } else if in_range {
+                self.status.insert(PpuStatus::SPRITE_OVERFLOW);
+                self.eval_done = true;
+                self.oam_addr = addr.wrapping_add(4);
+            } else {
+                self.oam_addr = (addr.wrapping_add(4) & 0xFC) | (addr.wrapping_add(1) & 0x03);
             }
         }
+
+        // Wrapping past sprite 63 ends the search
+        if self.oam_addr < addr {
+            self.eval_done = true;
+        }
     }
-    
-    fn fetch_sprites(&mut self) {
-        let sprite_height = if self.ctrl.contains(PpuCtrl::SPRITE_SIZE) { 16 } else { 8 };
-        
-        for i in 0..self.sprite_count.min(8) {
-            let oam_offset = i as usize * 4;
-            let y_pos = self.secondary_oam[oam_offset];
-            let tile_index = self.secondary_oam[oam_offset + 1];
-            let attributes = self.secondary_oam[oam_offset + 2];
-            let x_pos = self.secondary_oam[oam_offset + 3];
-            
-            // Calculate the line of the sprite to fetch (sprites fetched are for the next scanline)
-            let sprite_y = (self.scanline + 1).wrapping_sub(y_pos as u16 + 1);
-            
-            // Handle vertical flip
-            let actual_y = if (attributes & 0x80) != 0 {
-                // Vertically flipped
-                (sprite_height - 1) - sprite_y
-            } else {
-                sprite_y
-            };
-            
-            // Determine pattern table address
-            let pattern_addr = if sprite_height == 8 {
-                // 8x8 sprites
-                let base = if self.ctrl.contains(PpuCtrl::SPRITE_PATTERN) { 0x1000 } else { 0x0000 };
-                base + (tile_index as u16 * 16) + (actual_y & 7)
-            } else {
-                // 8x16 sprites
-                let bank = (tile_index & 1) as u16 * 0x1000;
-                let tile = (tile_index & 0xFE) as u16;
-                let offset = if actual_y >= 8 { actual_y - 8 + 16 } else { actual_y };
-                bank + (tile * 16) + offset
-            };
-            
-            // Fetch pattern data
-            let low_byte = self.vram[(pattern_addr & 0x1FFF) as usize];
-            let high_byte = self.vram[((pattern_addr + 8) & 0x1FFF) as usize];
-            
-            // Handle horizontal flip
-            let (low, high) = if (attributes & 0x40) != 0 {
-                // Horizontally flipped
-                (reverse_byte(low_byte), reverse_byte(high_byte))
-            } else {
-                (low_byte, high_byte)
-            };
-            
-            self.sprite_patterns[i as usize] = (low, high);
-            self.sprite_positions[i as usize] = x_pos;
-            self.sprite_priorities[i as usize] = attributes & 0x20;
+
+    /// Pattern address of one row of a sprite on the current line.
+    fn sprite_pattern_addr(&self, sprite: [u8; 4]) -> u16 {
+        let [y, tile, attributes, _] = sprite;
+        let sprite_height = self.sprite_height();
+        let row = self.scanline.wrapping_sub(y as u16) % sprite_height;
+        // Handle vertical flip
+        let row = if attributes & 0x80 != 0 { sprite_height - 1 - row } else { row };
+
+        if sprite_height == 8 {
+            let base = if self.ctrl.contains(PpuCtrl::SPRITE_PATTERN) { 0x1000 } else { 0x0000 };
+            base + tile as u16 * 16 + row
+        } else {
+            // 8x16 sprites pick the pattern table with bit 0 of the tile number
+            let bank = (tile & 1) as u16 * 0x1000;
+            let tile = (tile & 0xFE) as u16 + row / 8;
+            bank + tile * 16 + row % 8
         }
     }
-    
-    fn get_sprite_pixel(&self, x: u8) -> (u8, bool, bool) {
-        if self.sprite_count == 0 {
-            return (0, false, false);
+
+    /// Fetch one plane of a sprite slot's pattern for the next line. Empty slots still fetch
+    /// tile $FF, which 8x16 sprites take from $1000.
+    fn fetch_sprite(&mut self, slot: usize, high_plane: bool) {
+        let plane = if high_plane { 8 } else { 0 };
+        let mut sprite = [0; 4];
+        sprite.copy_from_slice(&self.secondary_oam[slot * 4..slot * 4 + 4]);
+        self.oam_latch = sprite[if high_plane { 3 } else { 2 }];
+
+        if slot >= self.eval_found as usize {
+            let base = if self.sprite_height() == 16 || self.ctrl.contains(PpuCtrl::SPRITE_PATTERN) { 0x1000 } else { 0x0000 };
+            let tile = if self.sprite_height() == 16 { 0xFE } else { 0xFF };
+            self.read_vram(base + tile * 16 + plane);
+            return;
         }
-        
-        for i in 0..self.sprite_count.min(8) {
-            let sprite_x = self.sprite_positions[i as usize];
-            
-            if x >= sprite_x && x < sprite_x.wrapping_add(8) {
-                let pixel_x = x.wrapping_sub(sprite_x);
-                let (low, high) = self.sprite_patterns[i as usize];
-                
-                let bit = 7 - pixel_x;
-                let pixel_value = ((high >> bit) & 1) << 1 | ((low >> bit) & 1);
-                
-                if pixel_value != 0 {
-                    // Get palette from attributes
-                    let oam_index = (i as usize * 4 + 2).min(31);
-                    let attributes = self.secondary_oam[oam_index];
-                    let palette = (attributes & 0x03) + 4; // Sprite palettes are 4-7
-                    let priority = (attributes & 0x20) != 0;
-                    let is_sprite_zero = i == 0 && self.sprite_zero_in_secondary;
-                    
-                    return ((palette << 2) | pixel_value, priority, is_sprite_zero);
-                }
+
+        let byte = self.read_vram(self.sprite_pattern_addr(sprite) + plane);
+        self.latch_sprite(slot, sprite, byte, high_plane);
+    }
+
+    /// Unlimited sprite mode: fetch the in-range sprites past the first eight at the end of
+    /// the sprite fetches. They come from OAM directly, leaving secondary OAM as it was.
+    fn fetch_extra_sprites(&mut self) {
+        if self.eval_found < 8 {
+            return;
+        }
+        for offset in (self.eval_extra_start as usize..256).step_by(4) {
+            let mut sprite = [0; 4];
+            sprite.copy_from_slice(&self.oam_data[offset..offset + 4]);
+            if self.scanline.wrapping_sub(sprite[0] as u16) >= self.sprite_height() {
+                continue;
             }
+            let slot = self.sprite_count as usize;
+            let addr = self.sprite_pattern_addr(sprite);
+            let low = self.peek_chr(addr);
+            let high = self.peek_chr(addr + 8);
+            self.latch_sprite(slot, sprite, low, false);
+            self.latch_sprite(slot, sprite, high, true);
+            self.sprite_count += 1;
         }
-        
+    }
+
+    fn latch_sprite(&mut self, slot: usize, sprite: [u8; 4], byte: u8, high_plane: bool) {
+        let [_, _, attributes, x] = sprite;
+        // Handle horizontal flip
+        let byte = if attributes & 0x40 != 0 { reverse_byte(byte) } else { byte };
+        let pattern = &mut self.sprite_patterns[slot];
+        if high_plane {
+            pattern.1 = byte;
+        } else {
+            pattern.0 = byte;
+        }
+        self.sprite_positions[slot] = x;
+        self.sprite_attributes[slot] = attributes;
+    }
+
+    fn get_sprite_pixel(&self, x: u8) -> (u8, bool, bool) {
+        for i in 0..self.sprite_count as usize {
+            let column = (x as u16).wrapping_sub(self.sprite_positions[i] as u16);
+            if column >= 8 {
+                continue;
+            }
+            let (low, high) = self.sprite_patterns[i];
+            let bit = 7 - column;
+            let pixel_value = ((high >> bit) & 1) << 1 | ((low >> bit) & 1);
+
+            if pixel_value != 0 {
+                let attributes = self.sprite_attributes[i];
+                let palette = (attributes & 0x03) + 4; // Sprite palettes are 4-7
+                let priority = (attributes & 0x20) != 0;
+                let is_sprite_zero = i == 0 && self.sprite_zero_on_line;
+
+                return ((palette << 2) | pixel_value, priority, is_sprite_zero);
+            }
+        }
+
         (0, false, false)
     }
 }
@@ -774,4 +1088,455 @@ const NES_PALETTE: [(u8, u8, u8); 64] = [
     (0xFF, 0xC7, 0xFF), (0xFF, 0xC7, 0xDB), (0xFF, 0xBF, 0xB3), (0xFF, 0xDB, 0xAB),
     (0xFF, 0xE7, 0xA3), (0xE3, 0xFF, 0xA3), (0xAB, 0xF3, 0xBF), (0xB3, 0xFF, 0xCF),
     (0x9F, 0xFF, 0xF3), (0xDD, 0xDD, 0xDD), (0x11, 0x11, 0x11), (0x11, 0x11, 0x11),
-];
\ No newline at end of file
+];
+
+/// NES_PALETTE under each combination of the PPUMASK emphasis bits, indexed by
+/// emphasis << 6 | color. Emphasizing a channel dims the other two.
+const EMPHASIS_PALETTE: [(u8, u8, u8); 512] = emphasis_palette();
+
+const fn emphasis_palette() -> [(u8, u8, u8); 512] {
+    const fn attenuate(level: u8, dimmed: bool) -> u8 {
+        if dimmed { (level as u16 * 209 / 256) as u8 } else { level }
+    }
+
+    let mut table = [(0, 0, 0); 512];
+    let mut index = 0;
+    while index < 512 {
+        let (r, g, b) = NES_PALETTE[index & 0x3F];
+        // Bit 0 emphasizes red, bit 1 green, bit 2 blue
+        let emphasis = index >> 6;
+        table[index] = (
+            attenuate(r, emphasis & 0b110 != 0),
+            attenuate(g, emphasis & 0b101 != 0),
+            attenuate(b, emphasis & 0b011 != 0),
+        );
+        index += 1;
+    }
+    table
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Ppu, PpuStatus, NES_PALETTE, SCREEN_WIDTH};
+    use crate::cartridge::Cartridge;
+    use crate::state::{StateReader, StateWriter};
+    use std::cell::RefCell;
+    use std::rc::Rc;
+
+    /// PPU on an NROM board with CHR RAM, where tile 1 is solid color 1 (white).
+    fn ppu_with_solid_tile() -> Ppu {
+        let mut rom = vec![0x4E, 0x45, 0x53, 0x1A, 1, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0];
+        rom.extend(vec![0; 0x4000]);
+        let mut ppu = Ppu::new();
+        ppu.connect_cartridge(Rc::new(RefCell::new(Cartridge::load_from_bytes(&rom).unwrap())));
+
+        write_vram(&mut ppu, 0x0010, &[0xFF; 8]);
+        write_vram(&mut ppu, 0x3F00, &[0x0F, 0x30]);
+        ppu
+    }
+
+    fn write_vram(ppu: &mut Ppu, addr: u16, data: &[u8]) {
+        ppu.write_register(0x2006, (addr >> 8) as u8);
+        ppu.write_register(0x2006, addr as u8);
+        for &value in data {
+            ppu.write_register(0x2007, value);
+        }
+    }
+
+    fn run_until(ppu: &mut Ppu, scanline: u16, cycle: u16) {
+        while ppu.scanline != scanline || ppu.cycle != cycle {
+            ppu.step();
+        }
+    }
+
+    fn is_lit(ppu: &Ppu, x: usize, y: usize) -> bool {
+        let offset = (y * SCREEN_WIDTH + x) * 3;
+        let (r, g, b) = NES_PALETTE[0x30];
+        ppu.frame_buffer[offset..offset + 3] == [r, g, b]
+    }
+
+    #[test]
+    fn background_follows_scroll_writes_mid_frame() {
+        let mut ppu = ppu_with_solid_tile();
+        // A column of tile 1 at x = 8-15 in the first nametable
+        ppu.write_register(0x2000, 0x04);
+        write_vram(&mut ppu, 0x2001, &[1; 30]);
+        ppu.write_register(0x2000, 0x00);
+        ppu.write_register(0x2005, 0);
+        ppu.write_register(0x2005, 0);
+        ppu.write_register(0x2001, 0x0A);
+
+        // Let the pre-render line load the scroll, then move 8 pixels right partway down
+        run_until(&mut ppu, 0, 0);
+        run_until(&mut ppu, 120, 0);
+        ppu.write_register(0x2005, 8);
+        ppu.write_register(0x2005, 0);
+        run_until(&mut ppu, 240, 0);
+
+        assert!(is_lit(&ppu, 8, 50) && is_lit(&ppu, 15, 50));
+        assert!(!is_lit(&ppu, 7, 50) && !is_lit(&ppu, 16, 50));
+        // The new X scroll takes effect at dot 257, so from the next line on
+        assert!(is_lit(&ppu, 8, 120));
+        assert!(is_lit(&ppu, 0, 121) && is_lit(&ppu, 7, 121));
+        assert!(!is_lit(&ppu, 8, 121));
+    }
+
+    #[test]
+    fn fine_x_scroll_shifts_the_background_by_pixels() {
+        let mut ppu = ppu_with_solid_tile();
+        ppu.write_register(0x2000, 0x04);
+        write_vram(&mut ppu, 0x2001, &[1; 30]);
+        ppu.write_register(0x2000, 0x00);
+        ppu.write_register(0x2005, 3);
+        ppu.write_register(0x2005, 0);
+        ppu.write_register(0x2001, 0x0A);
+
+        run_until(&mut ppu, 0, 0);
+        run_until(&mut ppu, 240, 0);
+        assert!(is_lit(&ppu, 5, 100) && is_lit(&ppu, 12, 100));
+        assert!(!is_lit(&ppu, 4, 100) && !is_lit(&ppu, 13, 100));
+    }
+
+    /// Fill OAM from the start with the given (y, tile, attributes, x) entries; the rest is $FF.
+    fn set_sprites(ppu: &mut Ppu, sprites: &[[u8; 4]]) {
+        ppu.oam_data = [0xFF; 256];
+        for (i, sprite) in sprites.iter().enumerate() {
+            ppu.oam_data[i * 4..i * 4 + 4].copy_from_slice(sprite);
+        }
+    }
+
+    fn overflow_after_line_40(sprites: &[[u8; 4]]) -> bool {
+        let mut ppu = ppu_with_solid_tile();
+        set_sprites(&mut ppu, sprites);
+        ppu.write_register(0x2001, 0x18);
+        run_until(&mut ppu, 41, 0);
+        ppu.status.contains(PpuStatus::SPRITE_OVERFLOW)
+    }
+
+    #[test]
+    fn sprite_overflow_follows_the_diagonal_oam_scan() {
+        let eight = [[40, 0, 0, 0]; 8];
+        assert!(!overflow_after_line_40(&eight));
+        assert!(overflow_after_line_40(&[[40, 0, 0, 0]; 9]));
+
+        // After eight, sprite 9 is checked at its tile number, which happens to be in range
+        let mut false_positive = eight.to_vec();
+        false_positive.extend([[0xF0, 0, 0, 0], [0xF0, 40, 0, 0]]);
+        assert!(overflow_after_line_40(&false_positive));
+
+        // ...and here its real Y is in range but the tile number checked instead is not
+        let mut false_negative = eight.to_vec();
+        false_negative.extend([[0xF0; 4], [40, 0xF0, 0xF0, 0xF0]]);
+        assert!(!overflow_after_line_40(&false_negative));
+    }
+
+    #[test]
+    fn sprite_evaluation_starts_at_oamaddr() {
+        let mut ppu = ppu_with_solid_tile();
+        set_sprites(&mut ppu, &[[40, 0, 0, 0], [0xFF; 4], [40, 1, 0, 16]]);
+        ppu.write_register(0x2001, 0x18);
+        // OAMADDR is reset during each line's sprite fetches, so set it in the gap after them
+        run_until(&mut ppu, 39, 330);
+        ppu.write_register(0x2003, 8);
+        run_until(&mut ppu, 40, 257);
+
+        assert_eq!(ppu.eval_found, 1);
+        assert_eq!(ppu.secondary_oam[..4], [40, 1, 0, 16]);
+        // Whichever sprite is checked first takes part in sprite 0 hits
+        assert!(ppu.sprite_zero_next);
+    }
+
+    /// Opaque background everywhere and sprite 0 drawn on line 41.
+    fn sprite_zero_hit_cycle(sprite_x: u8, mask: u8) -> Option<u16> {
+        let mut ppu = ppu_with_solid_tile();
+        write_vram(&mut ppu, 0x2000, &[1; 960]);
+        ppu.write_register(0x2005, 0);
+        ppu.write_register(0x2005, 0);
+        set_sprites(&mut ppu, &[[40, 1, 0, sprite_x]]);
+        ppu.write_register(0x2001, mask);
+        run_until(&mut ppu, 41, 0);
+        while ppu.scanline == 41 {
+            ppu.step();
+            if ppu.status.contains(PpuStatus::SPRITE_ZERO_HIT) {
+                return Some(ppu.cycle);
+            }
+        }
+        None
+    }
+
+    #[test]
+    fn sprite_zero_hit_edge_cases() {
+        // The hit lands on the dot that draws the first overlapping pixel
+        assert_eq!(sprite_zero_hit_cycle(20, 0x1E), Some(21));
+        // Left-edge clipping of either layer hides the first eight pixels
+        assert_eq!(sprite_zero_hit_cycle(4, 0x1E), Some(5));
+        assert_eq!(sprite_zero_hit_cycle(4, 0x1A), Some(9));
+        assert_eq!(sprite_zero_hit_cycle(4, 0x1C), Some(9));
+        assert_eq!(sprite_zero_hit_cycle(0, 0x1C), None);
+        // Never at x = 255
+        assert_eq!(sprite_zero_hit_cycle(255, 0x1E), None);
+        assert_eq!(sprite_zero_hit_cycle(248, 0x1E), Some(249));
+        // Both layers have to be enabled
+        assert_eq!(sprite_zero_hit_cycle(20, 0x16), None);
+    }
+
+    #[test]
+    fn unlimited_sprites_draws_past_eight_without_changing_overflow() {
+        for unlimited in [false, true] {
+            let mut ppu = ppu_with_solid_tile();
+            write_vram(&mut ppu, 0x3F11, &[0x30]);
+            ppu.unlimited_sprites = unlimited;
+            let sprites: Vec<[u8; 4]> = (0..10).map(|i| [40, 1, 0, i * 10]).collect();
+            set_sprites(&mut ppu, &sprites);
+            ppu.write_register(0x2001, 0x14);
+            run_until(&mut ppu, 42, 0);
+
+            assert!(is_lit(&ppu, 72, 41));
+            assert_eq!(is_lit(&ppu, 92, 41), unlimited);
+            assert!(ppu.status.contains(PpuStatus::SPRITE_OVERFLOW));
+        }
+
+        // Eight sprites of tile $FD set the MMC2's lower latch; two more of tile $FE must not
+        // flip it back when their extra fetches come after
+        let board_states = [false, true].map(|unlimited| {
+            let cart = Rc::new(RefCell::new(Cartridge::load_from_bytes(&mmc2_rom(vec![0; 0x4000])).unwrap()));
+            let mut ppu = Ppu::new();
+            ppu.connect_cartridge(cart.clone());
+            ppu.unlimited_sprites = unlimited;
+            let sprites: Vec<[u8; 4]> = (0..10).map(|i| [40, if i < 8 { 0xFD } else { 0xFE }, 0, i * 10]).collect();
+            set_sprites(&mut ppu, &sprites);
+            // Background from the upper pattern table, which has its own latch
+            ppu.write_register(0x2000, 0x10);
+            ppu.write_register(0x2001, 0x18);
+            run_until(&mut ppu, 41, 0);
+
+            let mut state = StateWriter::new();
+            cart.borrow().save_state(&mut state);
+            state.into_bytes()
+        });
+        assert_eq!(board_states[0], board_states[1]);
+    }
+
+    #[test]
+    fn save_states_with_out_of_range_sprite_counts_are_rejected() {
+        for corrupt in [|ppu: &mut Ppu| ppu.sprite_count = 65, |ppu: &mut Ppu| ppu.eval_found = 9] {
+            let mut ppu = ppu_with_solid_tile();
+            corrupt(&mut ppu);
+            let mut state = StateWriter::new();
+            ppu.save_state(&mut state);
+            let bytes = state.into_bytes();
+            assert!(ppu_with_solid_tile().load_state(&mut StateReader::new(&bytes)).is_err());
+        }
+    }
+
+    fn dots_per_frame(ppu: &mut Ppu) -> usize {
+        let frame = ppu.frame;
+        let mut dots = 0;
+        while ppu.frame == frame {
+            ppu.step();
+            dots += 1;
+        }
+        dots
+    }
+
+    #[test]
+    fn odd_frames_are_one_dot_shorter_while_rendering() {
+        let mut ppu = ppu_with_solid_tile();
+        run_until(&mut ppu, 0, 0);
+        assert_eq!([dots_per_frame(&mut ppu), dots_per_frame(&mut ppu)], [341 * 262; 2]);
+
+        ppu.write_register(0x2001, 0x08);
+        assert_eq!([dots_per_frame(&mut ppu), dots_per_frame(&mut ppu)], [341 * 262, 341 * 262 - 1]);
+    }
+
+    /// Read $2002 at the given dot with NMI enabled. Returns the VBlank bit read and whether
+    /// an NMI went out for the frame.
+    fn read_status_at(scanline: u16, cycle: u16) -> (bool, bool) {
+        let mut ppu = ppu_with_solid_tile();
+        ppu.write_register(0x2000, 0x80);
+        run_until(&mut ppu, scanline, cycle);
+        let vblank = ppu.read_register(0x2002) & 0x80 != 0;
+        run_until(&mut ppu, 241, 20);
+        (vblank, ppu.nmi_interrupt)
+    }
+
+    #[test]
+    fn reading_status_as_vblank_starts_suppresses_it() {
+        assert_eq!(read_status_at(240, 340), (false, true));
+        // One dot early: the flag reads clear and is never set this frame
+        assert_eq!(read_status_at(241, 0), (false, false));
+        // On the dot it is set or the next: the flag reads set but no NMI follows
+        assert_eq!(read_status_at(241, 1), (true, false));
+        assert_eq!(read_status_at(241, 2), (true, false));
+        assert_eq!(read_status_at(241, 3), (true, true));
+
+        let mut ppu = ppu_with_solid_tile();
+        run_until(&mut ppu, 241, 0);
+        ppu.read_register(0x2002);
+        run_until(&mut ppu, 241, 20);
+        assert_eq!(ppu.read_register(0x2002) & 0x80, 0);
+    }
+
+    #[test]
+    fn nmi_follows_nmi_enable_during_vblank() {
+        let mut ppu = ppu_with_solid_tile();
+        run_until(&mut ppu, 241, 20);
+        assert!(!ppu.nmi_interrupt);
+
+        // Each time NMI is enabled while the flag is set, another NMI goes out after a delay
+        for _ in 0..2 {
+            ppu.write_register(0x2000, 0x80);
+            ppu.step();
+            assert!(!ppu.nmi_interrupt);
+            ppu.step();
+            assert!(std::mem::take(&mut ppu.nmi_interrupt));
+            ppu.write_register(0x2000, 0x00);
+        }
+
+        // Disabling it straight after VBlank starts cancels the NMI
+        let mut ppu = ppu_with_solid_tile();
+        ppu.write_register(0x2000, 0x80);
+        run_until(&mut ppu, 241, 1);
+        ppu.write_register(0x2000, 0x00);
+        run_until(&mut ppu, 241, 20);
+        assert!(!ppu.nmi_interrupt);
+    }
+
+    #[test]
+    fn undriven_register_bits_read_the_io_latch() {
+        let mut ppu = ppu_with_solid_tile();
+        ppu.write_register(0x2003, 0x5A);
+        assert_eq!(ppu.read_register(0x2000), 0x5A);
+        assert_eq!(ppu.read_register(0x2006), 0x5A);
+
+        // $2002 drives only its top three bits
+        ppu.write_register(0x2003, 0x1F);
+        assert_eq!(ppu.read_register(0x2002), 0x1F);
+
+        // Palette entries are six bits wide
+        write_vram(&mut ppu, 0x3F01, &[0x2A]);
+        ppu.write_register(0x2006, 0x3F);
+        ppu.write_register(0x2006, 0x01);
+        assert_eq!(ppu.read_register(0x2007), 0x2A);
+        ppu.write_register(0x2006, 0x3F);
+        ppu.write_register(0x2006, 0xC1);
+        assert_eq!(ppu.read_register(0x2007), 0xEA);
+        assert_eq!(ppu.read_register(0x2005), 0xEA);
+    }
+
+    #[test]
+    fn io_latch_bits_decay_unless_refreshed() {
+        let mut ppu = ppu_with_solid_tile();
+        ppu.write_register(0x2003, 0xFF);
+        for _ in 0..39 {
+            dots_per_frame(&mut ppu);
+        }
+        run_until(&mut ppu, 241, 10);
+        assert_eq!(ppu.read_register(0x2001), 0xFF);
+
+        // A $2002 read refreshes the top three bits; the low five keep their original age
+        assert_eq!(ppu.read_register(0x2002), 0x9F);
+        for _ in 0..30 {
+            dots_per_frame(&mut ppu);
+        }
+        assert_eq!(ppu.read_register(0x2001), 0x80);
+        for _ in 0..30 {
+            dots_per_frame(&mut ppu);
+        }
+        assert_eq!(ppu.read_register(0x2001), 0x00);
+    }
+
+    /// Render a frame of tile 1 with the given color and PPUMASK; returns the color drawn at
+    /// a lit pixel and at the backdrop-colored pixel of an empty tile.
+    fn rendered_colors(color: u8, mask: u8) -> ((u8, u8, u8), (u8, u8, u8)) {
+        let mut ppu = ppu_with_solid_tile();
+        write_vram(&mut ppu, 0x2000, &[1]);
+        write_vram(&mut ppu, 0x3F00, &[0x00, color]);
+        ppu.write_register(0x2000, 0);
+        ppu.write_register(0x2005, 0);
+        ppu.write_register(0x2005, 0);
+        ppu.write_register(0x2001, mask);
+        dots_per_frame(&mut ppu);
+        run_until(&mut ppu, 1, 0);
+        let color_at = |x: usize| {
+            let pixel = &ppu.frame_buffer[x * 3..x * 3 + 3];
+            (pixel[0], pixel[1], pixel[2])
+        };
+        (color_at(4), color_at(12))
+    }
+
+    #[test]
+    fn backdrop_draws_the_color_written() {
+        assert_eq!(rendered_colors(0x30, 0x0A).1, NES_PALETTE[0x00]);
+    }
+
+    #[test]
+    fn greyscale_masks_palette_output_and_reads() {
+        assert_eq!(rendered_colors(0x16, 0x0A).0, NES_PALETTE[0x16]);
+        assert_eq!(rendered_colors(0x16, 0x0B).0, NES_PALETTE[0x10]);
+
+        let mut ppu = ppu_with_solid_tile();
+        write_vram(&mut ppu, 0x3F01, &[0x16]);
+        ppu.write_register(0x2001, 0x01);
+        ppu.write_register(0x2006, 0x3F);
+        ppu.write_register(0x2006, 0x01);
+        assert_eq!(ppu.read_register(0x2007) & 0x3F, 0x10);
+    }
+
+    #[test]
+    fn emphasis_dims_the_other_channels() {
+        let white = NES_PALETTE[0x30];
+        let (red, _) = rendered_colors(0x30, 0x2A);
+        assert_eq!(red.0, white.0);
+        assert!(red.1 < white.1 && red.2 < white.2);
+
+        let (green, _) = rendered_colors(0x30, 0x4A);
+        assert_eq!(green.1, white.1);
+        assert!(green.0 < white.0 && green.2 < white.2);
+
+        // With two channels emphasized, each one is dimmed by the other
+        let (_, backdrop) = rendered_colors(0x30, 0xCA);
+        assert_eq!(NES_PALETTE[0x00], (0x7C, 0x7C, 0x7C));
+        assert_eq!(backdrop, (0x65, 0x65, 0x65));
+
+        let (all, _) = rendered_colors(0x30, 0xEA);
+        assert!(all.0 < white.0 && all.1 < white.1 && all.2 < white.2);
+    }
+
+    /// Mapper 9 image with 32KB PRG and the given 16KB of CHR, four 4KB banks.
+    fn mmc2_rom(chr: Vec<u8>) -> Vec<u8> {
+        let mut rom = vec![0x4E, 0x45, 0x53, 0x1A, 2, 2, 0x90, 0, 0, 0, 0, 0, 0, 0, 0, 0];
+        rom.extend(vec![0; 0x8000]);
+        rom.extend(chr);
+        rom
+    }
+
+    #[test]
+    fn mmc2_latch_switches_banks_between_whole_tiles() {
+        let mut chr = vec![0; 0x4000];
+        // Bank 0 draws tiles $FD and $FE solid, bank 1 draws tile 1 solid
+        for offset in [0x0FD0, 0x0FE0, 0x1010] {
+            chr[offset..offset + 8].fill(0xFF);
+        }
+        let cart = Rc::new(RefCell::new(Cartridge::load_from_bytes(&mmc2_rom(chr)).unwrap()));
+        // Latch $FD selects bank 1 for the lower pattern table, latch $FE bank 0
+        cart.borrow_mut().write_prg(0xB000, 1);
+        cart.borrow_mut().write_prg(0xC000, 0);
+        let mut ppu = Ppu::new();
+        ppu.connect_cartridge(cart);
+
+        write_vram(&mut ppu, 0x2000, &[0xFD, 0x01, 0xFE]);
+        write_vram(&mut ppu, 0x3F00, &[0x0F, 0x30]);
+        ppu.write_register(0x2000, 0);
+        ppu.write_register(0x2005, 0);
+        ppu.write_register(0x2005, 0);
+        ppu.write_register(0x2001, 0x0A);
+        dots_per_frame(&mut ppu);
+        run_until(&mut ppu, 1, 0);
+
+        // Tile $FD comes from bank 0 even though fetching it flips the latch; tile 1 is
+        // fetched after the flip, from bank 1, and tile $FE from bank 1 before flipping back
+        let lit: Vec<bool> = (0..24).map(|x| is_lit(&ppu, x, 0)).collect();
+        assert_eq!(lit, [[true; 16].as_slice(), &[false; 8]].concat());
+    }
+}